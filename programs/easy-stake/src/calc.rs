@@ -5,28 +5,77 @@ use anchor_lang::{error, Result};
 use crate::error::StakingError;
 
 
+/// 虚拟份额：份额计算时视为已存在、但永远无人可赎回的份额数量。
+/// 首个存款人无法再用极少份额 + 捐赠的方式抬高单份额价格，捐赠的价值绝大部分会归属虚拟份额。
+pub const VIRTUAL_SHARES: u64 = 1_000_000;
 
-/// 计算公式：amount * numerator / denominator
-pub fn proportional(amount: u64, numerator: u64, denominator: u64) -> Result<u64> {
+/// 与 VIRTUAL_SHARES 配对的虚拟价值（lamports），两者相等使空池的初始兑换比例保持 1:1
+pub const VIRTUAL_VALUE: u64 = 1_000_000;
+
+
+/// 取整方向。铸造给用户的份额、支付给用户的资产向下取整，
+/// 用户需要支付的数额、收取的手续费向上取整，保证舍入误差始终有利于池子
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    RoundDown,
+    RoundUp,
+}
+
+
+/// 计算公式：amount * numerator / denominator，按 rounding 取整。
+/// denominator 为 0 时没有合理的结果，直接返回 DivisionByZero，由调用方保证或处理
+pub fn proportional(
+    amount: u64,
+    numerator: u64,
+    denominator: u64,
+    rounding: Rounding
+) -> Result<u64> {
     if denominator == 0 {
-        return Ok(amount);
+        return Err(error!(StakingError::DivisionByZero));
     }
-    
-    u64::try_from((amount as u128) * (numerator as u128) / (denominator as u128))
-        .map_err(|_| error!(StakingError::CalculationFailure))
+
+    let product = (amount as u128) * (numerator as u128);
+    let denominator = denominator as u128;
+    let result = match rounding {
+        Rounding::RoundDown => product / denominator,
+        Rounding::RoundUp => product.div_ceil(denominator),
+    };
+
+    u64::try_from(result).map_err(|_| error!(StakingError::CalculationFailure))
 }
 
 
-#[inline] //alias for proportional
-pub fn value_from_shares(shares: u64, total_value: u64, total_shares: u64) -> Result<u64> {
-    proportional(shares, total_value, total_shares)
+fn with_virtual_offsets(total_value: u64, total_shares: u64) -> Result<(u64, u64)> {
+    Ok((
+        total_value
+            .checked_add(VIRTUAL_VALUE)
+            .ok_or(error!(StakingError::MathOverflow))?,
+        total_shares
+            .checked_add(VIRTUAL_SHARES)
+            .ok_or(error!(StakingError::MathOverflow))?
+    ))
 }
 
 
-pub fn shares_from_value(value: u64, total_value: u64, total_shares: u64) -> Result<u64> {
-    if total_shares == 0 {
-        Ok(value)
-    } else {
-        proportional(value, total_shares, total_value)
-    }
+/// 份额 -> 价值，计入虚拟份额与虚拟价值
+pub fn value_from_shares(
+    shares: u64,
+    total_value: u64,
+    total_shares: u64,
+    rounding: Rounding
+) -> Result<u64> {
+    let (total_value, total_shares) = with_virtual_offsets(total_value, total_shares)?;
+    proportional(shares, total_value, total_shares, rounding)
+}
+
+
+/// 价值 -> 份额，计入虚拟份额与虚拟价值（空池时同样按 1:1 兑换）
+pub fn shares_from_value(
+    value: u64,
+    total_value: u64,
+    total_shares: u64,
+    rounding: Rounding
+) -> Result<u64> {
+    let (total_value, total_shares) = with_virtual_offsets(total_value, total_shares)?;
+    proportional(value, total_shares, total_value, rounding)
 }
@@ -1,7 +1,16 @@
-use anchor_lang::prelude::*;
+use anchor_lang::{
+    prelude::*,
+    solana_program::sysvar::instructions::{load_current_index_checked, load_instruction_at_checked},
+    Discriminator
+};
 use anchor_spl::{stake::StakeAccount, token::{Mint, TokenAccount}};
 
-use crate::error::StakingError;
+use crate::{
+    error::StakingError,
+    instruction,
+    state::{AllowlistEntry, StakePoolConfig},
+    ID
+};
 
 
 #[macro_export]
@@ -117,3 +126,111 @@ pub fn check_stake_amount_and_validator(
 
     Ok(())
 }
+
+
+/// 池子指令的资金方向，开启交易检查时同一交易中不允许同时出现两个方向的指令
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PoolDirection {
+    /// deposit、add_liquidity 及其变体
+    In,
+    /// unstake、remove_liquidity 及其变体，以及清退模式下的 wind_down_redeem
+    Out,
+    /// flash_borrow、flash_repay，与任何存取指令都不能出现在同一交易中
+    Flash,
+}
+
+
+impl PoolDirection {
+    const IN: [&'static [u8]; 4] = [
+        instruction::Deposit::DISCRIMINATOR,
+        instruction::DepositWsol::DISCRIMINATOR,
+        instruction::AddLiquidity::DISCRIMINATOR,
+        instruction::AddLiquidityMsol::DISCRIMINATOR,
+    ];
+
+    const OUT: [&'static [u8]; 5] = [
+        instruction::Unstake::DISCRIMINATOR,
+        instruction::UnstakeToWsol::DISCRIMINATOR,
+        instruction::RemoveLiquidity::DISCRIMINATOR,
+        instruction::RemoveLiquiditySol::DISCRIMINATOR,
+        instruction::WindDownRedeem::DISCRIMINATOR,
+    ];
+
+    const FLASH: [&'static [u8]; 2] = [
+        instruction::FlashBorrow::DISCRIMINATOR,
+        instruction::FlashRepay::DISCRIMINATOR,
+    ];
+
+    fn of_instruction_data(data: &[u8]) -> Option<Self> {
+        if Self::IN.iter().any(|discriminator| data.starts_with(discriminator)) {
+            Some(Self::In)
+        } else if Self::OUT.iter().any(|discriminator| data.starts_with(discriminator)) {
+            Some(Self::Out)
+        } else if Self::FLASH.iter().any(|discriminator| data.starts_with(discriminator)) {
+            Some(Self::Flash)
+        } else {
+            None
+        }
+    }
+}
+
+
+/// 开启交易检查时，当前指令必须是交易的顶层指令（不能被其他程序通过 CPI 包装），
+/// 且交易中不能包含与当前指令方向相反的本程序指令
+pub fn check_transaction_guard(
+    config: &StakePoolConfig,
+    instructions: Option<&UncheckedAccount>,
+    direction: PoolDirection
+) -> Result<()> {
+    if !config.transaction_guard_enabled {
+        return Ok(());
+    }
+
+    let Some(instructions) = instructions else {
+        msg!("Instructions sysvar is required while transaction guard is enabled");
+        return err!(StakingError::ConflictingPoolInstructions);
+    };
+    let instructions = instructions.to_account_info();
+
+    let current_index = load_current_index_checked(&instructions)? as usize;
+    let current = load_instruction_at_checked(current_index, &instructions)?;
+    if current.program_id != ID {
+        msg!("Pool instruction invoked through program {}", current.program_id);
+        return err!(StakingError::ConflictingPoolInstructions);
+    }
+
+    let mut index = 0;
+    while let Ok(instruction) = load_instruction_at_checked(index, &instructions) {
+        if instruction.program_id == ID {
+            if let Some(other) = PoolDirection::of_instruction_data(&instruction.data) {
+                if other != direction {
+                    msg!("Instruction {} conflicts with current instruction {}", index, current_index);
+                    return err!(StakingError::ConflictingPoolInstructions);
+                }
+            }
+        }
+        index += 1;
+    }
+
+    Ok(())
+}
+
+
+/// 许可模式下要求传入调用者本人的白名单账户
+pub fn check_allowlist(
+    config: &Account<StakePoolConfig>,
+    allowlist_entry: Option<&AllowlistEntry>,
+    user: &Pubkey
+) -> Result<()> {
+    if !config.permissioned {
+        return Ok(());
+    }
+
+    match allowlist_entry {
+        Some(entry) if entry.state == config.key() && entry.user == *user => Ok(()),
+        _ => {
+            msg!("User {} is not allowlisted", user);
+            err!(StakingError::NotAllowlisted)
+        }
+    }
+}
@@ -275,4 +275,91 @@ pub enum StakingError {
 
     #[msg("检测到未经授权或存在漏洞的 LP 代币铸造行为")]
     UnauthorizedOrExploitedLPMinting, // 6088 0x17c8
+
+    #[msg("除数为零")]
+    DivisionByZero, // 6089 0x17c9
+
+    #[msg("手续费曲线配置无效，池子流动性降低时费率不得下降")]
+    InvalidFeeCurve, // 6090 0x17ca
+
+    #[msg("存款兑换手续费过高")]
+    DepositSwapFeeIsTooHigh, // 6091 0x17cb
+
+    #[msg("提取的 SOL 低于用户设定的最小值")]
+    SolOutBelowMinimum, // 6092 0x17cc
+
+    #[msg("闪电贷手续费过高")]
+    FlashLoanFeeIsTooHigh, // 6093 0x17cd
+
+    #[msg("存在未归还的闪电贷")]
+    FlashLoanActive, // 6094 0x17ce
+
+    #[msg("没有进行中的闪电贷")]
+    FlashLoanNotActive, // 6095 0x17cf
+
+    #[msg("同一交易中缺少对应的闪电贷还款指令")]
+    FlashRepayMissing, // 6096 0x17d0
+
+    #[msg("闪电贷指令不能通过 CPI 调用")]
+    FlashLoanCpiNotAllowed, // 6097 0x17d1
+
+    #[msg("wSOL 解质押时接收 SOL 的账户必须是 mSOL 持有者本人")]
+    InvalidWsolUnstakeRecipient, // 6098 0x17d2
+
+    #[msg("return data 缺失、不是本程序写入或无法解码")]
+    InvalidReturnData, // 6099 0x17d3
+
+    #[msg("epoch 结束前窗口期存款手续费过高")]
+    EpochEndDepositFeeIsTooHigh, // 6100 0x17d4
+
+    #[msg("交易中包含方向冲突的池子指令，或池子指令被其他程序通过 CPI 调用")]
+    ConflictingPoolInstructions, // 6101 0x17d5
+
+    #[msg("推荐账户或合作方 mSOL 账户无效")]
+    InvalidReferralAccount, // 6102 0x17d6
+
+    #[msg("费率覆盖账户不属于当前用户或调用程序")]
+    InvalidFeeOverride, // 6103 0x17d7
+
+    #[msg("许可模式下调用者不在白名单中")]
+    NotAllowlisted, // 6104 0x17d8
+
+    #[msg("白名单管理员地址无效")]
+    InvalidAllowlistAuthority, // 6105 0x17d9
+
+    #[msg("超过本 epoch 的存款总额限制")]
+    EpochDepositLimitExceeded, // 6106 0x17da
+
+    #[msg("超过本 epoch 的单个钱包存款限制")]
+    UserDepositLimitExceeded, // 6107 0x17db
+
+    #[msg("用户存款记录账户缺失或不属于当前用户")]
+    InvalidUserDepositRecord, // 6108 0x17dc
+
+    #[msg("质押池处于清退模式")]
+    PoolIsWindingDown, // 6109 0x17dd
+
+    #[msg("质押池未处于清退模式")]
+    PoolIsNotWindingDown, // 6110 0x17de
+
+    #[msg("质押账户已在解除质押中")]
+    StakeAlreadyDeactivating, // 6111 0x17df
+
+    #[msg("质押池仍有未赎回的资产，不能关闭")]
+    PoolNotEmpty, // 6112 0x17e0
+
+    #[msg("批量质押的验证者与质押账户不匹配或数量超出上限")]
+    InvalidStakeBatch, // 6113 0x17e1
+
+    #[msg("只能向质押缺口最大的验证者质押")]
+    ValidatorNotMostUnderAllocated, // 6114 0x17e2
+
+    #[msg("质押账户本 epoch 已更新")]
+    StakeAlreadyUpdated, // 6115 0x17e3
+
+    #[msg("sol_leg 借给 reserve 的 SOL 尚未归还")]
+    SolLegLoanOutstanding, // 6116 0x17e4
+
+    #[msg("验证者数量超出上限")]
+    TooManyValidators, // 6117 0x17e5
 }
\ No newline at end of file
@@ -9,6 +9,37 @@ pub mod set_validator_score;
 pub mod realloc_validator_list;
 pub mod stake_reserve;
 pub mod deactivate_stake;
+pub mod config_lp;
+pub mod add_liquidity_msol;
+pub mod remove_liquidity_sol;
+pub mod recycle_msol_leg;
+pub mod rebalance_sol_leg;
+pub mod flash_borrow;
+pub mod flash_repay;
+pub mod deposit_wsol;
+pub mod unstake_to_wsol;
+pub mod quote_deposit;
+pub mod quote_unstake;
+pub mod quote_add_liquidity;
+pub mod quote_remove_liquidity;
+pub mod get_msol_price;
+pub mod init_price_oracle;
+pub mod update_price_oracle;
+pub mod config_stake_pool;
+pub mod add_referral;
+pub mod set_fee_override;
+pub mod remove_fee_override;
+pub mod add_to_allowlist;
+pub mod remove_from_allowlist;
+pub mod init_user_deposit_record;
+pub mod begin_wind_down;
+pub mod withdraw_stake;
+pub mod wind_down_redeem;
+pub mod close_pool;
+pub mod stake_reserve_batch;
+pub mod quote_add_liquidity_msol;
+pub mod quote_remove_liquidity_sol;
+pub mod update_stake;
 
 pub use initialize::*;
 pub use deposit::*;
@@ -20,4 +51,35 @@ pub use remove_validator::*;
 pub use set_validator_score::*;
 pub use realloc_validator_list::*;
 pub use stake_reserve::*;
-pub use deactivate_stake::*;
\ No newline at end of file
+pub use deactivate_stake::*;
+pub use config_lp::*;
+pub use add_liquidity_msol::*;
+pub use remove_liquidity_sol::*;
+pub use recycle_msol_leg::*;
+pub use rebalance_sol_leg::*;
+pub use flash_borrow::*;
+pub use flash_repay::*;
+pub use deposit_wsol::*;
+pub use unstake_to_wsol::*;
+pub use quote_deposit::*;
+pub use quote_unstake::*;
+pub use quote_add_liquidity::*;
+pub use quote_remove_liquidity::*;
+pub use get_msol_price::*;
+pub use init_price_oracle::*;
+pub use update_price_oracle::*;
+pub use config_stake_pool::*;
+pub use add_referral::*;
+pub use set_fee_override::*;
+pub use remove_fee_override::*;
+pub use add_to_allowlist::*;
+pub use remove_from_allowlist::*;
+pub use init_user_deposit_record::*;
+pub use begin_wind_down::*;
+pub use withdraw_stake::*;
+pub use wind_down_redeem::*;
+pub use close_pool::*;
+pub use stake_reserve_batch::*;
+pub use quote_add_liquidity_msol::*;
+pub use quote_remove_liquidity_sol::*;
+pub use update_stake::*;
\ No newline at end of file
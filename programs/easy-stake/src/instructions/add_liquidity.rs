@@ -1,7 +1,8 @@
 //! 池子添加流动性
 
 use anchor_lang::{
-    prelude::*,  
+    prelude::*,
+    solana_program::sysvar::instructions::ID as INSTRUCTIONS_ID,
     system_program::{ID as sys_id, transfer, Transfer}
 };
 use anchor_spl::{
@@ -11,7 +12,13 @@ use anchor_spl::{
     }
 };
 
-use crate::{calc::shares_from_value, error::StakingError, require_lte, state::{LiqPool, StakePoolConfig}};
+use crate::{
+    calc::{shares_from_value, Rounding}, 
+    checks::{check_allowlist, check_transaction_guard, PoolDirection},
+    error::StakingError, 
+    require_lte, 
+    state::{AllowlistEntry, LiqPool, StakePoolConfig}
+};
 
 
 #[event]
@@ -30,6 +37,48 @@ pub struct AddLiquidityEvent {
 }
 
 
+/// 通过 return data 返回给 CPI 调用方的结果，quote_add_liquidity 返回同一结构
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AddLiquidityResult {
+    pub lp_minted: u64,
+}
+
+
+impl AddLiquidityResult {
+    /// 只依据池子当前状态计算用 SOL 添加流动性获得的 LP，不修改任何状态。
+    /// add_liquidity 与 quote_add_liquidity 共用，保证报价与实际执行一致
+    pub fn calc(
+        config: &StakePoolConfig,
+        lamports: u64,
+        sol_leg_balance: u64,
+        msol_leg_balance: u64,
+        lp_supply: u64
+    ) -> Result<Self> {
+        require_gte!(
+            lamports,
+            config.min_deposit,
+            StakingError::DepositAmountIsTooLow
+        );
+
+        config.liq_pool.check_liquidity_cap(lamports, sol_leg_balance)?;
+
+        // 池子价值向上取整，用户获得的 LP 向下取整
+        let total_liq_pool_value = config.liq_pool_value(sol_leg_balance, msol_leg_balance)?;
+        msg!("liq_pool_value:{}", total_liq_pool_value);
+
+        let lp_minted = shares_from_value(
+            lamports, 
+            total_liq_pool_value, 
+            lp_supply,
+            Rounding::RoundDown
+        )?;
+        msg!("LP for user {}", lp_minted);
+
+        Ok(Self { lp_minted })
+    }
+}
+
+
 #[derive(Accounts)]
 pub struct AddLiquidity<'info> {
     #[account(
@@ -83,20 +132,33 @@ pub struct AddLiquidity<'info> {
     )]
     pub mint_to: Box<Account<'info, TokenAccount>>,
 
+    /// 许可模式下必须传入调用者的白名单账户
+    pub allowlist_entry: Option<Box<Account<'info, AllowlistEntry>>>,
+
+    /// CHECK: 指令 sysvar，仅在开启交易检查时需要
+    #[account(address = INSTRUCTIONS_ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+
     pub system_program: Program<'info, System>,
     pub token_program: Program<'info, Token>,
     pub associated_token_program: Program<'info, AssociatedToken>
 }
 
 impl<'info> AddLiquidity<'info> {
-    pub fn process(&mut self, lamports: u64) -> Result<()> {
+    pub fn process(&mut self, lamports: u64) -> Result<AddLiquidityResult> {
         require!(!self.stake_pool_config.paused, StakingError::ProgramIsPaused);
-
-        require_gte!(
-            lamports,
-            self.stake_pool_config.min_deposit,
-            StakingError::DepositAmountIsTooLow
-        );
+        self.stake_pool_config.check_not_winding_down()?;
+        self.stake_pool_config.check_no_flash_loan()?;
+        check_transaction_guard(
+            &self.stake_pool_config,
+            self.instructions.as_ref(),
+            PoolDirection::In
+        )?;
+        check_allowlist(
+            &self.stake_pool_config,
+            self.allowlist_entry.as_deref().map(|entry| &**entry),
+            &self.transfer_from.key()
+        )?;
 
         let user_sol_balance = self.transfer_from.lamports();
         require_lte!(
@@ -105,11 +167,6 @@ impl<'info> AddLiquidity<'info> {
             StakingError::NotEnoughUserFunds
         );
 
-        self.stake_pool_config.liq_pool.check_liquidity_cap(
-            lamports, 
-            self.liq_pool_sol_leg_pda.lamports()
-        )?;
-
         require_lte!(
             self.lp_mint.supply,
             self.stake_pool_config.liq_pool.lp_supply,
@@ -118,28 +175,18 @@ impl<'info> AddLiquidity<'info> {
 
         self.stake_pool_config.liq_pool.lp_supply = self.lp_mint.supply;
 
-        let total_virtual_staked_lamports = self.stake_pool_config.total_staked_lamports();
+        let total_virtual_staked_lamports = self.stake_pool_config.total_staked_lamports()?;
         let msol_supply = self.stake_pool_config.msol_supply;
 
         let sol_leg_balance = self.liq_pool_sol_leg_pda.lamports();
-        let sol_leg_available_balance = sol_leg_balance - self.stake_pool_config.rent_exempt_for_token_acc;
-        let msol_leg_value = self.stake_pool_config.msol_to_sol(self.liq_pool_msol_leg.amount)?;
-        let total_liq_pool_value = sol_leg_available_balance + msol_leg_value;
-
-        msg!(
-            "liq_pool SOL:{}, liq_pool mSOL value:{} liq_pool_value:{}",
-            sol_leg_available_balance,
-            msol_leg_value,
-            total_liq_pool_value
-        );
-
         let lp_supply = self.stake_pool_config.liq_pool.lp_supply;
-        let shares_for_user = shares_from_value(
-            lamports, 
-            total_liq_pool_value, 
+        let result = AddLiquidityResult::calc(
+            &self.stake_pool_config,
+            lamports,
+            sol_leg_balance,
+            self.liq_pool_msol_leg.amount,
             lp_supply
         )?;
-        msg!("LP for user {}", shares_for_user);
 
         transfer(
             CpiContext::new(
@@ -167,10 +214,10 @@ impl<'info> AddLiquidity<'info> {
                     &[self.stake_pool_config.liq_pool.lp_mint_authority_bump_seed]
                 ]]
             ), 
-            shares_for_user
+            result.lp_minted
         )?;
         
-        self.stake_pool_config.liq_pool.on_lp_mint(shares_for_user);
+        self.stake_pool_config.liq_pool.on_lp_mint(result.lp_minted);
 
         emit!(AddLiquidityEvent {
             state: self.stake_pool_config.key(),
@@ -180,12 +227,12 @@ impl<'info> AddLiquidity<'info> {
             sol_leg_balance,
             lp_supply,
             sol_added_amount: lamports,
-            lp_minted: shares_for_user,
+            lp_minted: result.lp_minted,
             // msol price components
             total_virtual_staked_lamports,
             msol_supply,
         });
 
-        Ok(())
+        Ok(result)
     }
 }
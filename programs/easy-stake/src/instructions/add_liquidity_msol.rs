@@ -0,0 +1,262 @@
+//! 池子添加流动性（使用 mSOL）
+
+use anchor_lang::{
+    prelude::*,
+    solana_program::sysvar::instructions::ID as INSTRUCTIONS_ID,
+    system_program::ID as sys_id
+};
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token::{
+        mint_to,
+        transfer as transfer_tokens,
+        Mint,
+        MintTo,
+        Token,
+        TokenAccount,
+        Transfer as TransferTokens
+    }
+};
+
+use crate::{
+    calc::{shares_from_value, Rounding},
+    checks::{check_allowlist, check_transaction_guard, PoolDirection},
+    error::StakingError,
+    require_lte,
+    state::{AllowlistEntry, LiqPool, StakePoolConfig}
+};
+
+use super::AddLiquidityResult;
+
+
+#[event]
+pub struct AddLiquidityMsolEvent {
+    pub state: Pubkey,
+    pub msol_owner: Pubkey,
+    pub user_msol_balance: u64,
+    pub user_lp_balance: u64,
+    pub sol_leg_balance: u64,
+    pub msol_leg_balance: u64,
+    pub lp_supply: u64,
+    pub msol_added_amount: u64,
+    pub msol_added_value: u64,
+    pub lp_minted: u64,
+    // MSOL price used
+    pub total_virtual_staked_lamports: u64,
+    pub msol_supply: u64,
+}
+
+
+impl AddLiquidityResult {
+    /// 只依据池子当前状态计算用 mSOL 添加流动性获得的 LP，不修改任何状态。
+    /// add_liquidity_msol 与 quote_add_liquidity_msol 共用，保证报价与实际执行一致
+    pub fn calc_msol(
+        config: &StakePoolConfig,
+        msol_amount: u64,
+        sol_leg_balance: u64,
+        msol_leg_balance: u64,
+        lp_supply: u64
+    ) -> Result<Self> {
+        // 存入的 mSOL 按当前价格折算，向下取整
+        let msol_added_value = config.msol_to_sol(msol_amount, Rounding::RoundDown)?;
+        require_gte!(
+            msol_added_value,
+            config.min_deposit,
+            StakingError::DepositAmountIsTooLow
+        );
+
+        // 池子价值向上取整，用户获得的 LP 向下取整
+        let total_liq_pool_value = config.liq_pool_value(sol_leg_balance, msol_leg_balance)?;
+        msg!("liq_pool_value:{}", total_liq_pool_value);
+
+        // mSOL 不进入 sol_leg，上限按池子总价值计算
+        config.liq_pool.check_liquidity_cap(msol_added_value, total_liq_pool_value)?;
+
+        let lp_minted = shares_from_value(
+            msol_added_value,
+            total_liq_pool_value,
+            lp_supply,
+            Rounding::RoundDown
+        )?;
+        msg!("LP for user {}", lp_minted);
+
+        Ok(Self { lp_minted })
+    }
+}
+
+
+#[derive(Accounts)]
+pub struct AddLiquidityMsol<'info> {
+    #[account(
+        mut,
+        owner = sys_id
+    )]
+    pub transfer_from_authority: Signer<'info>,
+
+    #[account(
+        mut,
+        token::mint = stake_pool_config.msol_mint,
+        token::authority = transfer_from_authority
+    )]
+    pub transfer_from: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    #[account(
+        mut,
+        address = stake_pool_config.liq_pool.lp_mint,
+        mint::authority = lp_mint_authority
+    )]
+    pub lp_mint: Box<Account<'info, Mint>>,
+
+    /// CHECK: PDA
+    #[account(
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            LiqPool::LP_MINT_AUTHORITY_SEED
+        ],
+        bump = stake_pool_config.liq_pool.lp_mint_authority_bump_seed
+    )]
+    pub lp_mint_authority: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        address = stake_pool_config.liq_pool.msol_leg
+    )]
+    pub liq_pool_msol_leg: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            LiqPool::SOL_LEG_SEED
+        ],
+        bump = stake_pool_config.liq_pool.sol_leg_bump_seed
+    )]
+    pub liq_pool_sol_leg_pda: SystemAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = transfer_from_authority,
+        associated_token::mint = lp_mint,
+        associated_token::authority = transfer_from_authority
+    )]
+    pub mint_to: Box<Account<'info, TokenAccount>>,
+
+    /// 许可模式下必须传入调用者的白名单账户
+    pub allowlist_entry: Option<Box<Account<'info, AllowlistEntry>>>,
+
+    /// CHECK: 指令 sysvar，仅在开启交易检查时需要
+    #[account(address = INSTRUCTIONS_ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>
+}
+
+
+impl<'info> AddLiquidityMsol<'info> {
+    pub fn process(&mut self, msol_amount: u64) -> Result<AddLiquidityResult> {
+        require!(!self.stake_pool_config.paused, StakingError::ProgramIsPaused);
+        self.stake_pool_config.check_not_winding_down()?;
+        self.stake_pool_config.check_no_flash_loan()?;
+        check_transaction_guard(
+            &self.stake_pool_config,
+            self.instructions.as_ref(),
+            PoolDirection::In
+        )?;
+        check_allowlist(
+            &self.stake_pool_config,
+            self.allowlist_entry.as_deref().map(|entry| &**entry),
+            &self.transfer_from_authority.key()
+        )?;
+
+        let user_msol_balance = self.transfer_from.amount;
+        require_lte!(
+            msol_amount,
+            user_msol_balance,
+            StakingError::NotEnoughUserFunds
+        );
+
+        require_lte!(
+            self.lp_mint.supply,
+            self.stake_pool_config.liq_pool.lp_supply,
+            StakingError::UnregisteredLPMinted
+        );
+
+        self.stake_pool_config.liq_pool.lp_supply = self.lp_mint.supply;
+
+        let total_virtual_staked_lamports = self.stake_pool_config.total_staked_lamports()?;
+        let msol_supply = self.stake_pool_config.msol_supply;
+
+        let sol_leg_balance = self.liq_pool_sol_leg_pda.lamports();
+        let msol_leg_balance = self.liq_pool_msol_leg.amount;
+        let lp_supply = self.stake_pool_config.liq_pool.lp_supply;
+        let msol_added_value = self.stake_pool_config.msol_to_sol(
+            msol_amount,
+            Rounding::RoundDown
+        )?;
+        let AddLiquidityResult { lp_minted: shares_for_user } = AddLiquidityResult::calc_msol(
+            &self.stake_pool_config,
+            msol_amount,
+            sol_leg_balance,
+            msol_leg_balance,
+            lp_supply
+        )?;
+
+        transfer_tokens(
+            CpiContext::new(
+                self.token_program.to_account_info(),
+                TransferTokens {
+                    from: self.transfer_from.to_account_info(),
+                    to: self.liq_pool_msol_leg.to_account_info(),
+                    authority: self.transfer_from_authority.to_account_info()
+                }
+            ),
+            msol_amount
+        )?;
+
+        let user_lp_balance = self.mint_to.amount;
+        mint_to(
+            CpiContext::new_with_signer(
+                self.token_program.to_account_info(),
+                MintTo {
+                    mint: self.lp_mint.to_account_info(),
+                    to: self.mint_to.to_account_info(),
+                    authority: self.lp_mint_authority.to_account_info()
+                },
+                &[&[
+                    self.stake_pool_config.key().as_ref(),
+                    LiqPool::LP_MINT_AUTHORITY_SEED,
+                    &[self.stake_pool_config.liq_pool.lp_mint_authority_bump_seed]
+                ]]
+            ),
+            shares_for_user
+        )?;
+
+        self.stake_pool_config.liq_pool.on_lp_mint(shares_for_user);
+
+        emit!(AddLiquidityMsolEvent {
+            state: self.stake_pool_config.key(),
+            msol_owner: self.transfer_from_authority.key(),
+            user_msol_balance,
+            user_lp_balance,
+            sol_leg_balance,
+            msol_leg_balance,
+            lp_supply,
+            msol_added_amount: msol_amount,
+            msol_added_value,
+            lp_minted: shares_for_user,
+            // msol price components
+            total_virtual_staked_lamports,
+            msol_supply,
+        });
+
+        Ok(AddLiquidityResult { lp_minted: shares_for_user })
+    }
+}
@@ -0,0 +1,88 @@
+//! 管理员为合作方创建推荐账户
+
+use anchor_lang::{prelude::*, system_program::ID as sys_id};
+use anchor_spl::token::TokenAccount;
+
+use crate::{
+    error::StakingError,
+    state::{Fee, ReferralAccount, StakePoolConfig}
+};
+
+
+#[event]
+pub struct AddReferralEvent {
+    pub state: Pubkey,
+    pub referral: Pubkey,
+    pub partner: Pubkey,
+    pub partner_msol_account: Pubkey,
+    pub fee_share: Fee,
+}
+
+
+#[derive(Accounts)]
+pub struct AddReferral<'info> {
+    #[account(
+        mut,
+        owner = sys_id
+    )]
+    pub rent_payer: Signer<'info>,
+
+    pub admin_authority: Signer<'info>,
+
+    #[account(
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump,
+        has_one = admin_authority @ StakingError::InvalidAdminAuthority
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    /// CHECK: 合作方钱包，只用于派生推荐账户地址
+    pub partner: UncheckedAccount<'info>,
+
+    #[account(token::mint = stake_pool_config.msol_mint)]
+    pub partner_msol_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        payer = rent_payer,
+        space = ReferralAccount::LEN,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            ReferralAccount::SEED,
+            partner.key().as_ref()
+        ],
+        bump
+    )]
+    pub referral: Box<Account<'info, ReferralAccount>>,
+
+    pub system_program: Program<'info, System>
+}
+
+
+impl<'info> AddReferral<'info> {
+    pub fn process(&mut self, fee_share: Fee, bumps: AddReferralBumps) -> Result<()> {
+        fee_share.check()?;
+        msg!("Add referral for {} with fee share {}", self.partner.key(), fee_share);
+
+        self.referral.set_inner(ReferralAccount {
+            state: self.stake_pool_config.key(),
+            partner: self.partner.key(),
+            partner_msol_account: self.partner_msol_account.key(),
+            fee_share,
+            deposited_lamports: 0,
+            unstaked_msol: 0,
+            msol_earned: 0,
+            bump: bumps.referral,
+        });
+
+        emit!(AddReferralEvent {
+            state: self.stake_pool_config.key(),
+            referral: self.referral.key(),
+            partner: self.partner.key(),
+            partner_msol_account: self.partner_msol_account.key(),
+            fee_share,
+        });
+
+        Ok(())
+    }
+}
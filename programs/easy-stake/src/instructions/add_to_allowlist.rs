@@ -0,0 +1,70 @@
+//! 白名单管理员将用户加入白名单
+
+use anchor_lang::{prelude::*, system_program::ID as sys_id};
+
+use crate::{
+    error::StakingError,
+    state::{AllowlistEntry, StakePoolConfig}
+};
+
+
+#[event]
+pub struct AddToAllowlistEvent {
+    pub state: Pubkey,
+    pub user: Pubkey,
+}
+
+
+#[derive(Accounts)]
+#[instruction(user: Pubkey)]
+pub struct AddToAllowlist<'info> {
+    #[account(
+        mut,
+        owner = sys_id
+    )]
+    pub rent_payer: Signer<'info>,
+
+    pub allowlist_authority: Signer<'info>,
+
+    #[account(
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump,
+        has_one = allowlist_authority @ StakingError::InvalidAllowlistAuthority
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    #[account(
+        init,
+        payer = rent_payer,
+        space = AllowlistEntry::LEN,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            AllowlistEntry::SEED,
+            user.as_ref()
+        ],
+        bump
+    )]
+    pub allowlist_entry: Box<Account<'info, AllowlistEntry>>,
+
+    pub system_program: Program<'info, System>
+}
+
+
+impl<'info> AddToAllowlist<'info> {
+    pub fn process(&mut self, user: Pubkey, bumps: AddToAllowlistBumps) -> Result<()> {
+        msg!("Add {} to allowlist", user);
+
+        self.allowlist_entry.set_inner(AllowlistEntry {
+            state: self.stake_pool_config.key(),
+            user,
+            bump: bumps.allowlist_entry,
+        });
+
+        emit!(AddToAllowlistEvent {
+            state: self.stake_pool_config.key(),
+            user,
+        });
+
+        Ok(())
+    }
+}
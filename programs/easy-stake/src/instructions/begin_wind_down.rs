@@ -0,0 +1,51 @@
+//! 开启清退模式，开启后不可撤销
+
+use anchor_lang::prelude::*;
+
+use crate::{error::StakingError, state::StakePoolConfig};
+
+
+#[event]
+pub struct BeginWindDownEvent {
+    pub state: Pubkey,
+    pub epoch: u64,
+    pub msol_supply: u64,
+    pub lp_supply: u64,
+    pub total_active_balance: u64,
+    pub stake_count: u32,
+}
+
+
+#[derive(Accounts)]
+pub struct BeginWindDown<'info> {
+    pub admin_authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump,
+        has_one = admin_authority @ StakingError::InvalidAdminAuthority
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+}
+
+
+impl<'info> BeginWindDown<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        self.stake_pool_config.check_not_winding_down()?;
+
+        msg!("Begin wind down");
+        self.stake_pool_config.wind_down = true;
+
+        emit!(BeginWindDownEvent {
+            state: self.stake_pool_config.key(),
+            epoch: Clock::get()?.epoch,
+            msol_supply: self.stake_pool_config.msol_supply,
+            lp_supply: self.stake_pool_config.liq_pool.lp_supply,
+            total_active_balance: self.stake_pool_config.validator_system.total_active_balance,
+            stake_count: self.stake_pool_config.stake_system.stake_list.count,
+        });
+
+        Ok(())
+    }
+}
@@ -0,0 +1,322 @@
+//! 清退完成后关闭质押池，回收 state、列表、国库等账户的租金。
+//! 验证者需先通过 remove_validator 移除，同时回收去重标志账户的租金。
+//! reserve 与 sol_leg 只把免租最低余额交给 rent_collector，国库份额及其余无人认领的 SOL
+//! 连同 operational_sol_account 的全部余额转给管理员指定的 treasury_destination。
+//! state 关闭后不再有指令能为这些 PDA 签名，因此必须在本指令中全部转出
+
+use anchor_lang::{
+    prelude::*,
+    system_program::{transfer, Transfer}
+};
+use anchor_spl::token::{burn, close_account, Burn, CloseAccount, Mint, Token, TokenAccount};
+
+use crate::{
+    calc::Rounding,
+    error::StakingError,
+    state::{
+        stake_system::StakeList,
+        validator_system::ValidatorList,
+        LiqPool,
+        StakePoolConfig
+    }
+};
+
+
+#[event]
+pub struct ClosePoolEvent {
+    pub state: Pubkey,
+    pub rent_collector: Pubkey,
+    /// 流动性池 mSOL leg 中无人认领的余额，关闭时销毁
+    pub msol_leg_burned: u64,
+    /// 国库中的 mSOL，关闭时销毁，对应的 SOL 转入 operational_sol_account
+    pub treasury_msol_burned: u64,
+    /// 国库 mSOL 按关闭前价格折算的 SOL
+    pub treasury_lamports: u64,
+    /// reserve 与 sol_leg 初始化时付的免租金，转给 rent_collector
+    pub rent_collected: u64,
+    pub treasury_destination: Pubkey,
+    /// reserve 与 sol_leg 中转给 treasury_destination 的 SOL：国库份额加上取整余量与捐赠
+    pub swept_lamports: u64,
+    /// operational_sol_account 的全部余额，转给 treasury_destination 后该账户被回收
+    pub operational_lamports: u64,
+}
+
+
+#[derive(Accounts)]
+pub struct ClosePool<'info> {
+    pub admin_authority: Signer<'info>,
+
+    #[account(mut)]
+    pub rent_collector: SystemAccount<'info>,
+
+    /// 接收国库份额、无人认领的 SOL 与 operational_sol_account 余额的账户，由管理员指定
+    #[account(mut)]
+    pub treasury_destination: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        close = rent_collector,
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump,
+        has_one = admin_authority @ StakingError::InvalidAdminAuthority,
+        has_one = msol_mint,
+        has_one = treasury_msol_account
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    #[account(mut)]
+    pub msol_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        close = rent_collector,
+        address = stake_pool_config.stake_system.stake_list.account
+    )]
+    pub stake_list: Account<'info, StakeList>,
+
+    #[account(
+        mut,
+        close = rent_collector,
+        address = stake_pool_config.validator_system.validator_list.account
+    )]
+    pub validator_list: Account<'info, ValidatorList>,
+
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            StakePoolConfig::RESERVE_SEED
+        ],
+        bump = stake_pool_config.reserve_bump_seed
+    )]
+    pub reserve_pda: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            LiqPool::SOL_LEG_SEED
+        ],
+        bump = stake_pool_config.liq_pool.sol_leg_bump_seed
+    )]
+    pub liq_pool_sol_leg_pda: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        address = stake_pool_config.liq_pool.msol_leg
+    )]
+    pub liq_pool_msol_leg: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: PDA
+    #[account(
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            LiqPool::MSOL_LEG_AUTHORITY_SEED
+        ],
+        bump = stake_pool_config.liq_pool.msol_leg_authority_bump_seed
+    )]
+    pub liq_pool_msol_leg_authority: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub treasury_msol_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            StakePoolConfig::OPERATIONAL_SOL_SEED
+        ],
+        bump = stake_pool_config.operational_sol_bump_seed
+    )]
+    pub operational_sol_account: SystemAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>
+}
+
+
+impl<'info> ClosePool<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        self.stake_pool_config.check_winding_down()?;
+        self.stake_pool_config.check_no_flash_loan()?;
+
+        // 所有质押必须已取回，验证者必须已移除，LP 必须已全部提取
+        require_eq!(
+            self.stake_pool_config.stake_system.stake_list.count,
+            0,
+            StakingError::PoolNotEmpty
+        );
+        require_eq!(
+            self.stake_pool_config.validator_system.validator_list.count,
+            0,
+            StakingError::PoolNotEmpty
+        );
+        require_eq!(
+            self.stake_pool_config.validator_system.total_active_balance,
+            0,
+            StakingError::PoolNotEmpty
+        );
+        require_eq!(
+            self.stake_pool_config.liq_pool.lp_supply,
+            0,
+            StakingError::PoolNotEmpty
+        );
+        // sol_leg 借给 reserve 的 SOL 属于已退出的 LP，必须先通过 rebalance 归还
+        require_eq!(
+            self.stake_pool_config.liq_pool.lent_from_sol_leg,
+            0,
+            StakingError::SolLegLoanOutstanding
+        );
+
+        // state 关闭后池子的 PDA 无法再签名，SOL 不能转给它们
+        for pda in [
+            self.reserve_pda.key(),
+            self.liq_pool_sol_leg_pda.key(),
+            self.operational_sol_account.key()
+        ] {
+            require_keys_neq!(self.rent_collector.key(), pda);
+            require_keys_neq!(self.treasury_destination.key(), pda);
+        }
+
+        let state_key = self.stake_pool_config.key();
+        let stake_pool_seeds: &[&[u8]] = &[
+            StakePoolConfig::STAKE_POOL_CONFIG_SEED,
+            &[self.stake_pool_config.stake_bump]
+        ];
+        let msol_leg_authority_seeds: &[&[u8]] = &[
+            state_key.as_ref(),
+            LiqPool::MSOL_LEG_AUTHORITY_SEED,
+            &[self.stake_pool_config.liq_pool.msol_leg_authority_bump_seed]
+        ];
+
+        // LP 全部提取后 mSOL leg 中只剩取整余量，国库 mSOL 归协议，两者销毁后用户 mSOL 必须已全部赎回
+        let msol_leg_burned = self.liq_pool_msol_leg.amount;
+        let treasury_msol_burned = self.treasury_msol_account.amount;
+        require_eq!(
+            self.stake_pool_config.msol_supply,
+            msol_leg_burned + treasury_msol_burned,
+            StakingError::PoolNotEmpty
+        );
+        let treasury_lamports = self.stake_pool_config
+            .msol_to_sol(treasury_msol_burned, Rounding::RoundDown)?;
+
+        if msol_leg_burned > 0 {
+            burn(
+                CpiContext::new_with_signer(
+                    self.token_program.to_account_info(),
+                    Burn {
+                        mint: self.msol_mint.to_account_info(),
+                        from: self.liq_pool_msol_leg.to_account_info(),
+                        authority: self.liq_pool_msol_leg_authority.to_account_info()
+                    },
+                    &[msol_leg_authority_seeds]
+                ),
+                msol_leg_burned
+            )?;
+        }
+        if treasury_msol_burned > 0 {
+            burn(
+                CpiContext::new_with_signer(
+                    self.token_program.to_account_info(),
+                    Burn {
+                        mint: self.msol_mint.to_account_info(),
+                        from: self.treasury_msol_account.to_account_info(),
+                        authority: self.stake_pool_config.to_account_info()
+                    },
+                    &[stake_pool_seeds]
+                ),
+                treasury_msol_burned
+            )?;
+        }
+        self.stake_pool_config.on_msol_burn(msol_leg_burned + treasury_msol_burned);
+
+        close_account(CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            CloseAccount {
+                account: self.liq_pool_msol_leg.to_account_info(),
+                destination: self.rent_collector.to_account_info(),
+                authority: self.liq_pool_msol_leg_authority.to_account_info()
+            },
+            &[msol_leg_authority_seeds]
+        ))?;
+        close_account(CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            CloseAccount {
+                account: self.treasury_msol_account.to_account_info(),
+                destination: self.rent_collector.to_account_info(),
+                authority: self.stake_pool_config.to_account_info()
+            },
+            &[stake_pool_seeds]
+        ))?;
+
+        // 初始化时付给 reserve 与 sol_leg 的免租金归 rent_collector，国库份额及其余 SOL 归 treasury_destination
+        let rent_exempt = self.stake_pool_config.rent_exempt_for_token_acc;
+        let (reserve_rent, reserve_rest) = self.sweep(
+            self.reserve_pda.to_account_info(),
+            &[
+                state_key.as_ref(),
+                StakePoolConfig::RESERVE_SEED,
+                &[self.stake_pool_config.reserve_bump_seed]
+            ],
+            rent_exempt
+        )?;
+        let (sol_leg_rent, sol_leg_rest) = self.sweep(
+            self.liq_pool_sol_leg_pda.to_account_info(),
+            &[
+                state_key.as_ref(),
+                LiqPool::SOL_LEG_SEED,
+                &[self.stake_pool_config.liq_pool.sol_leg_bump_seed]
+            ],
+            rent_exempt
+        )?;
+        // operational_sol_account 余额全部转出，余额为 0 的系统账户随后被回收
+        let (_, operational_lamports) = self.sweep(
+            self.operational_sol_account.to_account_info(),
+            &[
+                state_key.as_ref(),
+                StakePoolConfig::OPERATIONAL_SOL_SEED,
+                &[self.stake_pool_config.operational_sol_bump_seed]
+            ],
+            0
+        )?;
+
+        emit!(ClosePoolEvent {
+            state: state_key,
+            rent_collector: self.rent_collector.key(),
+            msol_leg_burned,
+            treasury_msol_burned,
+            treasury_lamports,
+            rent_collected: reserve_rent + sol_leg_rent,
+            treasury_destination: self.treasury_destination.key(),
+            swept_lamports: reserve_rest + sol_leg_rest,
+            operational_lamports,
+        });
+
+        Ok(())
+    }
+
+    /// 清空 PDA 余额：rent_exempt 以内的部分转给 rent_collector，其余转给 treasury_destination。
+    /// 返回 (转给 rent_collector 的数量, 转给 treasury_destination 的数量)
+    fn sweep(&self, from: AccountInfo<'info>, seeds: &[&[u8]], rent_exempt: u64) -> Result<(u64, u64)> {
+        let balance = from.lamports();
+        let rent = balance.min(rent_exempt);
+        let rest = balance - rent;
+        // 先转出 rent 之外的部分，保证中间状态的余额仍然免租
+        for (to, amount) in [
+            (self.treasury_destination.to_account_info(), rest),
+            (self.rent_collector.to_account_info(), rent)
+        ] {
+            if amount > 0 {
+                transfer(
+                    CpiContext::new_with_signer(
+                        self.system_program.to_account_info(),
+                        Transfer { from: from.clone(), to },
+                        &[seeds]
+                    ),
+                    amount
+                )?;
+            }
+        }
+        Ok((rent, rest))
+    }
+}
@@ -0,0 +1,81 @@
+//! 修改流动性池配置
+
+use anchor_lang::prelude::*;
+
+use crate::{
+    error::StakingError, 
+    state::{Fee, FeeCurve, StakePoolConfig}
+};
+
+
+#[event]
+pub struct ConfigLpEvent {
+    pub state: Pubkey,
+    pub params: ConfigLpParams,
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, AnchorSerialize, AnchorDeserialize)]
+pub struct ConfigLpParams {
+    /// 流动性解质押手续费曲线，None 表示不修改
+    pub fee_curve: Option<FeeCurve>,
+
+    /// 存款由 mSOL leg 兑付部分的手续费，None 表示不修改
+    pub deposit_swap_fee: Option<Fee>,
+
+    /// sol_leg 借出前在目标流动性之上保留的缓冲，None 表示不修改
+    pub lending_buffer: Option<Fee>,
+
+    /// 闪电贷手续费，None 表示不修改
+    pub flash_loan_fee: Option<Fee>,
+}
+
+
+#[derive(Accounts)]
+pub struct ConfigLp<'info> {
+    pub admin_authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump,
+        has_one = admin_authority @ StakingError::InvalidAdminAuthority
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+}
+
+
+impl<'info> ConfigLp<'info> {
+    pub fn process(&mut self, params: ConfigLpParams) -> Result<()> {
+        if let Some(fee_curve) = params.fee_curve {
+            msg!("Set fee curve {:?}", fee_curve);
+            self.stake_pool_config.fee_curve = fee_curve;
+        }
+
+        if let Some(deposit_swap_fee) = params.deposit_swap_fee {
+            msg!("Set deposit swap fee {}", deposit_swap_fee);
+            self.stake_pool_config.deposit_swap_fee = deposit_swap_fee;
+        }
+
+        if let Some(lending_buffer) = params.lending_buffer {
+            msg!("Set lending buffer {}", lending_buffer);
+            self.stake_pool_config.lending_buffer = lending_buffer;
+        }
+
+        if let Some(flash_loan_fee) = params.flash_loan_fee {
+            msg!("Set flash loan fee {}", flash_loan_fee);
+            self.stake_pool_config.flash_loan_fee = flash_loan_fee;
+        }
+
+        self.stake_pool_config.liq_pool.validate()?;
+        self.stake_pool_config.validate_liq_pool_fees()?;
+        self.stake_pool_config.validate_fee_curve()?;
+
+        emit!(ConfigLpEvent {
+            state: self.stake_pool_config.key(),
+            params
+        });
+
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+//! 修改质押池配置
+
+use anchor_lang::prelude::*;
+
+use crate::{
+    error::StakingError, 
+    require_lte,
+    state::{Fee, StakePoolConfig}
+};
+
+
+#[event]
+pub struct ConfigStakePoolEvent {
+    pub state: Pubkey,
+    pub params: ConfigStakePoolParams,
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, AnchorSerialize, AnchorDeserialize)]
+pub struct ConfigStakePoolParams {
+    /// epoch 结束前窗口期内的存款手续费，None 表示不修改
+    pub epoch_end_deposit_fee: Option<Fee>,
+
+    /// 收取窗口期存款手续费的 slot 数，0 表示关闭，None 表示不修改
+    pub epoch_end_window_slots: Option<u64>,
+
+    /// 是否开启交易检查，None 表示不修改
+    pub transaction_guard_enabled: Option<bool>,
+
+    /// 每个 epoch 的存款总额限制，u64::MAX 表示不限制，None 表示不修改
+    pub epoch_deposit_limit: Option<u64>,
+
+    /// 每个钱包每个 epoch 的存款限制，u64::MAX 表示不限制，None 表示不修改
+    pub user_epoch_deposit_limit: Option<u64>,
+
+    /// 每个 epoch 从 operational_sol_account 支出的预算，u64::MAX 表示不限制，None 表示不修改
+    pub operational_budget_per_epoch: Option<u64>,
+
+    /// 每创建一个质押账户或记录到一次收益的调用奖励，0 表示不支付，None 表示不修改
+    pub crank_bounty: Option<u64>,
+
+    /// 每个 epoch 的调用奖励总额上限，None 表示不修改
+    pub crank_bounty_cap_per_epoch: Option<u64>,
+
+    /// 是否开启许可模式，开启后只有白名单用户可以存取，None 表示不修改
+    pub permissioned: Option<bool>,
+}
+
+
+#[derive(Accounts)]
+pub struct ConfigStakePool<'info> {
+    pub admin_authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump,
+        has_one = admin_authority @ StakingError::InvalidAdminAuthority
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+}
+
+
+impl<'info> ConfigStakePool<'info> {
+    pub fn process(&mut self, params: ConfigStakePoolParams) -> Result<()> {
+        if let Some(epoch_end_deposit_fee) = params.epoch_end_deposit_fee {
+            msg!("Set epoch end deposit fee {}", epoch_end_deposit_fee);
+            require_lte!(
+                epoch_end_deposit_fee,
+                StakePoolConfig::MAX_EPOCH_END_DEPOSIT_FEE,
+                StakingError::EpochEndDepositFeeIsTooHigh
+            );
+            self.stake_pool_config.epoch_end_deposit_fee = epoch_end_deposit_fee;
+        }
+
+        if let Some(epoch_end_window_slots) = params.epoch_end_window_slots {
+            msg!("Set epoch end window slots {}", epoch_end_window_slots);
+            self.stake_pool_config.epoch_end_window_slots = epoch_end_window_slots;
+        }
+
+        if let Some(transaction_guard_enabled) = params.transaction_guard_enabled {
+            msg!("Set transaction guard enabled {}", transaction_guard_enabled);
+            self.stake_pool_config.transaction_guard_enabled = transaction_guard_enabled;
+        }
+
+        if let Some(epoch_deposit_limit) = params.epoch_deposit_limit {
+            msg!("Set epoch deposit limit {}", epoch_deposit_limit);
+            self.stake_pool_config.epoch_deposit_limit = epoch_deposit_limit;
+        }
+
+        if let Some(user_epoch_deposit_limit) = params.user_epoch_deposit_limit {
+            msg!("Set user epoch deposit limit {}", user_epoch_deposit_limit);
+            self.stake_pool_config.user_epoch_deposit_limit = user_epoch_deposit_limit;
+        }
+
+        if let Some(operational_budget_per_epoch) = params.operational_budget_per_epoch {
+            msg!("Set operational budget per epoch {}", operational_budget_per_epoch);
+            self.stake_pool_config.operational_budget_per_epoch = operational_budget_per_epoch;
+        }
+
+        if let Some(crank_bounty) = params.crank_bounty {
+            msg!("Set crank bounty {}", crank_bounty);
+            self.stake_pool_config.crank_bounty = crank_bounty;
+        }
+
+        if let Some(crank_bounty_cap_per_epoch) = params.crank_bounty_cap_per_epoch {
+            msg!("Set crank bounty cap per epoch {}", crank_bounty_cap_per_epoch);
+            self.stake_pool_config.crank_bounty_cap_per_epoch = crank_bounty_cap_per_epoch;
+        }
+
+        if let Some(permissioned) = params.permissioned {
+            msg!("Set permissioned {}", permissioned);
+            self.stake_pool_config.permissioned = permissioned;
+        }
+
+        emit!(ConfigStakePoolEvent {
+            state: self.stake_pool_config.key(),
+            params
+        });
+
+        Ok(())
+    }
+}
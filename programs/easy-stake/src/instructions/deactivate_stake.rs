@@ -1,48 +1,39 @@
-//! 解质押(验证者需提前从验证者列表删除)
+//! 解除质押（清退模式下任何人都可调用），冷却结束后通过 withdraw_stake 取回
 
 use anchor_lang::{
-    prelude::*, 
-    system_program::ID as sys_id,
-    solana_program::{
-        sysvar::stake_history::ID as STAKE_HISTORY_ID,
-        stake::{
-            program::ID as STAKE_ID,
-            state::StakeStateV2
-        },
-    }
+    prelude::*,
+    solana_program::{program::invoke_signed, stake}
 };
-use anchor_spl::stake::{StakeAccount, Stake};
+use anchor_spl::stake::{Stake, StakeAccount};
 
 use crate::{
-    checks::check_stake_amount_and_validator, error::StakingError, state::{
-        stake_system::{StakeList, StakeSystem}, 
+    error::StakingError,
+    state::{
+        stake_system::{StakeList, StakeSystem},
         StakePoolConfig
     }
 };
 
 
+#[event]
+pub struct DeactivateStakeEvent {
+    pub state: Pubkey,
+    pub epoch: u64,
+    pub stake_index: u32,
+    pub stake_account: Pubkey,
+    pub validator_vote: Pubkey,
+    pub delegated_lamports: u64,
+}
+
+
 #[derive(Accounts)]
-pub struct DeacitvateStake<'info> {
+pub struct DeactivateStake<'info> {
     #[account(
-        mut,
         seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
         bump = stake_pool_config.stake_bump,
     )]
     pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
 
-    #[account(
-        seeds = [
-            stake_pool_config.key().as_ref(),
-            StakePoolConfig::RESERVE_SEED
-        ],
-        bump = stake_pool_config.reserve_bump_seed
-    )]
-    pub reserve_pda: SystemAccount<'info>,
-
-    /// CHECK: CPI
-    #[account(mut)]
-    pub validator_vote: UncheckedAccount<'info>,
-
     #[account(
         mut,
         address = stake_pool_config.stake_system.stake_list.account
@@ -52,6 +43,7 @@ pub struct DeacitvateStake<'info> {
     #[account(mut)]
     pub stake_account: Box<Account<'info, StakeAccount>>,
 
+    /// CHECK: PDA
     #[account(
         seeds = [
             stake_pool_config.key().as_ref(),
@@ -61,52 +53,66 @@ pub struct DeacitvateStake<'info> {
     )]
     pub stake_deposit_authority: UncheckedAccount<'info>,
 
-    #[account(
-        mut,
-        owner = sys_id
-    )]
-    pub split_stake_rent_payer: Signer<'info>,
-
-    #[account(
-        init,
-        payer = split_stake_rent_payer,
-        space = std::mem::size_of::<StakeStateV2>(),
-        owner = STAKE_ID
-    )]
-    pub split_stake_account: Account<'info, StakeAccount>,
-
-    /// CHECK: have no CPU budget to parse
-    #[account(address = STAKE_HISTORY_ID)]
-    pub stake_history: UncheckedAccount<'info>,
-
     pub clock: Sysvar<'info, Clock>,
-    pub epoch_schedule: Sysvar<'info, EpochSchedule>,
-    pub rent: Sysvar<'info, Rent>,
-    pub system_program: Program<'info, System>,
     pub stake_program: Program<'info, Stake>,
 }
 
-impl<'info> DeacitvateStake<'info> {
-    pub fn process(
-        &mut self,
-        stake_index: u32
-    ) -> Result<()> {
+
+impl<'info> DeactivateStake<'info> {
+    pub fn process(&mut self, stake_index: u32) -> Result<()> {
         require!(!self.stake_pool_config.paused, StakingError::ProgramIsPaused);
+        self.stake_pool_config.check_winding_down()?;
 
-        let stake = self.stake_pool_config.stake_system.get_checked(
-            &self.stake_list.to_account_info().data.borrow(), 
-            stake_index, 
+        let mut stake = self.stake_pool_config.stake_system.get_checked(
+            &self.stake_list.to_account_info().data.borrow(),
+            stake_index,
             self.stake_account.to_account_info().key
         )?;
+        require_eq!(
+            stake.is_emergency_unstaking,
+            0,
+            StakingError::StakeAlreadyDeactivating
+        );
+
+        let delegation = self.stake_account
+            .delegation()
+            .ok_or(StakingError::StakeNotDelegated)?;
+
+        msg!("Deactivate stake {}", self.stake_account.key());
+        invoke_signed(
+            &stake::instruction::deactivate_stake(
+                &self.stake_account.key(),
+                self.stake_deposit_authority.key
+            ),
+            &[
+                self.stake_program.to_account_info(),
+                self.stake_account.to_account_info(),
+                self.clock.to_account_info(),
+                self.stake_deposit_authority.to_account_info()
+            ],
+            &[&[
+                self.stake_pool_config.key().as_ref(),
+                StakeSystem::STAKE_DEPOSIT_SEED,
+                &[self.stake_pool_config.stake_system.stake_deposit_bump_seed]
+            ]]
+        )?;
 
-        check_stake_amount_and_validator(
-            &self.stake_account, 
-            stake.last_update_delegated_lamports, 
-            self.validator_vote.key
+        // 质押在取回前仍计入 total_active_balance，只标记为冷却中
+        stake.is_emergency_unstaking = 1;
+        self.stake_pool_config.stake_system.set(
+            &mut self.stake_list.to_account_info().data.borrow_mut(),
+            stake_index,
+            stake
         )?;
-        
-        // 将stake_account中的钱转入reserve_pda
-        // 销毁stake_account
+
+        emit!(DeactivateStakeEvent {
+            state: self.stake_pool_config.key(),
+            epoch: self.clock.epoch,
+            stake_index,
+            stake_account: self.stake_account.key(),
+            validator_vote: delegation.voter_pubkey,
+            delegated_lamports: delegation.stake,
+        });
 
         Ok(())
     }
@@ -1,6 +1,10 @@
 //! 用户质押逻辑
 
-use anchor_lang::{prelude::*, system_program::{self, transfer, Transfer}};
+use anchor_lang::{
+    prelude::*,
+    solana_program::sysvar::instructions::ID as INSTRUCTIONS_ID,
+    system_program::{self, transfer, Transfer}
+};
 use anchor_spl::{
     associated_token::AssociatedToken, 
     token::{
@@ -15,9 +19,20 @@ use anchor_spl::{
 };
 
 use crate::{
+    calc::Rounding,
+    checks::{check_allowlist, check_transaction_guard, PoolDirection},
     error::StakingError, 
     require_lte, 
-    state::{LiqPool, StakePoolConfig}
+    state::{
+        AllowlistEntry,
+        Fee,
+        FeeOverride,
+        LiqPool,
+        MsolPriceOracle,
+        ReferralAccount,
+        StakePoolConfig,
+        UserDepositRecord
+    }
 };
 
 
@@ -32,13 +47,142 @@ pub struct DepositEvent {
     pub reserve_balance: u64,
     pub sol_swapped: u64,
     pub msol_swapped: u64,
+    pub deposit_swap_fee: u64,
+    pub treasury_msol_cut: u64,
     pub sol_deposited: u64,
+    pub sol_leg_repaid: u64,
     pub msol_minted: u64,
+    pub epoch_end_fee: u64,
+    pub referral: Option<Pubkey>,
+    pub referral_msol_cut: u64,
+    pub fee_override: Option<Pubkey>,
+    // 存款限制
+    pub epoch_deposited: u64,
+    pub epoch_deposit_limit: u64,
+    pub user_epoch_deposited: Option<u64>,
+    pub user_epoch_deposit_limit: u64,
     pub total_virtual_staked_lamports: u64,
     pub msol_supply: u64
 }
 
 
+/// 通过 return data 返回给 CPI 调用方的结果，quote_deposit 返回同一结构
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DepositResult {
+    /// 用户实际收到的 mSOL（兑换部分扣除手续费后加上新铸造部分）
+    pub msol_out: u64,
+    pub msol_swapped: u64,
+    pub msol_minted: u64,
+    /// 兑换部分收取的 mSOL 手续费
+    pub fee: u64,
+    pub treasury_msol_cut: u64,
+    /// 进入 sol_leg 换取 mSOL 的 SOL
+    pub sol_swapped: u64,
+    /// 未经池子兑换、进入质押的 SOL
+    pub sol_deposited: u64,
+    /// sol_deposited 中用于归还 sol_leg 借出流动性的部分
+    pub sol_leg_repaid: u64,
+    /// epoch 结束前窗口期收取的 SOL 手续费，包含在 sol_deposited 中，不为其铸造 mSOL
+    pub epoch_end_fee: u64,
+}
+
+
+impl DepositResult {
+    /// 只依据池子当前状态计算质押结果，不修改任何状态。deposit 与 quote_deposit 共用，保证报价与实际执行一致。
+    /// epoch_end_deposit_fee 由调用方按当前 slot 确定，传入 fee_override 时两项存款手续费都按覆盖调整
+    pub fn calc(
+        config: &StakePoolConfig,
+        lamports: u64,
+        sol_leg_balance: u64,
+        msol_leg_balance: u64,
+        epoch_end_deposit_fee: Fee,
+        fee_override: Option<&FeeOverride>
+    ) -> Result<Self> {
+        require_gte!(
+            lamports, 
+            config.min_deposit, 
+            StakingError::DepositAmountIsTooLow
+        );
+
+        // lp_min_fee 只约束流动性解质押，存款手续费没有下限，覆盖可以将其降到 0
+        let min_fee = Fee::from_basis_points(0);
+        let epoch_end_deposit_fee = FeeOverride::apply_optional(
+            fee_override,
+            epoch_end_deposit_fee,
+            min_fee
+        );
+        let deposit_swap_fee = FeeOverride::apply_optional(
+            fee_override,
+            config.deposit_swap_fee,
+            min_fee
+        );
+
+        // 窗口期手续费向上取整，这部分 SOL 进入 reserve 但不铸造 mSOL，由现有持有者分享
+        let epoch_end_fee = epoch_end_deposit_fee.apply(lamports, Rounding::RoundUp);
+        let lamports_for_msol = lamports - epoch_end_fee;
+        if epoch_end_fee > 0 {
+            msg!("--- epoch_end_fee {}", epoch_end_fee);
+        }
+
+        // 用户获得的 mSOL 向下取整
+        let user_msol_buy_order = config.calc_msol_from_lamports(
+            lamports_for_msol, 
+            Rounding::RoundDown
+        )?;
+        msg!("--- user_MSOL_buy_order {}", user_msol_buy_order);
+
+        let msol_swapped = user_msol_buy_order.min(msol_leg_balance);
+        msg!("--- swap_MSOL_max {}", msol_swapped);
+
+        // 兑付部分收取手续费，其中国库分成转入国库，其余留在 mSOL leg 作为 LP 收益
+        let fee = deposit_swap_fee.apply(
+            msol_swapped, 
+            Rounding::RoundUp
+        );
+        let treasury_msol_cut = config.liq_pool.treasury_cut.apply(
+            fee, 
+            Rounding::RoundDown
+        );
+        msg!("--- deposit_swap_fee {} treasury_msol_cut {}", fee, treasury_msol_cut);
+
+        // 计算实际进入池子的sol
+        let sol_swapped = if msol_swapped == 0 {
+            0
+        } else if user_msol_buy_order == msol_swapped {
+            lamports_for_msol
+        } else {
+            // 用户为从池子换得的 mSOL 支付的 SOL 向上取整
+            config.msol_to_sol(msol_swapped, Rounding::RoundUp)?.min(lamports_for_msol)
+        };
+
+        let sol_deposited = lamports - sol_swapped;
+        // sol_leg 低于目标时，优先用本次进入 reserve 的 sol 收回借给 reserve 的流动性
+        let sol_leg_repaid = config.liq_pool
+            .repayable_lamports(
+                (sol_leg_balance + sol_swapped).saturating_sub(config.rent_exempt_for_token_acc)
+            )
+            .min(sol_deposited);
+        if sol_deposited > 0 {
+            config.check_staking_cap(sol_deposited)?;
+        }
+
+        let msol_minted = user_msol_buy_order - msol_swapped;
+
+        Ok(Self {
+            msol_out: msol_swapped - fee + msol_minted,
+            msol_swapped,
+            msol_minted,
+            fee,
+            treasury_msol_cut,
+            sol_swapped,
+            sol_deposited,
+            sol_leg_repaid,
+            epoch_end_fee,
+        })
+    }
+}
+
+
 #[derive(Accounts)]
 pub struct Deposit<'info> {
     #[account(
@@ -86,6 +230,12 @@ pub struct Deposit<'info> {
     )]
     pub liq_pool_msol_leg_authority: UncheckedAccount<'info>,
 
+    #[account(
+        mut,
+        address = stake_pool_config.treasury_msol_account
+    )]
+    pub treasury_msol_account: Box<Account<'info, TokenAccount>>,
+
     #[account(
         mut,
         seeds = [
@@ -114,6 +264,47 @@ pub struct Deposit<'info> {
     )]
     pub msol_mint_authority: UncheckedAccount<'info>,
 
+    #[account(
+        constraint = fee_override.state == stake_pool_config.key() @ StakingError::InvalidFeeOverride
+    )]
+    pub fee_override: Option<Box<Account<'info, FeeOverride>>>,
+
+    #[account(
+        mut,
+        constraint = referral.state == stake_pool_config.key() @ StakingError::InvalidReferralAccount
+    )]
+    pub referral: Option<Box<Account<'info, ReferralAccount>>>,
+
+    /// 合作方 mSOL 账户，传入 referral 时必须提供
+    #[account(mut)]
+    pub referral_msol_account: Option<Box<Account<'info, TokenAccount>>>,
+
+    /// 开启钱包存款限制时必须传入调用者的存款记录
+    #[account(
+        mut,
+        constraint = user_deposit_record.state == stake_pool_config.key()
+            && user_deposit_record.user == user.key() @ StakingError::InvalidUserDepositRecord
+    )]
+    pub user_deposit_record: Option<Box<Account<'info, UserDepositRecord>>>,
+
+    /// 许可模式下必须传入调用者的白名单账户
+    pub allowlist_entry: Option<Box<Account<'info, AllowlistEntry>>>,
+
+    /// CHECK: 指令 sysvar，仅在开启交易检查时需要
+    #[account(address = INSTRUCTIONS_ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+
+    /// 传入时在汇率变化后同步刷新 mSOL 价格预言机
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            MsolPriceOracle::SEED
+        ],
+        bump = price_oracle.bump
+    )]
+    pub price_oracle: Option<Box<Account<'info, MsolPriceOracle>>>,
+
     pub system_program: Program<'info, System>,
     pub token_program: Program<'info, Token>,
     pub associated_token_program: Program<'info, AssociatedToken>
@@ -121,14 +312,20 @@ pub struct Deposit<'info> {
 
 
 impl<'info> Deposit<'info> {
-    pub fn process(&mut self, lamports: u64) -> Result<()> {
+    pub fn process(&mut self, lamports: u64) -> Result<DepositResult> {
         require!(!self.stake_pool_config.paused, StakingError::ProgramIsPaused);
-
-        require_gte!(
-            lamports, 
-            self.stake_pool_config.min_deposit, 
-            StakingError::DepositAmountIsTooLow
-        );
+        self.stake_pool_config.check_not_winding_down()?;
+        self.stake_pool_config.check_no_flash_loan()?;
+        check_transaction_guard(
+            &self.stake_pool_config,
+            self.instructions.as_ref(),
+            PoolDirection::In
+        )?;
+        check_allowlist(
+            &self.stake_pool_config,
+            self.allowlist_entry.as_deref().map(|entry| &**entry),
+            &self.user.key()
+        )?;
 
         let user_sol_balance = self.user.lamports();
         require_gte!(
@@ -143,45 +340,75 @@ impl<'info> Deposit<'info> {
             StakingError::UnregisteredMsolMinted
         );
 
+        // 每个 epoch 的存款总额与钱包存款限制，进入新 epoch 后重新计数
+        let epoch = Clock::get()?.epoch;
+        self.stake_pool_config.check_epoch_deposit_limit(lamports, epoch)?;
+        let user_epoch_deposit_limit = self.stake_pool_config.user_epoch_deposit_limit;
+        match &mut self.user_deposit_record {
+            Some(record) => {
+                record.check_limit(lamports, epoch, user_epoch_deposit_limit)?;
+                record.on_deposit(lamports, epoch);
+            }
+            None => require_eq!(
+                user_epoch_deposit_limit,
+                u64::MAX,
+                StakingError::InvalidUserDepositRecord
+            ),
+        }
+        self.stake_pool_config.on_epoch_deposit(lamports, epoch);
+
         let user_msol_balance = self.mint_to.amount;
         let reserve_balance = self.reserve_pda.lamports();
         let sol_leg_balance = self.liq_pool_sol_leg_pda.lamports();
-        let total_virtual_staked_lamports = self.stake_pool_config.total_staked_lamports();
+        let msol_leg_balance = self.liq_pool_msol_leg.amount;
+        let total_virtual_staked_lamports = self.stake_pool_config.total_staked_lamports()?;
         let msol_supply = self.stake_pool_config.msol_supply;
 
-        let user_msol_buy_order = self.stake_pool_config.calc_msol_from_lamports(lamports)?;
-        msg!("--- user_MSOL_buy_order {}", user_msol_buy_order);
+        if let Some(fee_override) = &self.fee_override {
+            fee_override.check_applies(&self.user.key(), self.instructions.as_ref())?;
+        }
 
-        let msol_leg_balance = self.liq_pool_msol_leg.amount;
-        let msol_swapped = user_msol_buy_order.min(msol_leg_balance);
-        msg!("--- swap_MSOL_max {}", msol_swapped);
+        let result = DepositResult::calc(
+            &self.stake_pool_config,
+            lamports,
+            sol_leg_balance,
+            msol_leg_balance,
+            self.stake_pool_config.current_epoch_end_deposit_fee()?,
+            self.fee_override.as_deref().map(|fee_override| &**fee_override)
+        )?;
+
+        // 国库分成中合作方的部分直接转给合作方
+        let referral_msol_cut = match &self.referral {
+            Some(referral) => {
+                referral.check_partner_msol_account(self.referral_msol_account.as_deref())?;
+                referral.partner_cut(result.treasury_msol_cut)
+            }
+            None => 0
+        };
 
-        // 计算实际进入池子的sol
-        let sol_swapped = if msol_swapped > 0 {
-            let sol_swapped = if user_msol_buy_order == msol_swapped {
-                lamports
-            } else {
-                self.stake_pool_config.msol_to_sol(msol_swapped)?
-            };
-
-            // 给用户发放 mSOL
-            transfer_tokens(
-                CpiContext::new_with_signer(
-                    self.token_program.to_account_info(), 
-                    TransferTokens {
-                        from: self.liq_pool_msol_leg.to_account_info(),
-                        to: self.mint_to.to_account_info(),
-                        authority: self.liq_pool_msol_leg_authority.to_account_info()
-                    }, 
-                    &[&[
-                        self.stake_pool_config.key().as_array(),
-                        LiqPool::MSOL_LEG_AUTHORITY_SEED,
-                        &[self.stake_pool_config.msol_mint_authority_bump_seed]
-                    ]]
-                ),
-                msol_swapped
+        if result.msol_swapped > 0 {
+            // 给用户发放扣除手续费后的 mSOL
+            self.transfer_from_msol_leg(
+                self.mint_to.to_account_info(), 
+                result.msol_swapped - result.fee
             )?;
 
+            if referral_msol_cut > 0 {
+                if let Some(referral_msol_account) = &self.referral_msol_account {
+                    self.transfer_from_msol_leg(
+                        referral_msol_account.to_account_info(), 
+                        referral_msol_cut
+                    )?;
+                }
+            }
+
+            if result.treasury_msol_cut > referral_msol_cut {
+                self.transfer_from_msol_leg(
+                    self.treasury_msol_account.to_account_info(), 
+                    result.treasury_msol_cut - referral_msol_cut
+                )?;
+            }
+
             // 将用户的 sol 转入池子 sol 代币账户
             transfer(
                 CpiContext::new(
@@ -191,38 +418,47 @@ impl<'info> Deposit<'info> {
                         to: self.liq_pool_sol_leg_pda.to_account_info()
                     }
                 ), 
-                sol_swapped
+                result.sol_swapped
             )?;
+        }
 
-            sol_swapped
-        } else {
-            0
-        };
-
-        let sol_deposited = lamports - sol_swapped;
         // 未进入池子的 sol
-        if sol_deposited > 0 {
-            self.stake_pool_config.check_staking_cap(sol_deposited)?;
+        if result.sol_leg_repaid > 0 {
+            msg!("--- sol_leg_repaid {}", result.sol_leg_repaid);
+            transfer(
+                CpiContext::new(
+                    self.system_program.to_account_info(), 
+                    Transfer {
+                        from: self.user.to_account_info(),
+                        to: self.liq_pool_sol_leg_pda.to_account_info()
+                    }
+                ), 
+                result.sol_leg_repaid
+            )?;
+
+            self.stake_pool_config.on_repay_to_sol_leg(result.sol_leg_repaid, false);
+        }
 
+        let sol_to_reserve = result.sol_deposited - result.sol_leg_repaid;
+        if sol_to_reserve > 0 {
             // 将 sol 转入 reserve_pda 账户
             transfer(
                 CpiContext::new(
                     self.system_program.to_account_info(), 
-                Transfer {
-                    from: self.user.to_account_info(),
-                    to: self.reserve_pda.to_account_info()
+                    Transfer {
+                        from: self.user.to_account_info(),
+                        to: self.reserve_pda.to_account_info()
                     }
                 ), 
-            sol_deposited
+                sol_to_reserve
             )?;
 
-            self.stake_pool_config.on_transfer_to_reserve(sol_deposited);
+            self.stake_pool_config.on_transfer_to_reserve(sol_to_reserve);
         }
 
-        let msol_minted = user_msol_buy_order - msol_swapped;
         // 未能通过池子兑换的 msol 调用 msol mint 进行铸造
-        if msol_minted > 0 {
-            msg!("--- msol_to_mint {}", msol_minted);
+        if result.msol_minted > 0 {
+            msg!("--- msol_to_mint {}", result.msol_minted);
             mint_to(
                 CpiContext::new_with_signer(
                     self.token_program.to_account_info(), 
@@ -237,12 +473,21 @@ impl<'info> Deposit<'info> {
                         &[self.stake_pool_config.msol_mint_authority_bump_seed]
                     ]]
                 ), 
-                msol_minted
+                result.msol_minted
             )?;
 
-            self.stake_pool_config.on_msol_mint(msol_minted);
+            self.stake_pool_config.on_msol_mint(result.msol_minted);
+        }
+
+        if let Some(referral) = &mut self.referral {
+            referral.on_deposit(lamports, referral_msol_cut);
+            referral.emit_event(referral.key(), referral_msol_cut);
         }
 
+        self.stake_pool_config.refresh_price_oracle(
+            self.price_oracle.as_deref_mut().map(|price_oracle| &mut **price_oracle)
+        )?;
+
         // 链下事件记录
         emit!(DepositEvent {
             state: self.stake_pool_config.key(),
@@ -252,14 +497,44 @@ impl<'info> Deposit<'info> {
             sol_leg_balance,
             msol_leg_balance,
             reserve_balance,
-            sol_swapped,
-            msol_swapped,
-            sol_deposited,
-            msol_minted,
+            sol_swapped: result.sol_swapped,
+            msol_swapped: result.msol_swapped,
+            deposit_swap_fee: result.fee,
+            treasury_msol_cut: result.treasury_msol_cut,
+            sol_deposited: result.sol_deposited,
+            sol_leg_repaid: result.sol_leg_repaid,
+            msol_minted: result.msol_minted,
+            epoch_end_fee: result.epoch_end_fee,
+            referral: self.referral.as_ref().map(|referral| referral.key()),
+            referral_msol_cut,
+            fee_override: self.fee_override.as_ref().map(|fee_override| fee_override.key()),
+            epoch_deposited: self.stake_pool_config.epoch_deposited,
+            epoch_deposit_limit: self.stake_pool_config.epoch_deposit_limit,
+            user_epoch_deposited: self.user_deposit_record.as_ref().map(|record| record.deposited),
+            user_epoch_deposit_limit,
             total_virtual_staked_lamports,
             msol_supply
         });
 
-        Ok(())
+        Ok(result)
+    }
+
+    fn transfer_from_msol_leg(&self, to: AccountInfo<'info>, amount: u64) -> Result<()> {
+        transfer_tokens(
+            CpiContext::new_with_signer(
+                self.token_program.to_account_info(), 
+                TransferTokens {
+                    from: self.liq_pool_msol_leg.to_account_info(),
+                    to,
+                    authority: self.liq_pool_msol_leg_authority.to_account_info()
+                }, 
+                &[&[
+                    self.stake_pool_config.key().as_ref(),
+                    LiqPool::MSOL_LEG_AUTHORITY_SEED,
+                    &[self.stake_pool_config.liq_pool.msol_leg_authority_bump_seed]
+                ]]
+            ),
+            amount
+        )
     }
 }
@@ -0,0 +1,90 @@
+//! 用户使用 wSOL 代币账户质押
+
+use anchor_lang::prelude::*;
+use anchor_spl::token::{
+    close_account,
+    spl_token::native_mint,
+    transfer as transfer_tokens,
+    CloseAccount,
+    Mint,
+    Token,
+    TokenAccount,
+    Transfer as TransferTokens
+};
+
+use crate::{error::StakingError, require_lte, state::StakePoolConfig};
+
+use super::deposit::*;
+
+#[derive(Accounts)]
+pub struct DepositWsol<'info> {
+    pub deposit: Deposit<'info>,
+
+    #[account(
+        mut,
+        token::mint = wsol_mint,
+        token::authority = deposit.user
+    )]
+    pub wsol_from: Box<Account<'info, TokenAccount>>,
+
+    /// 临时 wSOL 账户，本指令内创建并关闭，关闭时 SOL 解包到用户账户
+    #[account(
+        init,
+        payer = deposit.user,
+        seeds = [
+            deposit.stake_pool_config.key().as_ref(),
+            StakePoolConfig::WSOL_TEMP_SEED,
+            deposit.user.key().as_ref()
+        ],
+        bump,
+        token::mint = wsol_mint,
+        token::authority = deposit.user
+    )]
+    pub wsol_temp: Box<Account<'info, TokenAccount>>,
+
+    #[account(address = native_mint::ID)]
+    pub wsol_mint: Box<Account<'info, Mint>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>
+}
+
+
+impl<'info> DepositWsol<'info> {
+    pub fn process(&mut self, lamports: u64) -> Result<DepositResult> {
+        require_lte!(
+            lamports,
+            self.wsol_from.amount,
+            StakingError::NotEnoughUserFunds
+        );
+
+        let token_program = self.deposit.token_program.to_account_info();
+
+        // 将 wSOL 转入临时账户后关闭，临时账户中的 SOL 连同租金一起退回用户
+        transfer_tokens(
+            CpiContext::new(
+                token_program.clone(),
+                TransferTokens {
+                    from: self.wsol_from.to_account_info(),
+                    to: self.wsol_temp.to_account_info(),
+                    authority: self.deposit.user.to_account_info()
+                }
+            ),
+            lamports
+        )?;
+
+        close_account(
+            CpiContext::new(
+                token_program,
+                CloseAccount {
+                    account: self.wsol_temp.to_account_info(),
+                    destination: self.deposit.user.to_account_info(),
+                    authority: self.deposit.user.to_account_info()
+                }
+            )
+        )?;
+
+        // 之后与原生 SOL 质押完全一致
+        self.deposit.process(lamports)
+    }
+}
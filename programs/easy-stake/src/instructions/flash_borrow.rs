@@ -0,0 +1,126 @@
+//! 从流动性池 sol_leg 借出闪电贷，同一交易内必须调用 flash_repay 归还
+
+use anchor_lang::{
+    prelude::*,
+    system_program::{transfer, Transfer},
+    solana_program::sysvar::instructions::{
+        load_current_index_checked,
+        load_instruction_at_checked,
+        ID as INSTRUCTIONS_ID
+    }
+};
+
+use crate::{
+    error::StakingError,
+    instruction::FlashRepay,
+    require_lte,
+    state::{LiqPool, StakePoolConfig},
+    ID
+};
+
+
+#[event]
+pub struct FlashBorrowEvent {
+    pub state: Pubkey,
+    pub borrower: Pubkey,
+    pub sol_leg_balance: u64,
+    pub amount: u64,
+}
+
+
+#[derive(Accounts)]
+pub struct FlashBorrow<'info> {
+    #[account(
+        mut,
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            LiqPool::SOL_LEG_SEED
+        ],
+        bump = stake_pool_config.liq_pool.sol_leg_bump_seed
+    )]
+    pub liq_pool_sol_leg_pda: SystemAccount<'info>,
+
+    #[account(mut)]
+    pub transfer_sol_to: SystemAccount<'info>,
+
+    /// CHECK: 指令 sysvar，用于检查同一交易中后续的还款指令
+    #[account(address = INSTRUCTIONS_ID)]
+    pub instructions: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>
+}
+
+
+impl<'info> FlashBorrow<'info> {
+    pub fn process(&mut self, lamports: u64) -> Result<()> {
+        require!(!self.stake_pool_config.paused, StakingError::ProgramIsPaused);
+        self.stake_pool_config.check_not_winding_down()?;
+        // 同一时间只允许一笔闪电贷
+        self.stake_pool_config.check_no_flash_loan()?;
+        require_gt!(lamports, 0, StakingError::InsufficientLiquidity);
+
+        let sol_leg_balance = self.liq_pool_sol_leg_pda.lamports();
+        require_lte!(
+            lamports,
+            sol_leg_balance.saturating_sub(self.stake_pool_config.rent_exempt_for_token_acc),
+            StakingError::InsufficientLiquidity
+        );
+
+        self.check_repay_follows()?;
+
+        transfer(
+            CpiContext::new_with_signer(
+                self.system_program.to_account_info(),
+                Transfer {
+                    from: self.liq_pool_sol_leg_pda.to_account_info(),
+                    to: self.transfer_sol_to.to_account_info()
+                },
+                &[&[
+                    self.stake_pool_config.key().as_ref(),
+                    LiqPool::SOL_LEG_SEED,
+                    &[self.stake_pool_config.liq_pool.sol_leg_bump_seed]
+                ]]
+            ),
+            lamports
+        )?;
+
+        self.stake_pool_config.flash_loan_amount = lamports;
+
+        emit!(FlashBorrowEvent {
+            state: self.stake_pool_config.key(),
+            borrower: self.transfer_sol_to.key(),
+            sol_leg_balance,
+            amount: lamports,
+        });
+
+        Ok(())
+    }
+
+    /// 借款指令必须是本程序的顶层指令，且同一交易中其后存在本程序的 flash_repay 指令
+    fn check_repay_follows(&self) -> Result<()> {
+        let instructions = self.instructions.to_account_info();
+        let current_index = load_current_index_checked(&instructions)? as usize;
+
+        let current = load_instruction_at_checked(current_index, &instructions)?;
+        require_keys_eq!(current.program_id, ID, StakingError::FlashLoanCpiNotAllowed);
+
+        let mut index = current_index + 1;
+        while let Ok(instruction) = load_instruction_at_checked(index, &instructions) {
+            if instruction.program_id == ID 
+                && instruction.data.starts_with(FlashRepay::DISCRIMINATOR) 
+            {
+                return Ok(());
+            }
+            index += 1;
+        }
+
+        err!(StakingError::FlashRepayMissing)
+    }
+}
@@ -0,0 +1,176 @@
+//! 归还闪电贷，手续费按 treasury_cut 分给国库，其余留在 sol_leg 归 LP
+
+use anchor_lang::{prelude::*, system_program::{transfer, Transfer, ID as sys_id}};
+use anchor_spl::token::{mint_to, Mint, MintTo, Token, TokenAccount};
+
+use crate::{
+    calc::Rounding,
+    error::StakingError,
+    state::{LiqPool, MsolPriceOracle, StakePoolConfig}
+};
+
+
+#[event]
+pub struct FlashRepayEvent {
+    pub state: Pubkey,
+    pub repayer: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+    pub treasury_fee: u64,
+    pub treasury_msol_minted: u64,
+}
+
+
+#[derive(Accounts)]
+pub struct FlashRepay<'info> {
+    #[account(
+        mut,
+        owner = sys_id
+    )]
+    pub repayer: Signer<'info>,
+
+    #[account(
+        mut,
+        has_one = msol_mint,
+        has_one = treasury_msol_account,
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            LiqPool::SOL_LEG_SEED
+        ],
+        bump = stake_pool_config.liq_pool.sol_leg_bump_seed
+    )]
+    pub liq_pool_sol_leg_pda: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            StakePoolConfig::RESERVE_SEED
+        ],
+        bump = stake_pool_config.reserve_bump_seed
+    )]
+    pub reserve_pda: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        mint::authority = msol_mint_authority
+    )]
+    pub msol_mint: Box<Account<'info, Mint>>,
+
+    /// CHECK: PDA
+    #[account(
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            StakePoolConfig::MSOL_MINT_AUTHORITY_SEED
+        ],
+        bump = stake_pool_config.msol_mint_authority_bump_seed
+    )]
+    pub msol_mint_authority: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub treasury_msol_account: Box<Account<'info, TokenAccount>>,
+
+    /// 传入时在汇率变化后同步刷新 mSOL 价格预言机
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            MsolPriceOracle::SEED
+        ],
+        bump = price_oracle.bump
+    )]
+    pub price_oracle: Option<Box<Account<'info, MsolPriceOracle>>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>
+}
+
+
+impl<'info> FlashRepay<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        let amount = self.stake_pool_config.flash_loan_amount;
+        require_gt!(amount, 0, StakingError::FlashLoanNotActive);
+
+        // 手续费向上取整，国库分成向下取整，余数留给 LP
+        let fee = self.stake_pool_config.flash_loan_fee.apply(amount, Rounding::RoundUp);
+        let treasury_fee = self.stake_pool_config.liq_pool.treasury_cut.apply(
+            fee,
+            Rounding::RoundDown
+        );
+        // 国库分成存入 reserve，按当前价格为国库铸造 mSOL；
+        // 不足以铸造 mSOL 时这部分并入 sol_leg 归 LP，保证手续费始终全额支付
+        let treasury_msol_minted = self.stake_pool_config.calc_msol_from_lamports(
+            treasury_fee,
+            Rounding::RoundDown
+        )?;
+        let treasury_fee = if treasury_msol_minted > 0 { treasury_fee } else { 0 };
+        msg!("Flash loan fee {} treasury fee {}", fee, treasury_fee);
+
+        transfer(
+            CpiContext::new(
+                self.system_program.to_account_info(),
+                Transfer {
+                    from: self.repayer.to_account_info(),
+                    to: self.liq_pool_sol_leg_pda.to_account_info()
+                }
+            ),
+            amount + fee - treasury_fee
+        )?;
+
+        self.stake_pool_config.flash_loan_amount = 0;
+
+        if treasury_msol_minted > 0 {
+            transfer(
+                CpiContext::new(
+                    self.system_program.to_account_info(),
+                    Transfer {
+                        from: self.repayer.to_account_info(),
+                        to: self.reserve_pda.to_account_info()
+                    }
+                ),
+                treasury_fee
+            )?;
+            self.stake_pool_config.on_transfer_to_reserve(treasury_fee);
+
+            mint_to(
+                CpiContext::new_with_signer(
+                    self.token_program.to_account_info(),
+                    MintTo {
+                        mint: self.msol_mint.to_account_info(),
+                        to: self.treasury_msol_account.to_account_info(),
+                        authority: self.msol_mint_authority.to_account_info()
+                    },
+                    &[&[
+                        self.stake_pool_config.key().as_ref(),
+                        StakePoolConfig::MSOL_MINT_AUTHORITY_SEED,
+                        &[self.stake_pool_config.msol_mint_authority_bump_seed]
+                    ]]
+                ),
+                treasury_msol_minted
+            )?;
+            self.stake_pool_config.on_msol_mint(treasury_msol_minted);
+        }
+
+        self.stake_pool_config.refresh_price_oracle(
+            self.price_oracle.as_deref_mut().map(|price_oracle| &mut **price_oracle)
+        )?;
+
+        emit!(FlashRepayEvent {
+            state: self.stake_pool_config.key(),
+            repayer: self.repayer.key(),
+            amount,
+            fee,
+            treasury_fee,
+            treasury_msol_minted,
+        });
+
+        Ok(())
+    }
+}
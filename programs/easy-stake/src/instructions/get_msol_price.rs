@@ -0,0 +1,39 @@
+//! 查询当前 mSOL 价格，只读，结果通过 return data 返回
+
+use anchor_lang::prelude::*;
+
+use crate::state::StakePoolConfig;
+
+
+/// 通过 return data 返回的 mSOL 价格
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MsolPrice {
+    /// 1 mSOL 可兑换的 SOL，按 StakePoolConfig::PRICE_DENOMINATOR 放大
+    pub price: u64,
+    pub price_denominator: u64,
+    // 价格的组成部分
+    pub total_virtual_staked_lamports: u64,
+    pub msol_supply: u64,
+}
+
+
+#[derive(Accounts)]
+pub struct GetMsolPrice<'info> {
+    #[account(
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+}
+
+
+impl<'info> GetMsolPrice<'info> {
+    pub fn process(&self) -> Result<MsolPrice> {
+        Ok(MsolPrice {
+            price: self.stake_pool_config.current_msol_price()?,
+            price_denominator: StakePoolConfig::PRICE_DENOMINATOR,
+            total_virtual_staked_lamports: self.stake_pool_config.total_staked_lamports()?,
+            msol_supply: self.stake_pool_config.msol_supply,
+        })
+    }
+}
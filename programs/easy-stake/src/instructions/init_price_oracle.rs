@@ -0,0 +1,67 @@
+//! 创建 mSOL 价格预言机账户
+
+use anchor_lang::{prelude::*, system_program::ID as sys_id};
+
+use crate::{
+    error::StakingError,
+    state::{MsolPriceOracle, PriceRecord, StakePoolConfig}
+};
+
+
+#[derive(Accounts)]
+pub struct InitPriceOracle<'info> {
+    #[account(
+        mut,
+        owner = sys_id
+    )]
+    pub rent_payer: Signer<'info>,
+
+    pub admin_authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump,
+        has_one = admin_authority @ StakingError::InvalidAdminAuthority
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    #[account(
+        init,
+        payer = rent_payer,
+        space = MsolPriceOracle::LEN,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            MsolPriceOracle::SEED
+        ],
+        bump
+    )]
+    pub price_oracle: Box<Account<'info, MsolPriceOracle>>,
+
+    pub system_program: Program<'info, System>
+}
+
+
+impl<'info> InitPriceOracle<'info> {
+    pub fn process(&mut self, bumps: InitPriceOracleBumps) -> Result<()> {
+        let price = self.stake_pool_config.current_msol_price()?;
+        msg!("Init mSOL price oracle, price {}", price);
+
+        self.price_oracle.set_inner(MsolPriceOracle {
+            state: self.stake_pool_config.key(),
+            price: 0,
+            epoch: 0,
+            slot: 0,
+            total_staked_lamports: 0,
+            msol_supply: 0,
+            history_head: 0,
+            history_len: 0,
+            bump: bumps.price_oracle,
+            history: [PriceRecord::default(); MsolPriceOracle::HISTORY_LEN],
+        });
+
+        self.stake_pool_config.refresh_price_oracle(Some(&mut self.price_oracle))?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+//! 创建用户存款记录账户，开启钱包存款限制后存款需要传入该账户
+
+use anchor_lang::{prelude::*, system_program::ID as sys_id};
+
+use crate::state::{StakePoolConfig, UserDepositRecord};
+
+
+#[derive(Accounts)]
+pub struct InitUserDepositRecord<'info> {
+    #[account(
+        mut,
+        owner = sys_id
+    )]
+    pub rent_payer: Signer<'info>,
+
+    /// CHECK: 记录所属的钱包，只用于派生地址
+    pub user: UncheckedAccount<'info>,
+
+    #[account(
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    #[account(
+        init,
+        payer = rent_payer,
+        space = UserDepositRecord::LEN,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            UserDepositRecord::SEED,
+            user.key().as_ref()
+        ],
+        bump
+    )]
+    pub user_deposit_record: Box<Account<'info, UserDepositRecord>>,
+
+    pub system_program: Program<'info, System>
+}
+
+
+impl<'info> InitUserDepositRecord<'info> {
+    pub fn process(&mut self, bumps: InitUserDepositRecordBumps) -> Result<()> {
+        self.user_deposit_record.set_inner(UserDepositRecord {
+            state: self.stake_pool_config.key(),
+            user: self.user.key(),
+            epoch: 0,
+            deposited: 0,
+            bump: bumps.user_deposit_record,
+        });
+
+        Ok(())
+    }
+}
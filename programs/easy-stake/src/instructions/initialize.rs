@@ -10,7 +10,7 @@ use anchor_lang::{
 use anchor_spl::token::{spl_token, Mint, Token, TokenAccount};
 
 
-use crate::{error::StakingError, require_lte, state::{Fee, LiqPool, StakePoolConfig, StakeSystem, ValidatorSystem}};
+use crate::{error::StakingError, require_lte, state::{Fee, FeeCurve, LiqPool, StakePoolConfig, StakeSystem, ValidatorSystem}};
 
 
 #[event]
@@ -163,8 +163,19 @@ pub struct Initialize<'info> {
     )]
     pub msol_leg: Box<Account<'info, TokenAccount>>,
 
-    /// 操作用的SOL账户
-    pub operational_sol_account: SystemAccount<'info>,
+    /// CHECK: 操作用的 SOL PDA 账户，由程序签名支出
+    #[account(
+        init,
+        payer = payer,
+        space = 0,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            StakePoolConfig::OPERATIONAL_SOL_SEED
+        ],
+        bump,
+        owner = sys_id
+    )]
+    pub operational_sol_account: UncheckedAccount<'info>,
 
     /// CHECK: 用于流动性池中存储 SOL 的 PDA 账户
     #[account(
@@ -243,6 +254,15 @@ pub struct InitializeData {
 
     /// 可以暂停操作的权限地址（用于紧急控制）
     pub pause_authority: Pubkey,
+
+    /// 是否为许可模式，开启后只有白名单中的地址可以存取
+    pub permissioned: bool,
+
+    /// 管理白名单的权限地址
+    pub allowlist_authority: Pubkey,
+
+    /// 每个 epoch 允许从 operational_sol_account 支出的 SOL（质押账户租金与调用奖励）
+    pub operational_budget_per_epoch: u64,
 }
 
 
@@ -291,7 +311,7 @@ impl<'info> Initialize<'info> {
             treasury_cut: data.lp_treasury_cut,
             lp_supply: 0,
             lent_from_sol_leg: 0,
-            liquidity_sol_cap: std::u64::MAX
+            liquidity_sol_cap: u64::MAX
         };
 
         liq_pool.validate()?;
@@ -378,12 +398,37 @@ impl<'info> Initialize<'info> {
             msol_price: StakePoolConfig::PRICE_DENOMINATOR,
             min_deposit: 1,
             min_withdraw: 1,
-            staking_sol_cap: std::u64::MAX,
+            staking_sol_cap: u64::MAX,
             pause_authority: initialize_data.pause_authority,
             paused: false,
             last_stake_move_epoch: 0,
             stake_moved: 0,
             max_stake_moved_per_epoch: Fee::from_basis_points(10000), // 100%
+            epoch_end_deposit_fee: Fee::from_basis_points(0),
+            epoch_end_window_slots: 0,
+            transaction_guard_enabled: false,
+            permissioned: initialize_data.permissioned,
+            allowlist_authority: initialize_data.allowlist_authority,
+            epoch_deposit_limit: u64::MAX,
+            user_epoch_deposit_limit: u64::MAX,
+            deposit_epoch: 0,
+            epoch_deposited: 0,
+            wind_down: false,
+            operational_sol_bump_seed: bumps.operational_sol_account,
+            operational_budget_per_epoch: initialize_data.operational_budget_per_epoch,
+            operational_spent_epoch: 0,
+            operational_spent: 0,
+            crank_bounty: 0,
+            crank_bounty_cap_per_epoch: StakePoolConfig::DEFAULT_CRANK_BOUNTY_CAP_PER_EPOCH,
+            crank_bounty_paid_epoch: 0,
+            crank_bounty_paid: 0,
+            deposit_swap_fee: Fee::from_basis_points(0),
+            lending_buffer: Fee::from_basis_points(Fee::MAX_BASIS_POINTS), // 100%
+            flash_loan_fee: Fee::from_basis_points(0),
+            flash_loan_amount: 0,
+            stakes_updated_epoch: 0,
+            stakes_updated: 0,
+            fee_curve: FeeCurve::linear(),
         });
 
         // 事件记录
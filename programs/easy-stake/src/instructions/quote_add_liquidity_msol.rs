@@ -0,0 +1,61 @@
+//! 添加流动性（mSOL）报价，只读，结果通过 return data 返回
+
+use anchor_lang::prelude::*;
+use anchor_spl::token::{Mint, TokenAccount};
+
+use crate::{
+    error::StakingError,
+    require_lte,
+    state::{LiqPool, StakePoolConfig}
+};
+
+use super::AddLiquidityResult;
+
+
+#[derive(Accounts)]
+pub struct QuoteAddLiquidityMsol<'info> {
+    #[account(
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    #[account(address = stake_pool_config.liq_pool.lp_mint)]
+    pub lp_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            LiqPool::SOL_LEG_SEED
+        ],
+        bump = stake_pool_config.liq_pool.sol_leg_bump_seed
+    )]
+    pub liq_pool_sol_leg_pda: SystemAccount<'info>,
+
+    #[account(address = stake_pool_config.liq_pool.msol_leg)]
+    pub liq_pool_msol_leg: Box<Account<'info, TokenAccount>>,
+}
+
+
+impl<'info> QuoteAddLiquidityMsol<'info> {
+    pub fn process(&self, msol_amount: u64) -> Result<AddLiquidityResult> {
+        require!(!self.stake_pool_config.paused, StakingError::ProgramIsPaused);
+        self.stake_pool_config.check_not_winding_down()?;
+        self.stake_pool_config.check_no_flash_loan()?;
+
+        // 与 add_liquidity_msol 一致，以 LP mint 的实际供应量计算
+        require_lte!(
+            self.lp_mint.supply,
+            self.stake_pool_config.liq_pool.lp_supply,
+            StakingError::UnregisteredLPMinted
+        );
+
+        AddLiquidityResult::calc_msol(
+            &self.stake_pool_config,
+            msol_amount,
+            self.liq_pool_sol_leg_pda.lamports(),
+            self.liq_pool_msol_leg.amount,
+            self.lp_mint.supply
+        )
+    }
+}
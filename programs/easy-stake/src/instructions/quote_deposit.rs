@@ -0,0 +1,58 @@
+//! 质押报价，只读，结果通过 return data 返回
+
+use anchor_lang::prelude::*;
+use anchor_spl::token::TokenAccount;
+
+use crate::{
+    error::StakingError,
+    state::{FeeOverride, LiqPool, StakePoolConfig}
+};
+
+use super::DepositResult;
+
+
+#[derive(Accounts)]
+pub struct QuoteDeposit<'info> {
+    #[account(
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    #[account(
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            LiqPool::SOL_LEG_SEED
+        ],
+        bump = stake_pool_config.liq_pool.sol_leg_bump_seed
+    )]
+    pub liq_pool_sol_leg_pda: SystemAccount<'info>,
+
+    #[account(address = stake_pool_config.liq_pool.msol_leg)]
+    pub liq_pool_msol_leg: Box<Account<'info, TokenAccount>>,
+
+    /// 报价时直接按传入的费率覆盖计算，不校验其归属
+    #[account(
+        constraint = fee_override.state == stake_pool_config.key() @ StakingError::InvalidFeeOverride
+    )]
+    pub fee_override: Option<Box<Account<'info, FeeOverride>>>,
+}
+
+
+impl<'info> QuoteDeposit<'info> {
+    pub fn process(&self, lamports: u64) -> Result<DepositResult> {
+        require!(!self.stake_pool_config.paused, StakingError::ProgramIsPaused);
+        self.stake_pool_config.check_not_winding_down()?;
+        self.stake_pool_config.check_no_flash_loan()?;
+        self.stake_pool_config.check_epoch_deposit_limit(lamports, Clock::get()?.epoch)?;
+
+        DepositResult::calc(
+            &self.stake_pool_config,
+            lamports,
+            self.liq_pool_sol_leg_pda.lamports(),
+            self.liq_pool_msol_leg.amount,
+            self.stake_pool_config.current_epoch_end_deposit_fee()?,
+            self.fee_override.as_deref().map(|fee_override| &**fee_override)
+        )
+    }
+}
@@ -0,0 +1,59 @@
+//! 提取流动性报价，只读，结果通过 return data 返回
+
+use anchor_lang::prelude::*;
+use anchor_spl::token::{Mint, TokenAccount};
+
+use crate::{
+    error::StakingError,
+    state::{LiqPool, StakePoolConfig}
+};
+
+use super::RemoveLiquidityResult;
+
+
+#[derive(Accounts)]
+pub struct QuoteRemoveLiquidity<'info> {
+    #[account(
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    #[account(address = stake_pool_config.liq_pool.lp_mint)]
+    pub lp_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            LiqPool::SOL_LEG_SEED
+        ],
+        bump = stake_pool_config.liq_pool.sol_leg_bump_seed
+    )]
+    pub liq_pool_sol_leg_pda: SystemAccount<'info>,
+
+    #[account(address = stake_pool_config.liq_pool.msol_leg)]
+    pub liq_pool_msol_leg: Box<Account<'info, TokenAccount>>,
+}
+
+
+impl<'info> QuoteRemoveLiquidity<'info> {
+    pub fn process(&self, tokens: u64) -> Result<RemoveLiquidityResult> {
+        require!(!self.stake_pool_config.paused, StakingError::ProgramIsPaused);
+        self.stake_pool_config.check_no_flash_loan()?;
+
+        if self.lp_mint.supply > self.stake_pool_config.liq_pool.lp_supply {
+            return err!(StakingError::UnauthorizedOrExploitedLPMinting);
+        }
+
+        // 与 remove_liquidity 一致，以 LP mint 的实际供应量计算；只修改内存中的副本，不写回账户
+        let mut config = StakePoolConfig::clone(&self.stake_pool_config);
+        config.liq_pool.lp_supply = self.lp_mint.supply;
+
+        RemoveLiquidityResult::calc(
+            &config,
+            tokens,
+            self.liq_pool_sol_leg_pda.lamports(),
+            self.liq_pool_msol_leg.amount
+        )
+    }
+}
@@ -0,0 +1,50 @@
+//! 解质押报价，只读，结果通过 return data 返回
+
+use anchor_lang::prelude::*;
+
+use crate::{
+    error::StakingError,
+    state::{FeeOverride, LiqPool, StakePoolConfig}
+};
+
+use super::UnstakeResult;
+
+
+#[derive(Accounts)]
+pub struct QuoteUnstake<'info> {
+    #[account(
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    #[account(
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            LiqPool::SOL_LEG_SEED
+        ],
+        bump = stake_pool_config.liq_pool.sol_leg_bump_seed
+    )]
+    pub liq_pool_sol_leg_pda: SystemAccount<'info>,
+
+    /// 报价时直接按传入的费率覆盖计算，不校验其归属
+    #[account(
+        constraint = fee_override.state == stake_pool_config.key() @ StakingError::InvalidFeeOverride
+    )]
+    pub fee_override: Option<Box<Account<'info, FeeOverride>>>,
+}
+
+
+impl<'info> QuoteUnstake<'info> {
+    pub fn process(&self, msol_amount: u64) -> Result<UnstakeResult> {
+        require!(!self.stake_pool_config.paused, StakingError::ProgramIsPaused);
+        self.stake_pool_config.check_no_flash_loan()?;
+
+        UnstakeResult::calc(
+            &self.stake_pool_config,
+            msol_amount,
+            self.liq_pool_sol_leg_pda.lamports(),
+            self.fee_override.as_deref().map(|fee_override| &**fee_override)
+        )
+    }
+}
@@ -51,6 +51,11 @@ pub struct ReallocValidatorList<'info> {
 
 impl<'info> ReallocValidatorList<'info> {
     pub fn process(&mut self, capacity: u32) -> Result<()> {
+        require_gte!(
+            ValidatorSystem::MAX_VALIDATORS,
+            capacity,
+            StakingError::TooManyValidators
+        );
         require_gte!(
             capacity,
             self.stake_pool_config.validator_system.validator_list.count,
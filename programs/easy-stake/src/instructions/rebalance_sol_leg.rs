@@ -0,0 +1,139 @@
+//! 将 sol_leg 中闲置的流动性借给 reserve 参与质押，或在 sol_leg 低于目标时收回
+
+use anchor_lang::{prelude::*, system_program::{transfer, Transfer}};
+
+use crate::{
+    error::StakingError,
+    state::{LiqPool, StakePoolConfig}
+};
+
+
+#[event]
+pub struct RebalanceSolLegEvent {
+    pub state: Pubkey,
+    pub sol_leg_balance: u64,
+    pub reserve_balance: u64,
+    pub lp_liquidity_target: u64,
+    pub lent_from_sol_leg: u64,
+    pub lent_amount: u64,
+    pub repaid_amount: u64,
+}
+
+
+#[derive(Accounts)]
+pub struct RebalanceSolLeg<'info> {
+    #[account(
+        mut,
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            LiqPool::SOL_LEG_SEED
+        ],
+        bump = stake_pool_config.liq_pool.sol_leg_bump_seed
+    )]
+    pub liq_pool_sol_leg_pda: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            StakePoolConfig::RESERVE_SEED
+        ],
+        bump = stake_pool_config.reserve_bump_seed
+    )]
+    pub reserve_pda: SystemAccount<'info>,
+
+    pub system_program: Program<'info, System>
+}
+
+
+impl<'info> RebalanceSolLeg<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        require!(!self.stake_pool_config.paused, StakingError::ProgramIsPaused);
+        self.stake_pool_config.check_no_flash_loan()?;
+
+        let sol_leg_balance = self.liq_pool_sol_leg_pda.lamports();
+        let reserve_balance = self.reserve_pda.lamports();
+        let sol_leg_available_balance = sol_leg_balance
+            .saturating_sub(self.stake_pool_config.rent_exempt_for_token_acc);
+
+        let lendable = self.stake_pool_config.lendable_lamports(sol_leg_available_balance);
+        let (lent_amount, repaid_amount) = if self.stake_pool_config.wind_down {
+            // 清退模式下不再借出，尽快归还全部借款，使 LP 可以提取
+            (
+                0,
+                self.stake_pool_config.liq_pool.lent_from_sol_leg
+                    .min(self.stake_pool_config.stake_delta(reserve_balance))
+            )
+        } else if lendable > 0 {
+            (lendable, 0)
+        } else {
+            // 只能用 reserve 中尚未质押出去的 SOL 归还
+            (
+                0,
+                self.stake_pool_config.liq_pool
+                    .repayable_lamports(sol_leg_available_balance)
+                    .min(self.stake_pool_config.stake_delta(reserve_balance))
+            )
+        };
+
+        if lent_amount > 0 {
+            msg!("Lend {} lamports from sol_leg to reserve", lent_amount);
+            transfer(
+                CpiContext::new_with_signer(
+                    self.system_program.to_account_info(),
+                    Transfer {
+                        from: self.liq_pool_sol_leg_pda.to_account_info(),
+                        to: self.reserve_pda.to_account_info()
+                    },
+                    &[&[
+                        self.stake_pool_config.key().as_ref(),
+                        LiqPool::SOL_LEG_SEED,
+                        &[self.stake_pool_config.liq_pool.sol_leg_bump_seed]
+                    ]]
+                ),
+                lent_amount
+            )?;
+            self.stake_pool_config.on_lend_from_sol_leg(lent_amount);
+        } else if repaid_amount > 0 {
+            msg!("Repay {} lamports from reserve to sol_leg", repaid_amount);
+            transfer(
+                CpiContext::new_with_signer(
+                    self.system_program.to_account_info(),
+                    Transfer {
+                        from: self.reserve_pda.to_account_info(),
+                        to: self.liq_pool_sol_leg_pda.to_account_info()
+                    },
+                    &[&[
+                        self.stake_pool_config.key().as_ref(),
+                        StakePoolConfig::RESERVE_SEED,
+                        &[self.stake_pool_config.reserve_bump_seed]
+                    ]]
+                ),
+                repaid_amount
+            )?;
+            self.stake_pool_config.on_repay_to_sol_leg(repaid_amount, true);
+        } else {
+            msg!("sol_leg is balanced");
+            return Ok(()); // Not an error. Don't fail other instructions in tx
+        }
+
+        emit!(RebalanceSolLegEvent {
+            state: self.stake_pool_config.key(),
+            sol_leg_balance,
+            reserve_balance,
+            lp_liquidity_target: self.stake_pool_config.liq_pool.lp_liquidity_target,
+            lent_from_sol_leg: self.stake_pool_config.liq_pool.lent_from_sol_leg,
+            lent_amount,
+            repaid_amount,
+        });
+
+        Ok(())
+    }
+}
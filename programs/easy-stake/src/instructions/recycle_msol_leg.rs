@@ -0,0 +1,198 @@
+//! 回收流动性池 mSOL leg 中积累的 mSOL，使用 reserve 中的 SOL 补充 sol_leg
+
+use anchor_lang::{prelude::*, system_program::{transfer, Transfer}};
+use anchor_spl::token::{burn, Burn, Mint, Token, TokenAccount};
+
+use crate::{
+    calc::Rounding,
+    error::StakingError,
+    state::{LiqPool, MsolPriceOracle, StakePoolConfig}
+};
+
+
+#[event]
+pub struct RecycleMsolLegEvent {
+    pub state: Pubkey,
+    pub sol_leg_balance: u64,
+    pub msol_leg_balance: u64,
+    pub reserve_balance: u64,
+    pub lp_liquidity_target: u64,
+    pub msol_burned: u64,
+    pub sol_recycled: u64,
+    // MSOL price used
+    pub total_virtual_staked_lamports: u64,
+    pub msol_supply: u64,
+}
+
+
+#[derive(Accounts)]
+pub struct RecycleMsolLeg<'info> {
+    #[account(
+        mut,
+        has_one = msol_mint,
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    #[account(mut)]
+    pub msol_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        address = stake_pool_config.liq_pool.msol_leg
+    )]
+    pub liq_pool_msol_leg: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: PDA
+    #[account(
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            LiqPool::MSOL_LEG_AUTHORITY_SEED
+        ],
+        bump = stake_pool_config.liq_pool.msol_leg_authority_bump_seed
+    )]
+    pub liq_pool_msol_leg_authority: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            LiqPool::SOL_LEG_SEED
+        ],
+        bump = stake_pool_config.liq_pool.sol_leg_bump_seed
+    )]
+    pub liq_pool_sol_leg_pda: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            StakePoolConfig::RESERVE_SEED
+        ],
+        bump = stake_pool_config.reserve_bump_seed
+    )]
+    pub reserve_pda: SystemAccount<'info>,
+
+    /// 传入时在汇率变化后同步刷新 mSOL 价格预言机
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            MsolPriceOracle::SEED
+        ],
+        bump = price_oracle.bump
+    )]
+    pub price_oracle: Option<Box<Account<'info, MsolPriceOracle>>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>
+}
+
+
+impl<'info> RecycleMsolLeg<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        require!(!self.stake_pool_config.paused, StakingError::ProgramIsPaused);
+        self.stake_pool_config.check_no_flash_loan()?;
+
+        let sol_leg_balance = self.liq_pool_sol_leg_pda.lamports();
+        let msol_leg_balance = self.liq_pool_msol_leg.amount;
+        let reserve_balance = self.reserve_pda.lamports();
+        let lp_liquidity_target = self.stake_pool_config.liq_pool.lp_liquidity_target;
+        let total_virtual_staked_lamports = self.stake_pool_config.total_staked_lamports()?;
+        let msol_supply = self.stake_pool_config.msol_supply;
+
+        // sol_leg 距离目标流动性的缺口
+        let sol_leg_shortfall = lp_liquidity_target.saturating_sub(
+            sol_leg_balance.saturating_sub(self.stake_pool_config.rent_exempt_for_token_acc)
+        );
+        // reserve 中尚未质押出去、可以直接动用的 SOL
+        let reserve_available = self.stake_pool_config
+            .stake_delta(reserve_balance)
+            .min(self.stake_pool_config.available_reserve_balance);
+        let msol_leg_value = self.stake_pool_config.msol_to_sol(
+            msol_leg_balance,
+            Rounding::RoundDown
+        )?;
+
+        let max_recycle_lamports = sol_leg_shortfall
+            .min(reserve_available)
+            .min(msol_leg_value);
+
+        // 两次向下取整，保证销毁的 mSOL 价值不低于从 reserve 取出的 SOL
+        let msol_burned = self.stake_pool_config.calc_msol_from_lamports(
+            max_recycle_lamports,
+            Rounding::RoundDown
+        )?.min(msol_leg_balance);
+        let sol_recycled = self.stake_pool_config.msol_to_sol(
+            msol_burned,
+            Rounding::RoundDown
+        )?;
+
+        if msol_burned == 0 || sol_recycled == 0 {
+            msg!(
+                "Nothing to recycle. sol_leg shortfall:{}, reserve available:{}, mSOL leg value:{}",
+                sol_leg_shortfall,
+                reserve_available,
+                msol_leg_value
+            );
+            return Ok(()); // Not an error. Don't fail other instructions in tx
+        }
+
+        msg!("Recycle {} mSOL into {} lamports", msol_burned, sol_recycled);
+
+        burn(
+            CpiContext::new_with_signer(
+                self.token_program.to_account_info(),
+                Burn {
+                    mint: self.msol_mint.to_account_info(),
+                    from: self.liq_pool_msol_leg.to_account_info(),
+                    authority: self.liq_pool_msol_leg_authority.to_account_info()
+                },
+                &[&[
+                    self.stake_pool_config.key().as_ref(),
+                    LiqPool::MSOL_LEG_AUTHORITY_SEED,
+                    &[self.stake_pool_config.liq_pool.msol_leg_authority_bump_seed]
+                ]]
+            ),
+            msol_burned
+        )?;
+        self.stake_pool_config.on_msol_burn(msol_burned);
+
+        transfer(
+            CpiContext::new_with_signer(
+                self.system_program.to_account_info(),
+                Transfer {
+                    from: self.reserve_pda.to_account_info(),
+                    to: self.liq_pool_sol_leg_pda.to_account_info()
+                },
+                &[&[
+                    self.stake_pool_config.key().as_ref(),
+                    StakePoolConfig::RESERVE_SEED,
+                    &[self.stake_pool_config.reserve_bump_seed]
+                ]]
+            ),
+            sol_recycled
+        )?;
+        self.stake_pool_config.on_transfer_from_reserve(sol_recycled);
+
+        self.stake_pool_config.refresh_price_oracle(
+            self.price_oracle.as_deref_mut().map(|price_oracle| &mut **price_oracle)
+        )?;
+
+        emit!(RecycleMsolLegEvent {
+            state: self.stake_pool_config.key(),
+            sol_leg_balance,
+            msol_leg_balance,
+            reserve_balance,
+            lp_liquidity_target,
+            msol_burned,
+            sol_recycled,
+            // msol price components
+            total_virtual_staked_lamports,
+            msol_supply,
+        });
+
+        Ok(())
+    }
+}
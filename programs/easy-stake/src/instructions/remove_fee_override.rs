@@ -0,0 +1,59 @@
+//! 管理员删除费率覆盖，租金退回指定账户
+
+use anchor_lang::prelude::*;
+
+use crate::{
+    error::StakingError,
+    state::{FeeOverride, StakePoolConfig}
+};
+
+
+#[event]
+pub struct RemoveFeeOverrideEvent {
+    pub state: Pubkey,
+    pub fee_override: Pubkey,
+    pub key: Pubkey,
+}
+
+
+#[derive(Accounts)]
+pub struct RemoveFeeOverride<'info> {
+    pub admin_authority: Signer<'info>,
+
+    #[account(
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump,
+        has_one = admin_authority @ StakingError::InvalidAdminAuthority
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    #[account(
+        mut,
+        close = rent_collector,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            FeeOverride::SEED,
+            fee_override.key.as_ref()
+        ],
+        bump = fee_override.bump
+    )]
+    pub fee_override: Box<Account<'info, FeeOverride>>,
+
+    #[account(mut)]
+    pub rent_collector: SystemAccount<'info>,
+}
+
+
+impl<'info> RemoveFeeOverride<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        msg!("Remove fee override for {}", self.fee_override.key);
+
+        emit!(RemoveFeeOverrideEvent {
+            state: self.stake_pool_config.key(),
+            fee_override: self.fee_override.key(),
+            key: self.fee_override.key,
+        });
+
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+//! 白名单管理员将用户移出白名单，租金退回指定账户
+
+use anchor_lang::prelude::*;
+
+use crate::{
+    error::StakingError,
+    state::{AllowlistEntry, StakePoolConfig}
+};
+
+
+#[event]
+pub struct RemoveFromAllowlistEvent {
+    pub state: Pubkey,
+    pub user: Pubkey,
+}
+
+
+#[derive(Accounts)]
+pub struct RemoveFromAllowlist<'info> {
+    pub allowlist_authority: Signer<'info>,
+
+    #[account(
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump,
+        has_one = allowlist_authority @ StakingError::InvalidAllowlistAuthority
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    #[account(
+        mut,
+        close = rent_collector,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            AllowlistEntry::SEED,
+            allowlist_entry.user.as_ref()
+        ],
+        bump = allowlist_entry.bump
+    )]
+    pub allowlist_entry: Box<Account<'info, AllowlistEntry>>,
+
+    #[account(mut)]
+    pub rent_collector: SystemAccount<'info>,
+}
+
+
+impl<'info> RemoveFromAllowlist<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        msg!("Remove {} from allowlist", self.allowlist_entry.user);
+
+        emit!(RemoveFromAllowlistEvent {
+            state: self.stake_pool_config.key(),
+            user: self.allowlist_entry.user,
+        });
+
+        Ok(())
+    }
+}
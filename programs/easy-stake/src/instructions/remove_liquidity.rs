@@ -1,15 +1,20 @@
 //! 提取流动性
 
-use anchor_lang::{prelude::*, system_program::{transfer, Transfer}};
+use anchor_lang::{
+    prelude::*,
+    solana_program::sysvar::instructions::ID as INSTRUCTIONS_ID,
+    system_program::{transfer, Transfer}
+};
 use anchor_spl::token::{
     burn, transfer as transfer_token, Burn, Mint, Token, TokenAccount, Transfer as TransferToken
 };
 
 use crate::{
-    calc::proportional, 
+    calc::Rounding, 
+    checks::{check_allowlist, check_transaction_guard, PoolDirection},
     error::StakingError, 
     require_lte, 
-    state::{LiqPool, StakePoolConfig}
+    state::{AllowlistEntry, LiqPool, StakePoolConfig}
 };
 
 
@@ -28,6 +33,49 @@ pub struct RemoveLiquidityEvent {
 }
 
 
+/// 通过 return data 返回给 CPI 调用方的结果，quote_remove_liquidity 返回同一结构
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RemoveLiquidityResult {
+    pub sol_out: u64,
+    pub msol_out: u64,
+}
+
+
+impl RemoveLiquidityResult {
+    /// 只依据池子当前状态计算销毁 LP 取回的 SOL 与 mSOL，不修改任何状态。
+    /// 调用前 liq_pool.lp_supply 需已与 LP mint 同步。remove_liquidity 与 quote_remove_liquidity 共用
+    pub fn calc(
+        config: &StakePoolConfig,
+        tokens: u64,
+        sol_leg_balance: u64,
+        msol_leg_balance: u64
+    ) -> Result<Self> {
+        // 借给 reserve 的 SOL 也属于 LP，按份额计入，但只能从 sol_leg 现有余额中支付
+        let sol_leg_available_balance = sol_leg_balance - config.rent_exempt_for_token_acc;
+        let (sol_out, msol_out) = config.liq_pool.liquidity_out(
+            tokens, 
+            sol_leg_available_balance + config.liq_pool.lent_from_sol_leg, 
+            msol_leg_balance,
+            config.liq_pool_value(sol_leg_balance, msol_leg_balance)?
+        )?;
+
+        if sol_out > sol_leg_available_balance {
+            return err!(StakingError::InsufficientLiquidity);
+        }
+
+        require_gte!(
+            sol_out + config.msol_to_sol(msol_out, Rounding::RoundDown)?,
+            config.min_withdraw,
+            StakingError::WithdrawAmountIsTooLow
+        );
+
+        msg!("SOL out amount:{}, mSOL out amount:{}", sol_out, msol_out);
+
+        Ok(Self { sol_out, msol_out })
+    }
+}
+
+
 #[derive(Accounts)]
 pub struct RemoveLiquidity<'info> {
     pub burn_from_authority: Signer<'info>,
@@ -88,14 +136,32 @@ pub struct RemoveLiquidity<'info> {
     )]
     pub liq_pool_msol_leg_authority: UncheckedAccount<'info>,
 
+    /// 许可模式下必须传入调用者的白名单账户
+    pub allowlist_entry: Option<Box<Account<'info, AllowlistEntry>>>,
+
+    /// CHECK: 指令 sysvar，仅在开启交易检查时需要
+    #[account(address = INSTRUCTIONS_ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+
     pub system_program: Program<'info, System>,
     pub token_program: Program<'info, Token>
 }
 
 
 impl<'info> RemoveLiquidity<'info> {
-    pub fn process(&mut self, tokens: u64) -> Result<()> {
+    pub fn process(&mut self, tokens: u64) -> Result<RemoveLiquidityResult> {
         require!(!self.stake_pool_config.paused, StakingError::ProgramIsPaused);
+        self.stake_pool_config.check_no_flash_loan()?;
+        check_transaction_guard(
+            &self.stake_pool_config,
+            self.instructions.as_ref(),
+            PoolDirection::Out
+        )?;
+        check_allowlist(
+            &self.stake_pool_config,
+            self.allowlist_entry.as_deref().map(|entry| &**entry),
+            &self.burn_from_authority.key()
+        )?;
 
         require_lte!(
             tokens,
@@ -118,30 +184,14 @@ impl<'info> RemoveLiquidity<'info> {
         }
         msg!("mSOL-SOL-LP total supply {}", lp_mint_supply);
 
-        let sol_out_amount = proportional(
-            tokens, 
-            sol_leg_balance - self.stake_pool_config.rent_exempt_for_token_acc, 
-            self.stake_pool_config.liq_pool.lp_supply
-        )?;
-        let msol_out_amount = proportional(
-            tokens, 
-            msol_leg_balance, 
-            self.stake_pool_config.liq_pool.lp_supply
+        let result = RemoveLiquidityResult::calc(
+            &self.stake_pool_config,
+            tokens,
+            sol_leg_balance,
+            msol_leg_balance
         )?;
 
-        require_gte!(
-            sol_out_amount + self.stake_pool_config.msol_to_sol(msol_out_amount)?,
-            self.stake_pool_config.min_withdraw,
-            StakingError::WithdrawAmountIsTooLow
-        );
-
-        msg!(
-            "SOL out amount:{}, mSOL out amount:{}",
-            sol_out_amount,
-            msol_out_amount
-        );
-
-        if sol_out_amount > 0 {
+        if result.sol_out > 0 {
             transfer(
                 CpiContext::new_with_signer(
                     self.system_program.to_account_info(), 
@@ -155,11 +205,11 @@ impl<'info> RemoveLiquidity<'info> {
                         &[self.stake_pool_config.liq_pool.sol_leg_bump_seed]
                     ]]
                 ), 
-                sol_out_amount
+                result.sol_out
             )?;
         }
 
-        if msol_out_amount > 0 {
+        if result.msol_out > 0 {
             transfer_token(
                 CpiContext::new_with_signer(
                     self.token_program.to_account_info(), 
@@ -174,7 +224,7 @@ impl<'info> RemoveLiquidity<'info> {
                         &[self.stake_pool_config.liq_pool.msol_leg_authority_bump_seed]
                     ]]
                 ), 
-                msol_out_amount
+                result.msol_out
             )?;
         }
 
@@ -202,10 +252,10 @@ impl<'info> RemoveLiquidity<'info> {
             user_msol_balance,
             lp_mint_supply,
             lp_burned: tokens,
-            sol_out_amount,
-            msol_out_amount,
+            sol_out_amount: result.sol_out,
+            msol_out_amount: result.msol_out,
         });
 
-        Ok(())
+        Ok(result)
     }
 }
@@ -0,0 +1,321 @@
+//! 提取流动性（只提取 SOL）
+
+use anchor_lang::{
+    prelude::*,
+    solana_program::sysvar::instructions::ID as INSTRUCTIONS_ID,
+    system_program::{transfer, Transfer}
+};
+use anchor_spl::token::{
+    burn, transfer as transfer_token, Burn, Mint, Token, TokenAccount, Transfer as TransferToken
+};
+
+use crate::{
+    calc::Rounding,
+    checks::{check_allowlist, check_transaction_guard, PoolDirection},
+    error::StakingError,
+    require_lte,
+    state::{AllowlistEntry, Fee, LiqPool, StakePoolConfig}
+};
+
+
+#[event]
+pub struct RemoveLiquiditySolEvent {
+    pub state: Pubkey,
+    pub sol_leg_balance: u64,
+    pub msol_leg_balance: u64,
+    pub user_lp_balance: u64,
+    pub user_sol_balance: u64,
+    pub lp_mint_supply: u64,
+    pub lp_burned: u64,
+    pub sol_leg_share: u64,
+    pub msol_share: u64,
+    pub msol_fee: u64,
+    pub treasury_msol_cut: u64,
+    pub sol_out_amount: u64,
+    pub liquid_unstake_fee: Fee,
+}
+
+
+/// 通过 return data 返回给 CPI 调用方的结果
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RemoveLiquiditySolResult {
+    pub sol_out: u64,
+    /// mSOL 份额在池内解质押收取的 mSOL 手续费，包含国库分成
+    pub fee: u64,
+    pub treasury_msol_cut: u64,
+}
+
+
+/// 只提取 SOL 的完整计算过程，事件需要的中间结果与 return data 一起返回
+pub struct RemoveLiquiditySolQuote {
+    pub sol_leg_share: u64,
+    pub msol_share: u64,
+    pub liquid_unstake_fee: Fee,
+    pub result: RemoveLiquiditySolResult,
+}
+
+
+impl RemoveLiquiditySolQuote {
+    /// 只依据池子当前状态计算销毁 LP 只取回 SOL 的结果，不修改任何状态。
+    /// 调用前 liq_pool.lp_supply 需已与 LP mint 同步。remove_liquidity_sol 与 quote_remove_liquidity_sol 共用
+    pub fn calc(
+        config: &StakePoolConfig,
+        tokens: u64,
+        sol_leg_balance: u64,
+        msol_leg_balance: u64
+    ) -> Result<Self> {
+        let sol_leg_available_balance = sol_leg_balance
+            .saturating_sub(config.rent_exempt_for_token_acc);
+        // 借给 reserve 的 SOL 也属于 LP，按份额计入，但只能从 sol_leg 现有余额中支付
+        let (sol_leg_share, msol_share) = config.liq_pool.liquidity_out(
+            tokens,
+            sol_leg_available_balance + config.liq_pool.lent_from_sol_leg,
+            msol_leg_balance,
+            config.liq_pool_value(sol_leg_balance, msol_leg_balance)?
+        )?;
+
+        // mSOL 份额在池内按流动性解质押换成 SOL：
+        // 先取走 SOL 份额，再按剩余流动性确定费率，与 unstake 使用同一条手续费曲线
+        let msol_share_value = config.msol_to_sol(msol_share, Rounding::RoundUp)?;
+        let liquid_unstake_fee = config.liquid_unstake_fee(
+            sol_leg_available_balance.saturating_sub(sol_leg_share),
+            msol_share_value
+        )?;
+        let msol_fee = liquid_unstake_fee.apply(msol_share, Rounding::RoundUp);
+        // 国库分成向下取整，其余手续费随 mSOL 份额留在池中归 LP
+        let treasury_msol_cut = config.liq_pool.treasury_cut.apply(
+            msol_fee,
+            Rounding::RoundDown
+        );
+        msg!("msol_fee {} treasury_msol_cut {}", msol_fee, treasury_msol_cut);
+
+        let sol_out = sol_leg_share + config.msol_to_sol(
+            msol_share - msol_fee,
+            Rounding::RoundDown
+        )?;
+
+        require_gte!(
+            sol_out,
+            config.min_withdraw,
+            StakingError::WithdrawAmountIsTooLow
+        );
+
+        if sol_out > sol_leg_available_balance {
+            return err!(StakingError::InsufficientLiquidity);
+        }
+
+        Ok(Self {
+            sol_leg_share,
+            msol_share,
+            liquid_unstake_fee,
+            result: RemoveLiquiditySolResult {
+                sol_out,
+                fee: msol_fee,
+                treasury_msol_cut,
+            }
+        })
+    }
+}
+
+
+#[derive(Accounts)]
+pub struct RemoveLiquiditySol<'info> {
+    pub burn_from_authority: Signer<'info>,
+
+    #[account(
+        mut,
+        token::mint = lp_mint,
+        token::authority = burn_from_authority
+    )]
+    pub burn_from: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    #[account(
+        mut,
+        address = stake_pool_config.liq_pool.lp_mint
+    )]
+    pub lp_mint: Box<Account<'info, Mint>>,
+
+    #[account(mut)]
+    pub transfer_sol_to: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            LiqPool::SOL_LEG_SEED
+        ],
+        bump = stake_pool_config.liq_pool.sol_leg_bump_seed
+    )]
+    pub liq_pool_sol_leg_pda: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        address = stake_pool_config.liq_pool.msol_leg,
+        token::authority = liq_pool_msol_leg_authority
+    )]
+    pub liq_pool_msol_leg: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: PDA
+    #[account(
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            LiqPool::MSOL_LEG_AUTHORITY_SEED
+        ],
+        bump = stake_pool_config.liq_pool.msol_leg_authority_bump_seed
+    )]
+    pub liq_pool_msol_leg_authority: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        address = stake_pool_config.treasury_msol_account
+    )]
+    pub treasury_msol_account: Box<Account<'info, TokenAccount>>,
+
+    /// 许可模式下必须传入调用者的白名单账户
+    pub allowlist_entry: Option<Box<Account<'info, AllowlistEntry>>>,
+
+    /// CHECK: 指令 sysvar，仅在开启交易检查时需要
+    #[account(address = INSTRUCTIONS_ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>
+}
+
+
+impl<'info> RemoveLiquiditySol<'info> {
+    pub fn process(&mut self, tokens: u64, min_sol_out: u64) -> Result<RemoveLiquiditySolResult> {
+        require!(!self.stake_pool_config.paused, StakingError::ProgramIsPaused);
+        self.stake_pool_config.check_no_flash_loan()?;
+        check_transaction_guard(
+            &self.stake_pool_config,
+            self.instructions.as_ref(),
+            PoolDirection::Out
+        )?;
+        check_allowlist(
+            &self.stake_pool_config,
+            self.allowlist_entry.as_deref().map(|entry| &**entry),
+            &self.burn_from_authority.key()
+        )?;
+
+        require_lte!(
+            tokens,
+            self.burn_from.amount,
+            StakingError::NotEnoughUserFunds
+        );
+
+        let user_lp_balance = self.burn_from.amount;
+        let user_sol_balance = self.transfer_sol_to.lamports();
+        let sol_leg_balance = self.liq_pool_sol_leg_pda.lamports();
+        let msol_leg_balance = self.liq_pool_msol_leg.amount;
+
+        let lp_mint_supply = self.lp_mint.supply;
+        if lp_mint_supply > self.stake_pool_config.liq_pool.lp_supply {
+            msg!("有人未经我们的许可或发现漏洞而铸造了 LP 代币");
+            return Err(StakingError::UnauthorizedOrExploitedLPMinting.into());
+        } else {
+            self.stake_pool_config.liq_pool.lp_supply = lp_mint_supply;
+        }
+        msg!("mSOL-SOL-LP total supply {}", lp_mint_supply);
+
+        let RemoveLiquiditySolQuote {
+            sol_leg_share,
+            msol_share,
+            liquid_unstake_fee,
+            result
+        } = RemoveLiquiditySolQuote::calc(
+            &self.stake_pool_config,
+            tokens,
+            sol_leg_balance,
+            msol_leg_balance
+        )?;
+        let RemoveLiquiditySolResult {
+            sol_out: sol_out_amount,
+            fee: msol_fee,
+            treasury_msol_cut
+        } = result;
+
+        require_gte!(
+            sol_out_amount,
+            min_sol_out,
+            StakingError::SolOutBelowMinimum
+        );
+
+        msg!("SOL out amount:{}", sol_out_amount);
+
+        transfer(
+            CpiContext::new_with_signer(
+                self.system_program.to_account_info(),
+                Transfer {
+                    from: self.liq_pool_sol_leg_pda.to_account_info(),
+                    to: self.transfer_sol_to.to_account_info()
+                },
+                &[&[
+                    self.stake_pool_config.key().as_ref(),
+                    LiqPool::SOL_LEG_SEED,
+                    &[self.stake_pool_config.liq_pool.sol_leg_bump_seed]
+                ]]
+            ),
+            sol_out_amount
+        )?;
+
+        if treasury_msol_cut > 0 {
+            transfer_token(
+                CpiContext::new_with_signer(
+                    self.token_program.to_account_info(),
+                    TransferToken {
+                        from: self.liq_pool_msol_leg.to_account_info(),
+                        to: self.treasury_msol_account.to_account_info(),
+                        authority: self.liq_pool_msol_leg_authority.to_account_info()
+                    },
+                    &[&[
+                        self.stake_pool_config.key().as_ref(),
+                        LiqPool::MSOL_LEG_AUTHORITY_SEED,
+                        &[self.stake_pool_config.liq_pool.msol_leg_authority_bump_seed]
+                    ]]
+                ),
+                treasury_msol_cut
+            )?;
+        }
+
+        // 销毁 lp token
+        burn(
+            CpiContext::new(
+                self.token_program.to_account_info(),
+                Burn {
+                    mint: self.lp_mint.to_account_info(),
+                    from: self.burn_from.to_account_info(),
+                    authority: self.burn_from_authority.to_account_info()
+                }
+            ),
+            tokens
+        )?;
+
+        self.stake_pool_config.liq_pool.on_lp_burn(tokens);
+
+        emit!(RemoveLiquiditySolEvent {
+            state: self.stake_pool_config.key(),
+            sol_leg_balance,
+            msol_leg_balance,
+            user_lp_balance,
+            user_sol_balance,
+            lp_mint_supply,
+            lp_burned: tokens,
+            sol_leg_share,
+            msol_share,
+            msol_fee,
+            treasury_msol_cut,
+            sol_out_amount,
+            liquid_unstake_fee,
+        });
+
+        Ok(result)
+    }
+}
@@ -0,0 +1,81 @@
+//! 管理员为用户或程序设置费率覆盖
+
+use anchor_lang::{prelude::*, system_program::ID as sys_id};
+
+use crate::{
+    error::StakingError,
+    state::{FeeAdjustment, FeeOverride, StakePoolConfig}
+};
+
+
+#[event]
+pub struct SetFeeOverrideEvent {
+    pub state: Pubkey,
+    pub fee_override: Pubkey,
+    pub key: Pubkey,
+    pub adjustment: FeeAdjustment,
+}
+
+
+#[derive(Accounts)]
+#[instruction(key: Pubkey)]
+pub struct SetFeeOverride<'info> {
+    #[account(
+        mut,
+        owner = sys_id
+    )]
+    pub rent_payer: Signer<'info>,
+
+    pub admin_authority: Signer<'info>,
+
+    #[account(
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump,
+        has_one = admin_authority @ StakingError::InvalidAdminAuthority
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    #[account(
+        init_if_needed,
+        payer = rent_payer,
+        space = FeeOverride::LEN,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            FeeOverride::SEED,
+            key.as_ref()
+        ],
+        bump
+    )]
+    pub fee_override: Box<Account<'info, FeeOverride>>,
+
+    pub system_program: Program<'info, System>
+}
+
+
+impl<'info> SetFeeOverride<'info> {
+    pub fn process(
+        &mut self,
+        key: Pubkey,
+        adjustment: FeeAdjustment,
+        bumps: SetFeeOverrideBumps
+    ) -> Result<()> {
+        FeeOverride::validate(&adjustment)?;
+        msg!("Set fee override for {}: {:?}", key, adjustment);
+
+        self.fee_override.set_inner(FeeOverride {
+            state: self.stake_pool_config.key(),
+            key,
+            adjustment,
+            bump: bumps.fee_override,
+        });
+
+        emit!(SetFeeOverrideEvent {
+            state: self.stake_pool_config.key(),
+            fee_override: self.fee_override.key(),
+            key,
+            adjustment,
+        });
+
+        Ok(())
+    }
+}
@@ -13,18 +13,26 @@ use anchor_lang::{
         }, 
         sysvar::stake_history::ID as STAKE_HISTORY_ID
     }, 
-    system_program::{transfer, Transfer, ID as sys_id}
+    system_program::{
+        allocate,
+        assign,
+        transfer,
+        Allocate,
+        Assign,
+        Transfer,
+        ID as sys_id
+    }
 };
 
 
-use anchor_spl::stake::{withdraw, Stake, StakeAccount, Withdraw};
+use anchor_spl::stake::Stake;
 
 use crate::{
     ID,
     error::StakingError,
     state::{
         stake_system::{StakeList, StakeSystem}, 
-        validator_system::ValidatorList, 
+        validator_system::{ValidatorList, ValidatorRecord, ValidatorStakeDeviation}, 
         StakePoolConfig
     }
 };
@@ -45,11 +53,27 @@ pub struct StakeReserveEvent {
     pub validator_active_balance: u64,
     pub stake_delta: u64,
     pub amount: u64,
+    /// 质押账户租金中由 operational_sol_account 支付的部分
+    pub rent_from_operational: u64,
+    /// 支付给调用者的奖励，超出本 epoch 预算的部分不支付
+    pub crank_bounty: u64,
+}
+
+
+/// 质押后偏差绝对值最大的验证者（最多 ValidatorSystem::MAX_REPORTED_DEVIATIONS 个）相对目标的偏差，
+/// 目标按 total_stake_target 与评分计算
+#[event]
+pub struct StakeDistributionEvent {
+    pub state: Pubkey,
+    pub epoch: u64,
+    pub total_stake_target: u64,
+    pub deviations: Vec<ValidatorStakeDeviation>,
 }
 
 
 #[derive(Accounts)]
 pub struct StakeReserve<'info> {
+    /// 调用者：operational 预算不足时支付质押账户租金，并接收调用奖励
     #[account(
         mut,
         owner = sys_id
@@ -90,12 +114,27 @@ pub struct StakeReserve<'info> {
     pub reserve_pda: SystemAccount<'info>,
 
     #[account(
-        init,
-        payer = rent_payer,
-        space = std::mem::size_of::<StakeStateV2>(),
-        owner = STAKE_ID
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            StakePoolConfig::OPERATIONAL_SOL_SEED
+        ],
+        bump = stake_pool_config.operational_sol_bump_seed
+    )]
+    pub operational_sol_account: SystemAccount<'info>,
+
+    /// CHECK: PDA，只有实际质押时才创建，未质押时不占用租金
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            StakeSystem::STAKE_ACCOUNT_SEED,
+            validator_vote.key().as_ref(),
+            &stake_pool_config.stake_system.stake_account_counter.to_le_bytes()
+        ],
+        bump
     )]
-    pub stake_account: Account<'info, StakeAccount>,
+    pub stake_account: UncheckedAccount<'info>,
 
     /// CHECK: PDA
     #[account(
@@ -124,32 +163,15 @@ pub struct StakeReserve<'info> {
 
 
 impl<'info> StakeReserve<'info> {
-    pub fn process(&mut self, validator_index: u32) -> Result<()> {
+    pub fn process(&mut self, validator_index: u32, bumps: StakeReserveBumps) -> Result<()> {
         require!(!self.stake_pool_config.paused, StakingError::ProgramIsPaused);
+        self.stake_pool_config.check_not_winding_down()?;
 
         // 打印当前剩余 CU 数量
         sol_log_compute_units();
 
         let total_active_balance = self.stake_pool_config.validator_system.total_active_balance;
 
-        let staker = Pubkey::create_program_address(
-            &[
-                self.stake_pool_config.key().as_ref(),
-                StakeSystem::STAKE_DEPOSIT_SEED,
-                &[self.stake_pool_config.stake_system.stake_deposit_bump_seed]
-            ], 
-            &ID
-        ).unwrap();
-
-        let withdrawer = Pubkey::create_program_address(
-            &[
-                self.stake_pool_config.key().as_ref(),
-                StakeSystem::STAKE_WITHDRAW_SEED,
-                &[self.stake_pool_config.stake_system.stake_withdraw_bump_seed]
-            ], 
-            &ID
-        ).unwrap();
-
         let reserve_balance = self.reserve_pda.lamports();
         let stake_delta = self.stake_pool_config.stake_delta(reserve_balance);
         if stake_delta == 0 {
@@ -162,125 +184,66 @@ impl<'info> StakeReserve<'info> {
             self.validator_vote.key()
         ).map_err(|e| e.with_account_name("validator_vote"))?;
 
-        let validator_active_balance = validator.active_balance;
-        if validator.last_stake_delta_epoch == self.clock.epoch {
-            if self.stake_pool_config.stake_system.extra_stake_delta_runs == 0 {
+        // 目标按池子总质押计算，使长期分配符合评分比例而不取决于 crank 顺序
+        let total_stake_target = total_active_balance + stake_delta;
+        let most_under_allocated = self.stake_pool_config.validator_system.most_under_allocated(
+            &self.validator_list.to_account_info().data.borrow(),
+            total_stake_target,
+            self.clock.epoch,
+            self.stake_pool_config.stake_system.extra_stake_delta_runs > 0
+        )?;
+        match most_under_allocated {
+            Some((index, _)) if index == validator_index => {}
+            Some((index, shortfall)) => {
                 msg!(
-                    "Double delta stake command for validator {} in epoch {}",
-                    validator.validator_account,
-                    self.clock.epoch
+                    "Validator {} has the largest shortfall {}, stake into it first",
+                    index,
+                    shortfall
                 );
-                self.return_unused_stake_account_rent()?;
+                return err!(StakingError::ValidatorNotMostUnderAllocated);
+            }
+            None => {
+                msg!("No validator is below its stake target");
                 return Ok(());
-            } else {
-                self.stake_pool_config.stake_system.extra_stake_delta_runs -= 1;
             }
         }
 
-        let last_slot = self.epoch_schedule.get_last_slot_in_epoch(self.clock.epoch);
-
-        require_gte!(
-            self.clock.slot,
-            last_slot.saturating_sub(
-                self.stake_pool_config.stake_system.slots_for_stake_delta
-            ),
-            StakingError::TooEarlyForStakeDelta
-        );
-
-        let validator_stake_target = self.stake_pool_config.validator_system
-            .validator_stake_target(
-                &validator, 
-                stake_delta
-            )?;
-
-        if validator_active_balance >= validator_stake_target {
-            msg!(
-                "Validator {} has already reached stake target {}. Please stake into another validator",
-                validator.validator_account,
-                validator_stake_target
-            );
-            self.return_unused_stake_account_rent()?;
+        let validator_active_balance = validator.active_balance;
+        if !check_stake_delta_run(&mut self.stake_pool_config, &validator, self.clock.epoch) {
             return Ok(());
         }
 
-        let stake_target = validator_stake_target
-            .saturating_sub(validator_active_balance)
-            .min(stake_delta);
+        check_stake_delta_window(&self.stake_pool_config, &self.clock, &self.epoch_schedule)?;
 
-        let stake_target = if stake_delta - stake_target 
-            < self.stake_pool_config.stake_system.min_stake 
-        {
+        let Some((stake_target, validator_stake_target)) = validator_stake_amount(
+            &self.stake_pool_config,
+            &validator,
+            total_stake_target,
             stake_delta
-        } else {
-            stake_target
+        )? else {
+            return Ok(()); // Not an error. Don't fail other instructions in tx
         };
 
-        if stake_target < self.stake_pool_config.stake_system.min_stake {
-            msg!(
-                "Resulting stake {} is lower than min stake allowed {}",
-                stake_target,
-                self.stake_pool_config.stake_system.min_stake
-            );
-            self.return_unused_stake_account_rent()?;
-            return Ok(()); // Not an error. Don't fail other instructions in tx
-        }
-        
-        sol_log_compute_units();
-        msg!("Transfer to stake account");
-        transfer(
-            CpiContext::new_with_signer(
-                self.system_program.to_account_info(), 
-                Transfer {
-                    from: self.reserve_pda.to_account_info(),
-                    to: self.stake_account.to_account_info()
-                }, 
-                &[&[
-                    self.stake_pool_config.key().as_ref(),
-                    StakePoolConfig::RESERVE_SEED,
-                    &[self.stake_pool_config.reserve_bump_seed]
-                ]]
-            ), 
-            stake_target
-        )?;
-        self.stake_pool_config.on_transfer_to_reserve(stake_target);
+        let cpi = self.stake_cpi();
+        let stake_account = self.stake_account.to_account_info();
+        let validator_vote = self.validator_vote.to_account_info();
+        let counter = self.stake_pool_config.stake_system.stake_account_counter;
 
         sol_log_compute_units();
-        msg!("Initialize stake");
-        invoke(
-            &stake::instruction::initialize(
-                &self.stake_account.key(), 
-                &Authorized {staker, withdrawer}, 
-                &Lockup::default(),
-            ), 
-            &[
-                self.stake_program.to_account_info(),
-                self.stake_account.to_account_info(),
-                self.rent.to_account_info()
-            ]
+        msg!("Create stake account");
+        let rent_from_operational = cpi.create_stake_account(
+            &mut self.stake_pool_config,
+            &stake_account,
+            validator_vote.key,
+            counter,
+            bumps.stake_account
         )?;
 
-        sol_log_compute_units();
-        msg!("Delegate stake");
-        invoke_signed(
-            &stake::instruction::delegate_stake(
-                &self.stake_account.key(), 
-                &staker, 
-                self.validator_vote.key
-            ), 
-            &[
-                self.stake_program.to_account_info(),
-                self.stake_account.to_account_info(),
-                self.stake_deposit_authority.to_account_info(),
-                self.validator_vote.to_account_info(),
-                self.clock.to_account_info(),
-                self.stake_history.to_account_info(),
-                self.stake_config.to_account_info()
-            ], 
-            &[&[
-                self.stake_pool_config.key().as_ref(),
-                StakeSystem::STAKE_DEPOSIT_SEED,
-                &[self.stake_pool_config.stake_system.stake_deposit_bump_seed]
-            ]]
+        cpi.delegate(
+            &mut self.stake_pool_config,
+            &stake_account,
+            &validator_vote,
+            stake_target
         )?;
 
         self.stake_pool_config.stake_system.add(
@@ -290,6 +253,10 @@ impl<'info> StakeReserve<'info> {
             &self.clock, 
             0
         )?;
+        // 新建的质押账户本 epoch 没有待入账的收益
+        self.stake_pool_config.on_stake_updated(self.clock.epoch);
+
+        self.stake_pool_config.stake_system.stake_account_counter += 1;
 
         validator.active_balance += stake_target;
         validator.last_stake_delta_epoch = self.clock.epoch;
@@ -302,6 +269,8 @@ impl<'info> StakeReserve<'info> {
 
         self.stake_pool_config.validator_system.total_active_balance += stake_target;
 
+        let crank_bounty = cpi.pay_crank_bounty(&mut self.stake_pool_config)?;
+
         emit!(StakeReserveEvent {
             state: self.stake_pool_config.key(),
             epoch: self.clock.epoch,
@@ -316,25 +285,380 @@ impl<'info> StakeReserve<'info> {
             total_active_balance,
             validator_active_balance,
             stake_delta,
+            rent_from_operational,
+            crank_bounty,
         });
 
-        Ok(())
+        emit_stake_distribution(
+            self.stake_pool_config.key(),
+            &self.stake_pool_config,
+            &self.validator_list.to_account_info().data.borrow(),
+            total_stake_target,
+            self.clock.epoch
+        )
     }
 
-    pub fn return_unused_stake_account_rent(&self) -> Result<()> {
-        withdraw(
-            CpiContext::new(
-                self.stake_program.to_account_info(), 
-                Withdraw {
-                    stake: self.stake_account.to_account_info(),
-                    withdrawer: self.stake_account.to_account_info(),
-                    to: self.rent_payer.to_account_info(),
-                    clock: self.clock.to_account_info(),
-                    stake_history: self.stake_history.to_account_info()
-                }
-            ), 
-            self.stake_account.to_account_info().lamports(), 
-            None
+    fn stake_cpi(&self) -> StakeCpi<'info> {
+        StakeCpi {
+            state: self.stake_pool_config.key(),
+            rent_payer: self.rent_payer.to_account_info(),
+            reserve_pda: self.reserve_pda.to_account_info(),
+            operational_sol_account: self.operational_sol_account.to_account_info(),
+            stake_deposit_authority: self.stake_deposit_authority.to_account_info(),
+            stake_history: self.stake_history.to_account_info(),
+            stake_config: self.stake_config.to_account_info(),
+            clock: self.clock.to_account_info(),
+            rent: self.rent.to_account_info(),
+            system_program: self.system_program.to_account_info(),
+            stake_program: self.stake_program.to_account_info(),
+        }
+    }
+}
+
+
+/// 验证者本 epoch 已经质押过时，消耗一次额外调整次数；没有剩余次数时返回 false
+pub(crate) fn check_stake_delta_run(
+    config: &mut StakePoolConfig,
+    validator: &ValidatorRecord,
+    epoch: u64
+) -> bool {
+    if validator.last_stake_delta_epoch == epoch {
+        if config.stake_system.extra_stake_delta_runs == 0 {
+            msg!(
+                "Double delta stake command for validator {} in epoch {}",
+                validator.validator_account,
+                epoch
+            );
+            return false;
+        }
+        config.stake_system.extra_stake_delta_runs -= 1;
+    }
+
+    true
+}
+
+
+/// 只允许在 epoch 最后 slots_for_stake_delta 个 slot 内质押
+pub(crate) fn check_stake_delta_window(
+    config: &StakePoolConfig,
+    clock: &Clock,
+    epoch_schedule: &EpochSchedule
+) -> Result<()> {
+    let last_slot = epoch_schedule.get_last_slot_in_epoch(clock.epoch);
+
+    require_gte!(
+        clock.slot,
+        last_slot.saturating_sub(config.stake_system.slots_for_stake_delta),
+        StakingError::TooEarlyForStakeDelta
+    );
+
+    Ok(())
+}
+
+
+/// 计算本次向验证者质押的数量与验证者的质押目标，不需要质押时返回 None。
+/// total_stake_target 为 total_active_balance + stake_delta，按评分分配得到验证者目标；
+/// remaining_delta 为 reserve 中尚未分配的部分，最多补足验证者的缺口，
+/// 分配后剩余不足 min_stake 时一并质押，避免 reserve 中留下无法质押的零头
+pub(crate) fn validator_stake_amount(
+    config: &StakePoolConfig,
+    validator: &ValidatorRecord,
+    total_stake_target: u64,
+    remaining_delta: u64
+) -> Result<Option<(u64, u64)>> {
+    let validator_stake_target = config.validator_system
+        .validator_stake_target(
+            validator, 
+            total_stake_target
+        )?;
+
+    if validator.active_balance >= validator_stake_target {
+        msg!(
+            "Validator {} has already reached stake target {}. Please stake into another validator",
+            validator.validator_account,
+            validator_stake_target
+        );
+        return Ok(None);
+    }
+
+    let stake_target = validator_stake_target
+        .saturating_sub(validator.active_balance)
+        .min(remaining_delta);
+
+    let stake_target = if remaining_delta - stake_target 
+        < config.stake_system.min_stake 
+    {
+        remaining_delta
+    } else {
+        stake_target
+    };
+
+    if stake_target < config.stake_system.min_stake {
+        msg!(
+            "Resulting stake {} is lower than min stake allowed {}",
+            stake_target,
+            config.stake_system.min_stake
+        );
+        return Ok(None);
+    }
+
+    Ok(Some((stake_target, validator_stake_target)))
+}
+
+
+pub(crate) fn emit_stake_distribution(
+    state: Pubkey,
+    config: &StakePoolConfig,
+    validator_list_data: &[u8],
+    total_stake_target: u64,
+    epoch: u64
+) -> Result<()> {
+    emit!(StakeDistributionEvent {
+        state,
+        epoch,
+        total_stake_target,
+        deviations: config.validator_system.stake_deviations(validator_list_data, total_stake_target)?,
+    });
+
+    Ok(())
+}
+
+
+/// 从 operational_sol_account 向调用者支付一次奖励，受本 epoch 奖励上限与运营预算限制，
+/// 返回实际支付数量。stake_reserve、stake_reserve_batch 与 update_stake 共用
+pub(crate) fn pay_crank_bounty<'info>(
+    config: &mut StakePoolConfig,
+    state: &Pubkey,
+    operational_sol_account: &AccountInfo<'info>,
+    crank: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>
+) -> Result<u64> {
+    let epoch = Clock::get()?.epoch;
+    let crank_bounty = config.crank_bounty_payable(operational_sol_account.lamports(), epoch)?;
+    if crank_bounty == 0 {
+        return Ok(0);
+    }
+
+    msg!("Pay crank bounty {}", crank_bounty);
+    transfer(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            Transfer {
+                from: operational_sol_account.clone(),
+                to: crank.clone()
+            },
+            &[&[
+                state.as_ref(),
+                StakePoolConfig::OPERATIONAL_SOL_SEED,
+                &[config.operational_sol_bump_seed]
+            ]]
+        ),
+        crank_bounty
+    )?;
+    config.on_crank_bounty_paid(crank_bounty, epoch);
+
+    Ok(crank_bounty)
+}
+
+
+/// stake_reserve 与 stake_reserve_batch 创建、委托质押账户和支付奖励时共用的账户
+pub(crate) struct StakeCpi<'info> {
+    pub state: Pubkey,
+    pub rent_payer: AccountInfo<'info>,
+    pub reserve_pda: AccountInfo<'info>,
+    pub operational_sol_account: AccountInfo<'info>,
+    pub stake_deposit_authority: AccountInfo<'info>,
+    pub stake_history: AccountInfo<'info>,
+    pub stake_config: AccountInfo<'info>,
+    pub clock: AccountInfo<'info>,
+    pub rent: AccountInfo<'info>,
+    pub system_program: AccountInfo<'info>,
+    pub stake_program: AccountInfo<'info>,
+}
+
+
+impl<'info> StakeCpi<'info> {
+    /// 创建一个质押账户后向调用者支付奖励，返回实际支付数量
+    pub fn pay_crank_bounty(&self, config: &mut StakePoolConfig) -> Result<u64> {
+        pay_crank_bounty(
+            config,
+            &self.state,
+            &self.operational_sol_account,
+            &self.rent_payer,
+            &self.system_program
         )
     }
+
+    /// 以 PDA 签名创建质押账户，返回 operational_sol_account 支付的租金。
+    /// 租金优先由 operational_sol_account 在本 epoch 预算内支付，不足时由 rent_payer 支付；
+    /// 地址可能已被他人预先转入 lamports，只补足差额
+    pub fn create_stake_account(
+        &self,
+        config: &mut StakePoolConfig,
+        stake_account: &AccountInfo<'info>,
+        validator_vote: &Pubkey,
+        counter: u64,
+        bump: u8
+    ) -> Result<u64> {
+        let epoch = Clock::get()?.epoch;
+        let space = std::mem::size_of::<StakeStateV2>();
+        let rent_exempt = Rent::get()?.minimum_balance(space);
+        let counter = counter.to_le_bytes();
+        let seeds: &[&[u8]] = &[
+            self.state.as_ref(),
+            StakeSystem::STAKE_ACCOUNT_SEED,
+            validator_vote.as_ref(),
+            &counter,
+            &[bump]
+        ];
+
+        let required_lamports = rent_exempt.saturating_sub(stake_account.lamports());
+        let operational_spendable = config.operational_spendable(
+            self.operational_sol_account.lamports(),
+            epoch
+        )?;
+        let rent_from_operational = if required_lamports > 0
+            && required_lamports <= operational_spendable
+        {
+            transfer(
+                CpiContext::new_with_signer(
+                    self.system_program.clone(),
+                    Transfer {
+                        from: self.operational_sol_account.clone(),
+                        to: stake_account.clone()
+                    },
+                    &[&[
+                        self.state.as_ref(),
+                        StakePoolConfig::OPERATIONAL_SOL_SEED,
+                        &[config.operational_sol_bump_seed]
+                    ]]
+                ),
+                required_lamports
+            )?;
+            config.on_operational_spend(required_lamports, epoch);
+            required_lamports
+        } else {
+            if required_lamports > 0 {
+                msg!("Operational budget exhausted, rent paid by rent_payer");
+                transfer(
+                    CpiContext::new(
+                        self.system_program.clone(),
+                        Transfer {
+                            from: self.rent_payer.clone(),
+                            to: stake_account.clone()
+                        }
+                    ),
+                    required_lamports
+                )?;
+            }
+            0
+        };
+
+        allocate(
+            CpiContext::new_with_signer(
+                self.system_program.clone(),
+                Allocate { account_to_allocate: stake_account.clone() },
+                &[seeds]
+            ),
+            space as u64
+        )?;
+        assign(
+            CpiContext::new_with_signer(
+                self.system_program.clone(),
+                Assign { account_to_assign: stake_account.clone() },
+                &[seeds]
+            ),
+            &STAKE_ID
+        )?;
+
+        Ok(rent_from_operational)
+    }
+
+    /// 从 reserve 转入 amount 后初始化质押账户并委托给验证者
+    pub fn delegate(
+        &self,
+        config: &mut StakePoolConfig,
+        stake_account: &AccountInfo<'info>,
+        validator_vote: &AccountInfo<'info>,
+        amount: u64
+    ) -> Result<()> {
+        let staker = Pubkey::create_program_address(
+            &[
+                self.state.as_ref(),
+                StakeSystem::STAKE_DEPOSIT_SEED,
+                &[config.stake_system.stake_deposit_bump_seed]
+            ], 
+            &ID
+        ).map_err(ProgramError::from)?;
+
+        let withdrawer = Pubkey::create_program_address(
+            &[
+                self.state.as_ref(),
+                StakeSystem::STAKE_WITHDRAW_SEED,
+                &[config.stake_system.stake_withdraw_bump_seed]
+            ], 
+            &ID
+        ).map_err(ProgramError::from)?;
+
+        sol_log_compute_units();
+        msg!("Transfer to stake account");
+        transfer(
+            CpiContext::new_with_signer(
+                self.system_program.clone(), 
+                Transfer {
+                    from: self.reserve_pda.clone(),
+                    to: stake_account.clone()
+                }, 
+                &[&[
+                    self.state.as_ref(),
+                    StakePoolConfig::RESERVE_SEED,
+                    &[config.reserve_bump_seed]
+                ]]
+            ), 
+            amount
+        )?;
+        // SOL 离开 reserve 计入 active_balance，available_reserve_balance 需同步扣减
+        config.on_transfer_from_reserve(amount);
+
+        sol_log_compute_units();
+        msg!("Initialize stake");
+        invoke(
+            &stake::instruction::initialize(
+                stake_account.key, 
+                &Authorized {staker, withdrawer}, 
+                &Lockup::default(),
+            ), 
+            &[
+                self.stake_program.clone(),
+                stake_account.clone(),
+                self.rent.clone()
+            ]
+        )?;
+
+        sol_log_compute_units();
+        msg!("Delegate stake");
+        invoke_signed(
+            &stake::instruction::delegate_stake(
+                stake_account.key, 
+                &staker, 
+                validator_vote.key
+            ), 
+            &[
+                self.stake_program.clone(),
+                stake_account.clone(),
+                self.stake_deposit_authority.clone(),
+                validator_vote.clone(),
+                self.clock.clone(),
+                self.stake_history.clone(),
+                self.stake_config.clone()
+            ], 
+            &[&[
+                self.state.as_ref(),
+                StakeSystem::STAKE_DEPOSIT_SEED,
+                &[config.stake_system.stake_deposit_bump_seed]
+            ]]
+        )?;
+
+        Ok(())
+    }
 }
@@ -0,0 +1,343 @@
+//! 一次调用向多个验证者质押，验证者 vote 与质押账户通过 remaining_accounts 成对传入
+
+use anchor_lang::{
+    prelude::*,
+    solana_program::{
+        log::sol_log_compute_units,
+        stake::config::ID as STAKE_CONFIG_ID,
+        sysvar::stake_history::ID as STAKE_HISTORY_ID
+    },
+    system_program::ID as sys_id
+};
+use anchor_spl::stake::Stake;
+
+use crate::{
+    error::StakingError,
+    state::{
+        stake_system::{StakeList, StakeSystem},
+        validator_system::ValidatorList,
+        StakePoolConfig
+    }
+};
+
+use super::stake_reserve::{
+    check_stake_delta_run,
+    check_stake_delta_window,
+    emit_stake_distribution,
+    validator_stake_amount,
+    StakeCpi,
+    StakeReserveEvent
+};
+
+
+#[event]
+pub struct StakeReserveBatchEvent {
+    pub state: Pubkey,
+    pub epoch: u64,
+    pub reserve_balance: u64,
+    pub stake_delta: u64,
+    /// 本次实际质押的总量
+    pub staked: u64,
+    pub validators_staked: u32,
+    pub rent_from_operational: u64,
+    /// 本次支付的调用奖励总额
+    pub crank_bounty: u64,
+}
+
+
+#[derive(Accounts)]
+pub struct StakeReserveBatch<'info> {
+    /// 调用者：operational 预算不足时支付质押账户租金，并接收调用奖励
+    #[account(
+        mut,
+        owner = sys_id
+    )]
+    pub rent_payer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump,
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    #[account(
+        mut,
+        address = stake_pool_config.validator_system.validator_list.account
+    )]
+    pub validator_list: Account<'info, ValidatorList>,
+
+    #[account(
+        mut,
+        address = stake_pool_config.stake_system.stake_list.account
+    )]
+    pub stake_list: Account<'info, StakeList>,
+
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            StakePoolConfig::RESERVE_SEED
+        ],
+        bump = stake_pool_config.reserve_bump_seed
+    )]
+    pub reserve_pda: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            StakePoolConfig::OPERATIONAL_SOL_SEED
+        ],
+        bump = stake_pool_config.operational_sol_bump_seed
+    )]
+    pub operational_sol_account: SystemAccount<'info>,
+
+    /// CHECK: PDA
+    #[account(
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            StakeSystem::STAKE_DEPOSIT_SEED
+        ],
+        bump = stake_pool_config.stake_system.stake_deposit_bump_seed
+    )]
+    pub stake_deposit_authority: UncheckedAccount<'info>,
+
+    /// CHECK: have no CPU budget to parse
+    #[account(address = STAKE_HISTORY_ID)]
+    pub stake_history: UncheckedAccount<'info>,
+
+    /// CHECK: CPI
+    #[account(address = STAKE_CONFIG_ID)]
+    pub stake_config: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub epoch_schedule: Sysvar<'info, EpochSchedule>,
+    pub rent: Sysvar<'info, Rent>,
+    pub system_program: Program<'info, System>,
+    pub stake_program: Program<'info, Stake>,
+}
+
+
+impl<'info> StakeReserveBatch<'info> {
+    /// remaining_accounts 依次为 [validator_vote, stake_account] 对，与 validator_indices 一一对应。
+    /// 各对须按质押缺口从大到小排列，与逐次调用 stake_reserve 的顺序一致；
+    /// 按顺序处理，遇到第一个无法质押的验证者即停止，其后的对不再处理；
+    /// 第 i 对的质押账户由 stake_account_counter + i 派生，计数器只增加实际创建的质押账户数
+    pub fn process(
+        &mut self,
+        validator_indices: Vec<u32>,
+        remaining_accounts: &[AccountInfo<'info>]
+    ) -> Result<()> {
+        require!(!self.stake_pool_config.paused, StakingError::ProgramIsPaused);
+        self.stake_pool_config.check_not_winding_down()?;
+
+        require!(
+            !validator_indices.is_empty()
+                && validator_indices.len() <= StakeSystem::MAX_STAKE_BATCH_SIZE
+                && remaining_accounts.len() == validator_indices.len() * 2,
+            StakingError::InvalidStakeBatch
+        );
+
+        // 打印当前剩余 CU 数量
+        sol_log_compute_units();
+
+        let reserve_balance = self.reserve_pda.lamports();
+        let stake_delta = self.stake_pool_config.stake_delta(reserve_balance);
+        if stake_delta == 0 {
+            return Ok(());
+        }
+
+        check_stake_delta_window(&self.stake_pool_config, &self.clock, &self.epoch_schedule)?;
+
+        // 目标在整个批次中保持不变，按开始时的池子总质押计算
+        let total_stake_target = self.stake_pool_config.validator_system.total_active_balance
+            + stake_delta;
+        // 尚未处理的验证者按缺口从大到小排列，每质押一个就移除队首
+        let mut ranked: Vec<(u32, u64)> = Vec::new();
+        let cpi = self.stake_cpi();
+        let state = self.stake_pool_config.key();
+        let base_counter = self.stake_pool_config.stake_system.stake_account_counter;
+        let mut staked = 0;
+        let mut validators_staked = 0;
+        let mut rent_from_operational = 0;
+        let mut crank_bounty = 0;
+
+        for (i, (validator_index, pair)) in validator_indices
+            .iter()
+            .zip(remaining_accounts.chunks_exact(2))
+            .enumerate()
+        {
+            let remaining_delta = stake_delta - staked;
+            if remaining_delta < self.stake_pool_config.stake_system.min_stake {
+                break;
+            }
+            // 前面的对都已创建质押账户，计数器与 i 对应
+            let counter = base_counter + i as u64;
+
+            let (validator_vote, stake_account) = (&pair[0], &pair[1]);
+            let (expected_stake_account, bump) = StakeSystem::find_stake_account(
+                &state,
+                validator_vote.key,
+                counter
+            );
+            require_keys_eq!(
+                *stake_account.key,
+                expected_stake_account,
+                StakingError::InvalidStakeBatch
+            );
+
+            let mut validator = self.stake_pool_config.validator_system.get_checked(
+                &self.validator_list.to_account_info().data.borrow(),
+                *validator_index,
+                *validator_vote.key
+            ).map_err(|e| e.with_account_name("validator_vote"))?;
+
+            // 不允许重复质押时，质押过的验证者退出排序而其余验证者先后不变，只需遍历一次列表；
+            // 允许重复质押时，质押过的验证者按新的余额继续参与排序，每次质押前都重新排序
+            let allow_repeat = self.stake_pool_config.stake_system.extra_stake_delta_runs > 0;
+            if allow_repeat || ranked.is_empty() {
+                ranked = self.stake_pool_config.validator_system.most_under_allocated_n(
+                    &self.validator_list.to_account_info().data.borrow(),
+                    total_stake_target,
+                    self.clock.epoch,
+                    allow_repeat,
+                    if allow_repeat { 1 } else { validator_indices.len() - i }
+                )?;
+            }
+            match ranked.first() {
+                Some(&(index, _)) if index == *validator_index => {}
+                Some(&(index, shortfall)) => {
+                    msg!(
+                        "Validator {} has the next largest shortfall {}, stake into it first",
+                        index,
+                        shortfall
+                    );
+                    return err!(StakingError::ValidatorNotMostUnderAllocated);
+                }
+                None => {
+                    msg!("No more validators are below their stake target");
+                    break;
+                }
+            }
+
+            let validator_active_balance = validator.active_balance;
+            if !check_stake_delta_run(&mut self.stake_pool_config, &validator, self.clock.epoch) {
+                break;
+            }
+
+            let Some((stake_target, validator_stake_target)) = validator_stake_amount(
+                &self.stake_pool_config,
+                &validator,
+                total_stake_target,
+                remaining_delta
+            )? else {
+                break;
+            };
+
+            msg!("Create stake account for validator {}", validator_vote.key);
+            let rent = cpi.create_stake_account(
+                &mut self.stake_pool_config,
+                stake_account,
+                validator_vote.key,
+                counter,
+                bump
+            )?;
+            cpi.delegate(
+                &mut self.stake_pool_config,
+                stake_account,
+                validator_vote,
+                stake_target
+            )?;
+
+            self.stake_pool_config.stake_system.add(
+                &mut self.stake_list.to_account_info().data.borrow_mut(),
+                stake_account.key,
+                stake_target,
+                &self.clock,
+                0
+            )?;
+            self.stake_pool_config.on_stake_updated(self.clock.epoch);
+
+            let total_active_balance = self.stake_pool_config.validator_system.total_active_balance;
+            validator.active_balance += stake_target;
+            validator.last_stake_delta_epoch = self.clock.epoch;
+            self.stake_pool_config.validator_system.set(
+                &mut self.validator_list.to_account_info().data.borrow_mut(),
+                *validator_index,
+                validator
+            )?;
+            self.stake_pool_config.validator_system.total_active_balance += stake_target;
+            self.stake_pool_config.stake_system.stake_account_counter += 1;
+            ranked.remove(0);
+
+            // 每个实际质押的验证者各支付一次奖励
+            let bounty = cpi.pay_crank_bounty(&mut self.stake_pool_config)?;
+
+            staked += stake_target;
+            validators_staked += 1;
+            rent_from_operational += rent;
+            crank_bounty += bounty;
+
+            emit!(StakeReserveEvent {
+                state,
+                epoch: self.clock.epoch,
+                stake_index: self.stake_pool_config.stake_system.stake_list.count - 1,
+                stake_account: *stake_account.key,
+                validator_index: *validator_index,
+                validator_vote: *validator_vote.key,
+                amount: stake_target,
+                stake_target,
+                validator_stake_target,
+                reserve_balance,
+                total_active_balance,
+                validator_active_balance,
+                stake_delta,
+                rent_from_operational: rent,
+                crank_bounty: bounty,
+            });
+        }
+
+        if staked == 0 {
+            msg!("Nothing staked");
+            return Ok(()); // Not an error. Don't fail other instructions in tx
+        }
+
+        self.stake_pool_config.stake_system.last_stake_delta_epoch = self.clock.epoch;
+
+        emit!(StakeReserveBatchEvent {
+            state,
+            epoch: self.clock.epoch,
+            reserve_balance,
+            stake_delta,
+            staked,
+            validators_staked,
+            rent_from_operational,
+            crank_bounty,
+        });
+
+        emit_stake_distribution(
+            state,
+            &self.stake_pool_config,
+            &self.validator_list.to_account_info().data.borrow(),
+            total_stake_target,
+            self.clock.epoch
+        )
+    }
+
+    fn stake_cpi(&self) -> StakeCpi<'info> {
+        StakeCpi {
+            state: self.stake_pool_config.key(),
+            rent_payer: self.rent_payer.to_account_info(),
+            reserve_pda: self.reserve_pda.to_account_info(),
+            operational_sol_account: self.operational_sol_account.to_account_info(),
+            stake_deposit_authority: self.stake_deposit_authority.to_account_info(),
+            stake_history: self.stake_history.to_account_info(),
+            stake_config: self.stake_config.to_account_info(),
+            clock: self.clock.to_account_info(),
+            rent: self.rent.to_account_info(),
+            system_program: self.system_program.to_account_info(),
+            stake_program: self.stake_program.to_account_info(),
+        }
+    }
+}
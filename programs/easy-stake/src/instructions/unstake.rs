@@ -1,6 +1,10 @@
 //!  解质押
 
-use anchor_lang::{prelude::*, system_program::{transfer, Transfer}};
+use anchor_lang::{
+    prelude::*,
+    solana_program::sysvar::instructions::ID as INSTRUCTIONS_ID,
+    system_program::{transfer, Transfer}
+};
 use anchor_spl::token::{
     Mint, 
     Token, 
@@ -9,7 +13,21 @@ use anchor_spl::token::{
     Transfer as TransferToken
 };
 
-use crate::{error::StakingError, state::{Fee, LiqPool, StakePoolConfig}};
+use crate::{
+    calc::Rounding,
+    checks::{check_allowlist, check_transaction_guard, PoolDirection},
+    error::StakingError,
+    state::{
+        AllowlistEntry,
+        Fee,
+        FeeCurve,
+        FeeOverride,
+        LiqPool,
+        MsolPriceOracle,
+        ReferralAccount,
+        StakePoolConfig
+    }
+};
 
 
 #[event]
@@ -30,6 +48,78 @@ pub struct LiquidUnstakeEvent {
     pub lp_max_fee: Fee,
     pub lp_min_fee: Fee,
     pub treasury_cut: Fee,
+    pub fee_curve: FeeCurve,
+    pub referral: Option<Pubkey>,
+    pub referral_msol_cut: u64,
+    pub fee_override: Option<Pubkey>,
+}
+
+
+/// 通过 return data 返回给 CPI 调用方的结果，quote_unstake 返回同一结构
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnstakeResult {
+    pub sol_out: u64,
+    /// 以 mSOL 计的手续费，包含国库分成
+    pub fee: u64,
+    pub treasury_msol_cut: u64,
+    /// 本次解质押使用的费率
+    pub liquid_unstake_fee: Fee,
+}
+
+
+impl UnstakeResult {
+    /// 只依据池子当前状态计算解质押结果，不修改任何状态。unstake 与 quote_unstake 共用，保证报价与实际执行一致
+    pub fn calc(
+        config: &StakePoolConfig,
+        msol_amount: u64,
+        sol_leg_balance: u64,
+        fee_override: Option<&FeeOverride>
+    ) -> Result<Self> {
+        let sol_leg_available_balance = sol_leg_balance.saturating_sub(
+            config.rent_exempt_for_token_acc
+        );
+
+        // 计算能兑换到的 sol，仅用于确定费率，向上取整使费率偏向池子一侧
+        let user_remove_lamports = config.msol_to_sol(msol_amount, Rounding::RoundUp)?;
+        // 计算兑换手续费
+        let liquid_unstake_fee = config.liquid_unstake_fee(
+            sol_leg_available_balance, 
+            user_remove_lamports
+        )?;
+        let liquid_unstake_fee = FeeOverride::apply_optional(
+            fee_override,
+            liquid_unstake_fee,
+            config.liq_pool.lp_min_fee
+        );
+
+        let fee = liquid_unstake_fee.apply(msol_amount, Rounding::RoundUp);
+        msg!("msol_fee {}", fee);
+
+        // 扣除手续费后能提取到的 sol，向下取整
+        let sol_out = config.msol_to_sol(msol_amount - fee, Rounding::RoundDown)?;
+
+        require_gte!(
+            sol_out,
+            config.min_withdraw,
+            StakingError::WithdrawAmountIsTooLow
+        );
+
+        // 判断提取数量是否超过池子 sol 总量
+        if sol_out > sol_leg_available_balance {
+            return err!(StakingError::InsufficientLiquidity);
+        }
+
+        // 国库分成向下取整，余数留给 LP
+        let treasury_msol_cut = config.liq_pool.treasury_cut.apply(fee, Rounding::RoundDown);
+        msg!("treasury_msol_cut {}", treasury_msol_cut);
+
+        Ok(Self {
+            sol_out,
+            fee,
+            treasury_msol_cut,
+            liquid_unstake_fee,
+        })
+    }
 }
 
 
@@ -46,10 +136,7 @@ pub struct Unstake<'info> {
     )]
     pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
 
-    #[account(
-        mut,
-        mint::authority = stake_pool_config
-    )]
+    #[account(mut)]
     pub msol_mint: Box<Account<'info, Mint>>,
 
     #[account(
@@ -86,6 +173,39 @@ pub struct Unstake<'info> {
     #[account(mut)]
     pub transfer_sol_to: SystemAccount<'info>,
 
+    #[account(
+        constraint = fee_override.state == stake_pool_config.key() @ StakingError::InvalidFeeOverride
+    )]
+    pub fee_override: Option<Box<Account<'info, FeeOverride>>>,
+
+    #[account(
+        mut,
+        constraint = referral.state == stake_pool_config.key() @ StakingError::InvalidReferralAccount
+    )]
+    pub referral: Option<Box<Account<'info, ReferralAccount>>>,
+
+    /// 合作方 mSOL 账户，传入 referral 时必须提供
+    #[account(mut)]
+    pub referral_msol_account: Option<Box<Account<'info, TokenAccount>>>,
+
+    /// 许可模式下必须传入调用者的白名单账户
+    pub allowlist_entry: Option<Box<Account<'info, AllowlistEntry>>>,
+
+    /// CHECK: 指令 sysvar，仅在开启交易检查时需要
+    #[account(address = INSTRUCTIONS_ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+
+    /// 传入时在汇率变化后同步刷新 mSOL 价格预言机
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            MsolPriceOracle::SEED
+        ],
+        bump = price_oracle.bump
+    )]
+    pub price_oracle: Option<Box<Account<'info, MsolPriceOracle>>>,
+
     pub system_program: Program<'info, System>,
     pub token_program: Program<'info, Token>
 
@@ -93,52 +213,51 @@ pub struct Unstake<'info> {
 
 
 impl<'info> Unstake<'info> {
-    pub fn process(&mut self, msol_amount: u64) -> Result<()> {
+    pub fn process(&mut self, msol_amount: u64) -> Result<UnstakeResult> {
         require!(!self.stake_pool_config.paused, StakingError::ProgramIsPaused);
+        self.stake_pool_config.check_no_flash_loan()?;
+        check_transaction_guard(
+            &self.stake_pool_config,
+            self.instructions.as_ref(),
+            PoolDirection::Out
+        )?;
+        check_allowlist(
+            &self.stake_pool_config,
+            self.allowlist_entry.as_deref().map(|entry| &**entry),
+            &self.get_msol_from_authority.key()
+        )?;
 
         let user_sol_balance = self.transfer_sol_to.lamports();
         let user_msol_balance = self.get_msol_from.amount;
         let treasury_msol_balance = self.treasury_msol_account.amount;
         let liq_pool_msol_balance = self.liq_pool_msol_leg.amount;
         let liq_pool_sol_balance = self.liq_pool_sol_leg_pda.lamports();
-        let liq_pool_available_sol_balance = liq_pool_sol_balance.saturating_sub(
-                self.stake_pool_config.rent_exempt_for_token_acc
-            );
-        
-        // 计算能兑换到的 sol
-        let user_remove_lamports = self.stake_pool_config.msol_to_sol(msol_amount)?;
-        // 计算兑换手续费
-        let liquid_unstake_fee = if user_remove_lamports >= liq_pool_available_sol_balance {
-            // 兑换的 sol 数量超过池子sol总量，直接收取最大手续费
-            self.stake_pool_config.liq_pool.lp_max_fee
-        } else {
-            // 用户提取后池子剩余的 SOL，后续根据它计算手续费。
-            // 池子越接近枯竭，手续费越高（linear_fee 根据剩余量计算）。
-            let after_lamports = liq_pool_available_sol_balance - user_remove_lamports;
-            self.stake_pool_config.liq_pool.linear_fee(after_lamports)
-        };
 
-        let msol_fee = liquid_unstake_fee.apply(msol_amount);
-        msg!("msol_fee {}", msol_fee);
-
-        // 扣除手续费后能提取到的 sol
-        let working_lamports_value = self.stake_pool_config.msol_to_sol(msol_amount - msol_fee)?;
+        if let Some(fee_override) = &self.fee_override {
+            fee_override.check_applies(
+                &self.get_msol_from_authority.key(),
+                self.instructions.as_ref()
+            )?;
+        }
 
-        require_gte!(
-            working_lamports_value,
-            self.stake_pool_config.min_withdraw,
-            StakingError::WithdrawAmountIsTooLow
-        );
+        let result = UnstakeResult::calc(
+            &self.stake_pool_config,
+            msol_amount,
+            liq_pool_sol_balance,
+            self.fee_override.as_deref().map(|fee_override| &**fee_override)
+        )?;
 
-        // 判断提取数量是否超过池子 sol 总量
-        if working_lamports_value + self.stake_pool_config.rent_exempt_for_token_acc 
-            > self.liq_pool_sol_leg_pda.lamports() 
-        {
-            return err!(StakingError::InsufficientLiquidity);
-        }
+        // 国库分成中合作方的部分直接转给合作方
+        let referral_msol_cut = match &self.referral {
+            Some(referral) => {
+                referral.check_partner_msol_account(self.referral_msol_account.as_deref())?;
+                referral.partner_cut(result.treasury_msol_cut)
+            }
+            None => 0
+        };
 
         // 转帐 sol
-        if working_lamports_value > 0 {
+        if result.sol_out > 0 {
             transfer(
                 CpiContext::new_with_signer(
                     self.system_program.to_account_info(), 
@@ -152,13 +271,10 @@ impl<'info> Unstake<'info> {
                         &[self.stake_pool_config.liq_pool.sol_leg_bump_seed]
                     ]],
                 ), 
-                working_lamports_value
+                result.sol_out
             )?;
         }
 
-        let treasury_msol_cut = self.stake_pool_config.liq_pool.treasury_cut.apply(msol_fee);
-        msg!("treasury_msol_cut {}", treasury_msol_cut);
-
         // 扣除国库手续费后的 msol 入池
         transfer_token(
             CpiContext::new(
@@ -169,24 +285,32 @@ impl<'info> Unstake<'info> {
                     authority: self.get_msol_from_authority.to_account_info()
                 }
             ), 
-        msol_amount - treasury_msol_cut
+        msol_amount - result.treasury_msol_cut
         )?;
 
         // 将手续费转给 国库
-        if treasury_msol_cut > 0 {
-            transfer_token(
-                CpiContext::new(
-                    self.token_program.to_account_info(), 
-                    TransferToken {
-                        from: self.get_msol_from.to_account_info(),
-                        to: self.treasury_msol_account.to_account_info(),
-                        authority: self.get_msol_from_authority.to_account_info()
-                    }
-                ), 
-                treasury_msol_cut
+        if result.treasury_msol_cut > referral_msol_cut {
+            self.transfer_msol_fee(
+                self.treasury_msol_account.to_account_info(),
+                result.treasury_msol_cut - referral_msol_cut
             )?;
         }
+
+        if referral_msol_cut > 0 {
+            if let Some(referral_msol_account) = &self.referral_msol_account {
+                self.transfer_msol_fee(referral_msol_account.to_account_info(), referral_msol_cut)?;
+            }
+        }
+
+        if let Some(referral) = &mut self.referral {
+            referral.on_unstake(msol_amount, referral_msol_cut);
+            referral.emit_event(referral.key(), referral_msol_cut);
+        }
         
+        self.stake_pool_config.refresh_price_oracle(
+            self.price_oracle.as_deref_mut().map(|price_oracle| &mut **price_oracle)
+        )?;
+
         emit!(LiquidUnstakeEvent {
             state: self.stake_pool_config.key(),
             msol_owner: self.get_msol_from.owner,
@@ -196,15 +320,33 @@ impl<'info> Unstake<'info> {
             treasury_msol_balance,
             user_msol_balance,
             user_sol_balance,
-            msol_fee,
-            treasury_msol_cut,
-            sol_amount: working_lamports_value,
+            msol_fee: result.fee,
+            treasury_msol_cut: result.treasury_msol_cut,
+            sol_amount: result.sol_out,
             lp_liquidity_target: self.stake_pool_config.liq_pool.lp_liquidity_target,
             lp_max_fee: self.stake_pool_config.liq_pool.lp_max_fee,
             lp_min_fee: self.stake_pool_config.liq_pool.lp_min_fee,
-            treasury_cut: self.stake_pool_config.liq_pool.treasury_cut
+            treasury_cut: self.stake_pool_config.liq_pool.treasury_cut,
+            fee_curve: self.stake_pool_config.fee_curve,
+            referral: self.referral.as_ref().map(|referral| referral.key()),
+            referral_msol_cut,
+            fee_override: self.fee_override.as_ref().map(|fee_override| fee_override.key()),
         });
 
-        Ok(())
+        Ok(result)
+    }
+
+    fn transfer_msol_fee(&self, to: AccountInfo<'info>, amount: u64) -> Result<()> {
+        transfer_token(
+            CpiContext::new(
+                self.token_program.to_account_info(), 
+                TransferToken {
+                    from: self.get_msol_from.to_account_info(),
+                    to,
+                    authority: self.get_msol_from_authority.to_account_info()
+                }
+            ), 
+            amount
+        )
     }
 }
@@ -0,0 +1,64 @@
+//! 解质押，SOL 以 wSOL 形式存入用户的代币账户
+
+use anchor_lang::{prelude::*, system_program::{transfer, Transfer}};
+use anchor_spl::token::{
+    spl_token::native_mint,
+    sync_native,
+    SyncNative,
+    TokenAccount
+};
+
+use crate::error::StakingError;
+
+use super::unstake::*;
+
+
+#[derive(Accounts)]
+pub struct UnstakeToWsol<'info> {
+    /// SOL 先转给 mSOL 持有者本人，再由其签名包装为 wSOL
+    #[account(
+        constraint = unstake.transfer_sol_to.key() == unstake.get_msol_from_authority.key()
+            @ StakingError::InvalidWsolUnstakeRecipient
+    )]
+    pub unstake: Unstake<'info>,
+
+    #[account(
+        mut,
+        token::mint = native_mint::ID
+    )]
+    pub wsol_to: Box<Account<'info, TokenAccount>>,
+}
+
+
+impl<'info> UnstakeToWsol<'info> {
+    pub fn process(&mut self, msol_amount: u64) -> Result<UnstakeResult> {
+        let sol_balance_before = self.unstake.transfer_sol_to.lamports();
+
+        let result = self.unstake.process(msol_amount)?;
+
+        let sol_amount = self.unstake.transfer_sol_to.lamports() - sol_balance_before;
+        if sol_amount > 0 {
+            transfer(
+                CpiContext::new(
+                    self.unstake.system_program.to_account_info(),
+                    Transfer {
+                        from: self.unstake.transfer_sol_to.to_account_info(),
+                        to: self.wsol_to.to_account_info()
+                    }
+                ),
+                sol_amount
+            )?;
+
+            sync_native(
+                CpiContext::new(
+                    self.unstake.token_program.to_account_info(),
+                    SyncNative {
+                        account: self.wsol_to.to_account_info()
+                    }
+                )
+            )?;
+        }
+
+        Ok(result)
+    }
+}
@@ -0,0 +1,65 @@
+//! 更新 mSOL 价格预言机（无需权限），同时更新 StakePoolConfig.msol_price
+
+use anchor_lang::prelude::*;
+
+use crate::state::{MsolPriceOracle, StakePoolConfig};
+
+
+#[event]
+pub struct UpdatePriceOracleEvent {
+    pub state: Pubkey,
+    pub epoch: u64,
+    pub slot: u64,
+    pub previous_price: u64,
+    pub price: u64,
+    // 价格的组成部分
+    pub total_virtual_staked_lamports: u64,
+    pub msol_supply: u64,
+}
+
+
+#[derive(Accounts)]
+pub struct UpdatePriceOracle<'info> {
+    #[account(
+        mut,
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            MsolPriceOracle::SEED
+        ],
+        bump = price_oracle.bump
+    )]
+    pub price_oracle: Box<Account<'info, MsolPriceOracle>>,
+}
+
+
+impl<'info> UpdatePriceOracle<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        let clock = Clock::get()?;
+        let previous_price = self.price_oracle.price;
+
+        self.stake_pool_config.refresh_price_oracle(Some(&mut self.price_oracle))?;
+
+        let price = self.stake_pool_config.msol_price;
+        let total_virtual_staked_lamports = self.stake_pool_config.total_staked_lamports()?;
+        let msol_supply = self.stake_pool_config.msol_supply;
+
+        emit!(UpdatePriceOracleEvent {
+            state: self.stake_pool_config.key(),
+            epoch: clock.epoch,
+            slot: clock.slot,
+            previous_price,
+            price,
+            total_virtual_staked_lamports,
+            msol_supply,
+        });
+
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+//! 更新质押账户收益（任何人都可调用），每个质押账户每 epoch 一次
+
+use anchor_lang::{prelude::*, system_program::ID as sys_id};
+use anchor_spl::{
+    stake::StakeAccount,
+    token::{mint_to, Mint, MintTo, Token, TokenAccount}
+};
+
+use crate::{
+    calc::Rounding,
+    error::StakingError,
+    state::{
+        stake_system::StakeList,
+        validator_system::ValidatorList,
+        MsolPriceOracle,
+        StakePoolConfig
+    }
+};
+
+use super::stake_reserve::pay_crank_bounty;
+
+
+#[event]
+pub struct UpdateStakeEvent {
+    pub state: Pubkey,
+    pub epoch: u64,
+    pub stake_index: u32,
+    pub stake_account: Pubkey,
+    pub validator_index: u32,
+    pub validator_vote: Pubkey,
+    /// 上次更新时记录的质押数量
+    pub delegated_lamports: u64,
+    /// 本次更新后的质押数量：委托数量 delegation.stake，不包含直接转入账户的 lamports
+    pub stake_lamports: u64,
+    pub rewards: u64,
+    /// 质押数量减少（如被罚没）时从 active_balance 扣除的部分
+    pub lost_lamports: u64,
+    /// 按 reward_fee 铸造给 treasury 的 mSOL
+    pub treasury_msol_cut: u64,
+    /// 记录到收益时支付给调用者的奖励
+    pub crank_bounty: u64,
+    pub total_active_balance: u64,
+    pub msol_price: u64,
+}
+
+
+#[derive(Accounts)]
+pub struct UpdateStake<'info> {
+    /// 调用者：记录到收益时接收调用奖励
+    #[account(
+        mut,
+        owner = sys_id
+    )]
+    pub crank: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump,
+        has_one = msol_mint,
+        has_one = treasury_msol_account
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    #[account(
+        mut,
+        address = stake_pool_config.validator_system.validator_list.account
+    )]
+    pub validator_list: Account<'info, ValidatorList>,
+
+    #[account(
+        mut,
+        address = stake_pool_config.stake_system.stake_list.account
+    )]
+    pub stake_list: Account<'info, StakeList>,
+
+    pub stake_account: Box<Account<'info, StakeAccount>>,
+
+    #[account(
+        mut,
+        mint::authority = msol_mint_authority
+    )]
+    pub msol_mint: Box<Account<'info, Mint>>,
+
+    /// CHECK: PDA
+    #[account(
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            StakePoolConfig::MSOL_MINT_AUTHORITY_SEED
+        ],
+        bump = stake_pool_config.msol_mint_authority_bump_seed
+    )]
+    pub msol_mint_authority: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub treasury_msol_account: Box<Account<'info, TokenAccount>>,
+
+    /// 传入时在汇率变化后同步刷新 mSOL 价格预言机
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            MsolPriceOracle::SEED
+        ],
+        bump = price_oracle.bump
+    )]
+    pub price_oracle: Option<Box<Account<'info, MsolPriceOracle>>>,
+
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            StakePoolConfig::OPERATIONAL_SOL_SEED
+        ],
+        bump = stake_pool_config.operational_sol_bump_seed
+    )]
+    pub operational_sol_account: SystemAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+
+impl<'info> UpdateStake<'info> {
+    pub fn process(&mut self, stake_index: u32, validator_index: u32) -> Result<()> {
+        require!(!self.stake_pool_config.paused, StakingError::ProgramIsPaused);
+
+        let mut stake = self.stake_pool_config.stake_system.get_checked(
+            &self.stake_list.to_account_info().data.borrow(),
+            stake_index,
+            self.stake_account.to_account_info().key
+        )?;
+        require_gt!(
+            self.clock.epoch,
+            stake.last_update_epoch,
+            StakingError::StakeAlreadyUpdated
+        );
+
+        let delegation = self.stake_account
+            .delegation()
+            .ok_or(StakingError::StakeNotDelegated)?;
+        let validator_vote = delegation.voter_pubkey;
+        let mut validator = self.stake_pool_config.validator_system.get_checked(
+            &self.validator_list.to_account_info().data.borrow(),
+            validator_index,
+            validator_vote
+        )?;
+
+        // 质押奖励由运行时同时计入账户余额与委托数量，直接转入账户的 lamports 只增加余额。
+        // 按委托数量计算收益，捐赠不会被当作收益铸造手续费、推高 mSOL 价格或触发调用奖励
+        let stake_lamports = delegation.stake;
+        let delegated_lamports = stake.last_update_delegated_lamports;
+        let rewards = stake_lamports.saturating_sub(delegated_lamports);
+        let lost_lamports = delegated_lamports.saturating_sub(stake_lamports);
+
+        let mut treasury_msol_cut = 0;
+        if rewards > 0 {
+            // 先计入扣除手续费后的奖励，treasury 按此时的价格获得手续费对应的 mSOL，
+            // 使手续费部分的奖励不被 mSOL 持有者分得
+            let fee_lamports = self.stake_pool_config.reward_fee.apply(rewards, Rounding::RoundDown);
+            self.stake_pool_config.validator_system.total_active_balance += rewards - fee_lamports;
+            treasury_msol_cut = self.stake_pool_config
+                .calc_msol_from_lamports(fee_lamports, Rounding::RoundDown)?;
+            self.stake_pool_config.validator_system.total_active_balance += fee_lamports;
+            validator.active_balance += rewards;
+
+            if treasury_msol_cut > 0 {
+                msg!("Mint {} mSOL to treasury as reward fee", treasury_msol_cut);
+                mint_to(
+                    CpiContext::new_with_signer(
+                        self.token_program.to_account_info(),
+                        MintTo {
+                            mint: self.msol_mint.to_account_info(),
+                            to: self.treasury_msol_account.to_account_info(),
+                            authority: self.msol_mint_authority.to_account_info()
+                        },
+                        &[&[
+                            self.stake_pool_config.key().as_ref(),
+                            StakePoolConfig::MSOL_MINT_AUTHORITY_SEED,
+                            &[self.stake_pool_config.msol_mint_authority_bump_seed]
+                        ]]
+                    ),
+                    treasury_msol_cut
+                )?;
+                self.stake_pool_config.on_msol_mint(treasury_msol_cut);
+            }
+        } else if lost_lamports > 0 {
+            msg!("Stake {} lost {} lamports", self.stake_account.key(), lost_lamports);
+            validator.active_balance = validator.active_balance.saturating_sub(lost_lamports);
+            self.stake_pool_config.validator_system.total_active_balance = self.stake_pool_config
+                .validator_system
+                .total_active_balance
+                .saturating_sub(lost_lamports);
+        }
+
+        self.stake_pool_config.validator_system.set(
+            &mut self.validator_list.to_account_info().data.borrow_mut(),
+            validator_index,
+            validator
+        )?;
+
+        stake.last_update_delegated_lamports = stake_lamports;
+        stake.last_update_epoch = self.clock.epoch;
+        self.stake_pool_config.stake_system.set(
+            &mut self.stake_list.to_account_info().data.borrow_mut(),
+            stake_index,
+            stake
+        )?;
+        self.stake_pool_config.on_stake_updated(self.clock.epoch);
+
+        self.stake_pool_config.refresh_price_oracle(
+            self.price_oracle.as_deref_mut().map(|price_oracle| &mut **price_oracle)
+        )?;
+
+        // 只有记录到收益时才支付奖励
+        let state = self.stake_pool_config.key();
+        let crank_bounty = if rewards > 0 {
+            pay_crank_bounty(
+                &mut self.stake_pool_config,
+                &state,
+                &self.operational_sol_account.to_account_info(),
+                &self.crank.to_account_info(),
+                &self.system_program.to_account_info()
+            )?
+        } else {
+            0
+        };
+
+        emit!(UpdateStakeEvent {
+            state: self.stake_pool_config.key(),
+            epoch: self.clock.epoch,
+            stake_index,
+            stake_account: self.stake_account.key(),
+            validator_index,
+            validator_vote,
+            delegated_lamports,
+            stake_lamports,
+            rewards,
+            lost_lamports,
+            treasury_msol_cut,
+            crank_bounty,
+            total_active_balance: self.stake_pool_config.validator_system.total_active_balance,
+            msol_price: self.stake_pool_config.msol_price,
+        });
+
+        Ok(())
+    }
+}
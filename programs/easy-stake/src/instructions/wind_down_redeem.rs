@@ -0,0 +1,161 @@
+//! 清退模式下按比例赎回 mSOL，SOL 从 reserve 支付，不收取手续费
+
+use anchor_lang::{
+    prelude::*,
+    system_program::{transfer, Transfer}
+};
+use anchor_spl::token::{burn, Burn, Mint, Token, TokenAccount};
+
+use crate::{
+    calc::Rounding,
+    error::StakingError,
+    require_lte,
+    state::{MsolPriceOracle, StakePoolConfig}
+};
+
+
+#[event]
+pub struct WindDownRedeemEvent {
+    pub state: Pubkey,
+    pub msol_owner: Pubkey,
+    pub user_msol_balance: u64,
+    pub user_sol_balance: u64,
+    pub reserve_balance: u64,
+    pub msol_amount: u64,
+    pub sol_amount: u64,
+    // MSOL price used
+    pub total_virtual_staked_lamports: u64,
+    pub msol_supply: u64,
+}
+
+
+#[derive(Accounts)]
+pub struct WindDownRedeem<'info> {
+    pub burn_msol_authority: Signer<'info>,
+
+    #[account(
+        mut,
+        has_one = msol_mint,
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    #[account(mut)]
+    pub msol_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        token::mint = msol_mint,
+        token::authority = burn_msol_authority
+    )]
+    pub burn_msol_from: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub transfer_sol_to: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            StakePoolConfig::RESERVE_SEED
+        ],
+        bump = stake_pool_config.reserve_bump_seed
+    )]
+    pub reserve_pda: SystemAccount<'info>,
+
+    /// 传入时在汇率变化后同步刷新 mSOL 价格预言机
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            MsolPriceOracle::SEED
+        ],
+        bump = price_oracle.bump
+    )]
+    pub price_oracle: Option<Box<Account<'info, MsolPriceOracle>>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>
+}
+
+
+impl<'info> WindDownRedeem<'info> {
+    pub fn process(&mut self, msol_amount: u64) -> Result<()> {
+        require!(!self.stake_pool_config.paused, StakingError::ProgramIsPaused);
+        self.stake_pool_config.check_winding_down()?;
+
+        let user_msol_balance = self.burn_msol_from.amount;
+        require_lte!(
+            msol_amount,
+            user_msol_balance,
+            StakingError::NotEnoughUserFunds
+        );
+
+        let total_virtual_staked_lamports = self.stake_pool_config.total_staked_lamports()?;
+        let msol_supply = self.stake_pool_config.msol_supply;
+
+        // 按当前价格折算，向下取整，不收取手续费
+        let sol_amount = self.stake_pool_config.msol_to_sol(msol_amount, Rounding::RoundDown)?;
+        require_gt!(sol_amount, 0, StakingError::WithdrawAmountIsTooLow);
+
+        // 只能使用已取回到 reserve 且不属于 LP 借款的 SOL，其余需等待质押取回
+        let reserve_balance = self.reserve_pda.lamports();
+        let redeemable = self.stake_pool_config
+            .stake_delta(reserve_balance)
+            .min(
+                self.stake_pool_config.available_reserve_balance
+                    .saturating_sub(self.stake_pool_config.liq_pool.lent_from_sol_leg)
+            );
+        require_lte!(sol_amount, redeemable, StakingError::InsufficientLiquidity);
+
+        burn(
+            CpiContext::new(
+                self.token_program.to_account_info(),
+                Burn {
+                    mint: self.msol_mint.to_account_info(),
+                    from: self.burn_msol_from.to_account_info(),
+                    authority: self.burn_msol_authority.to_account_info()
+                }
+            ),
+            msol_amount
+        )?;
+        self.stake_pool_config.on_msol_burn(msol_amount);
+
+        let user_sol_balance = self.transfer_sol_to.lamports();
+        transfer(
+            CpiContext::new_with_signer(
+                self.system_program.to_account_info(),
+                Transfer {
+                    from: self.reserve_pda.to_account_info(),
+                    to: self.transfer_sol_to.to_account_info()
+                },
+                &[&[
+                    self.stake_pool_config.key().as_ref(),
+                    StakePoolConfig::RESERVE_SEED,
+                    &[self.stake_pool_config.reserve_bump_seed]
+                ]]
+            ),
+            sol_amount
+        )?;
+        self.stake_pool_config.on_transfer_from_reserve(sol_amount);
+
+        self.stake_pool_config.refresh_price_oracle(
+            self.price_oracle.as_deref_mut().map(|price_oracle| &mut **price_oracle)
+        )?;
+
+        emit!(WindDownRedeemEvent {
+            state: self.stake_pool_config.key(),
+            msol_owner: self.burn_msol_authority.key(),
+            user_msol_balance,
+            user_sol_balance,
+            reserve_balance,
+            msol_amount,
+            sol_amount,
+            total_virtual_staked_lamports,
+            msol_supply,
+        });
+
+        Ok(())
+    }
+}
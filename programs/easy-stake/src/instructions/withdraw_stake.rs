@@ -0,0 +1,184 @@
+//! 取回冷却结束的质押账户中的全部 SOL 到 reserve（清退模式下任何人都可调用）
+
+use anchor_lang::{
+    prelude::*,
+    solana_program::sysvar::stake_history::ID as STAKE_HISTORY_ID
+};
+use anchor_spl::stake::{withdraw, Stake, StakeAccount, Withdraw};
+
+use crate::{
+    error::StakingError,
+    state::{
+        stake_system::{StakeList, StakeSystem},
+        validator_system::ValidatorList,
+        MsolPriceOracle,
+        StakePoolConfig
+    }
+};
+
+
+#[event]
+pub struct WithdrawStakeEvent {
+    pub state: Pubkey,
+    pub epoch: u64,
+    pub stake_index: u32,
+    pub stake_account: Pubkey,
+    pub validator_index: u32,
+    pub validator_vote: Pubkey,
+    /// 记录中的质押数量，从 active_balance 中扣除
+    pub delegated_lamports: u64,
+    /// 实际取回的 SOL，包含奖励与账户租金
+    pub withdrawn_lamports: u64,
+    pub total_active_balance: u64,
+    pub available_reserve_balance: u64,
+}
+
+
+#[derive(Accounts)]
+pub struct WithdrawStake<'info> {
+    #[account(
+        mut,
+        seeds = [StakePoolConfig::STAKE_POOL_CONFIG_SEED],
+        bump = stake_pool_config.stake_bump,
+    )]
+    pub stake_pool_config: Box<Account<'info, StakePoolConfig>>,
+
+    #[account(
+        mut,
+        address = stake_pool_config.validator_system.validator_list.account
+    )]
+    pub validator_list: Account<'info, ValidatorList>,
+
+    #[account(
+        mut,
+        address = stake_pool_config.stake_system.stake_list.account
+    )]
+    pub stake_list: Account<'info, StakeList>,
+
+    #[account(mut)]
+    pub stake_account: Box<Account<'info, StakeAccount>>,
+
+    /// CHECK: PDA
+    #[account(
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            StakeSystem::STAKE_WITHDRAW_SEED
+        ],
+        bump = stake_pool_config.stake_system.stake_withdraw_bump_seed
+    )]
+    pub stake_withdraw_authority: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            StakePoolConfig::RESERVE_SEED
+        ],
+        bump = stake_pool_config.reserve_bump_seed
+    )]
+    pub reserve_pda: SystemAccount<'info>,
+
+    /// CHECK: have no CPU budget to parse
+    #[account(address = STAKE_HISTORY_ID)]
+    pub stake_history: UncheckedAccount<'info>,
+
+    /// 传入时在汇率变化后同步刷新 mSOL 价格预言机
+    #[account(
+        mut,
+        seeds = [
+            stake_pool_config.key().as_ref(),
+            MsolPriceOracle::SEED
+        ],
+        bump = price_oracle.bump
+    )]
+    pub price_oracle: Option<Box<Account<'info, MsolPriceOracle>>>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub stake_program: Program<'info, Stake>,
+}
+
+
+impl<'info> WithdrawStake<'info> {
+    pub fn process(&mut self, stake_index: u32, validator_index: u32) -> Result<()> {
+        require!(!self.stake_pool_config.paused, StakingError::ProgramIsPaused);
+        self.stake_pool_config.check_winding_down()?;
+
+        let stake = self.stake_pool_config.stake_system.get_checked(
+            &self.stake_list.to_account_info().data.borrow(),
+            stake_index,
+            self.stake_account.to_account_info().key
+        )?;
+
+        let validator_vote = self.stake_account
+            .delegation()
+            .ok_or(StakingError::StakeNotDelegated)?
+            .voter_pubkey;
+        let mut validator = self.stake_pool_config.validator_system.get_checked(
+            &self.validator_list.to_account_info().data.borrow(),
+            validator_index,
+            validator_vote
+        )?;
+
+        // 未完成冷却时质押程序会拒绝全额取回
+        let withdrawn_lamports = self.stake_account.to_account_info().lamports();
+        msg!("Withdraw {} lamports from stake {}", withdrawn_lamports, self.stake_account.key());
+        withdraw(
+            CpiContext::new_with_signer(
+                self.stake_program.to_account_info(),
+                Withdraw {
+                    stake: self.stake_account.to_account_info(),
+                    withdrawer: self.stake_withdraw_authority.to_account_info(),
+                    to: self.reserve_pda.to_account_info(),
+                    clock: self.clock.to_account_info(),
+                    stake_history: self.stake_history.to_account_info()
+                },
+                &[&[
+                    self.stake_pool_config.key().as_ref(),
+                    StakeSystem::STAKE_WITHDRAW_SEED,
+                    &[self.stake_pool_config.stake_system.stake_withdraw_bump_seed]
+                ]]
+            ),
+            withdrawn_lamports,
+            None
+        )?;
+
+        // 记录的质押从 active_balance 移到 reserve，奖励与租金一并计入 reserve 归 mSOL 持有者
+        let delegated_lamports = stake.last_update_delegated_lamports;
+        validator.active_balance = validator.active_balance.saturating_sub(delegated_lamports);
+        self.stake_pool_config.validator_system.set(
+            &mut self.validator_list.to_account_info().data.borrow_mut(),
+            validator_index,
+            validator
+        )?;
+        self.stake_pool_config.validator_system.total_active_balance = self.stake_pool_config
+            .validator_system
+            .total_active_balance
+            .saturating_sub(delegated_lamports);
+        self.stake_pool_config.on_transfer_to_reserve(withdrawn_lamports);
+
+        self.stake_pool_config.stake_system.remove(
+            &mut self.stake_list.to_account_info().data.borrow_mut(),
+            stake_index
+        )?;
+        self.stake_pool_config.on_stake_record_removed(stake.last_update_epoch);
+
+        self.stake_pool_config.refresh_price_oracle(
+            self.price_oracle.as_deref_mut().map(|price_oracle| &mut **price_oracle)
+        )?;
+
+        emit!(WithdrawStakeEvent {
+            state: self.stake_pool_config.key(),
+            epoch: self.clock.epoch,
+            stake_index,
+            stake_account: self.stake_account.key(),
+            validator_index,
+            validator_vote,
+            delegated_lamports,
+            withdrawn_lamports,
+            total_active_balance: self.stake_pool_config.validator_system.total_active_balance,
+            available_reserve_balance: self.stake_pool_config.available_reserve_balance,
+        });
+
+        Ok(())
+    }
+}
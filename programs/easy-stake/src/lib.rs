@@ -7,19 +7,30 @@ pub mod error;
 pub mod instructions;
 pub mod state;
 pub mod calc;
+#[cfg(feature = "cpi")]
+pub mod return_data;
 
 use instructions::*;
 use error::StakingError;
+use state::{Fee, FeeAdjustment};
 
 declare_id!("J8iXwM3SQQpL4PhQ2wXZBWfZ7oFmNRdFZHnHHSr2yiUd");
 
 
-fn check_context<T>(ctx: &Context<T>) -> Result<()>
+fn check_program_id<T>(ctx: &Context<T>) -> Result<()>
 where T: Bumps {
     if !check_id(ctx.program_id) {
         return err!(StakingError::InvalidProgramId);
     }
 
+    Ok(())
+}
+
+// 只有 stake_reserve_batch 这类通过 remaining_accounts 传入可变数量账户的指令跳过该检查
+fn check_context<T>(ctx: &Context<T>) -> Result<()>
+where T: Bumps {
+    check_program_id(ctx)?;
+
     if !ctx.remaining_accounts.is_empty() {
         return err!(StakingError::UnexpectedAccount);
     }
@@ -38,29 +49,57 @@ pub mod easy_stake {
     }
 
     // 用户质押
-    pub fn deposit(ctx: Context<Deposit>, lamports: u64) -> Result<()> {
+    pub fn deposit(ctx: Context<Deposit>, lamports: u64) -> Result<DepositResult> {
         check_context(&ctx)?;
         ctx.accounts.process(lamports)
     }
 
     // 用户解质押
-    pub fn unstake(ctx: Context<Unstake>, msol_amount: u64) -> Result<()> {
+    pub fn unstake(ctx: Context<Unstake>, msol_amount: u64) -> Result<UnstakeResult> {
+        check_context(&ctx)?;
+        ctx.accounts.process(msol_amount)
+    }
+
+    // 用户使用 wSOL 代币账户质押，定价与事件同 deposit
+    pub fn deposit_wsol(ctx: Context<DepositWsol>, lamports: u64) -> Result<DepositResult> {
+        check_context(&ctx)?;
+        ctx.accounts.process(lamports)
+    }
+
+    // 用户解质押，SOL 以 wSOL 形式存入代币账户，定价与事件同 unstake
+    pub fn unstake_to_wsol(ctx: Context<UnstakeToWsol>, msol_amount: u64) -> Result<UnstakeResult> {
         check_context(&ctx)?;
         ctx.accounts.process(msol_amount)
     }
 
     // 质押池添加流动性(只能通过sol进行添加)
-    pub fn add_liquidity(ctx: Context<AddLiquidity>, lamports: u64) -> Result<()> {
+    pub fn add_liquidity(ctx: Context<AddLiquidity>, lamports: u64) -> Result<AddLiquidityResult> {
         check_context(&ctx)?;
         ctx.accounts.process(lamports)
     }
 
+    // 质押池添加流动性(使用 mSOL 添加，按当前 mSOL 价格折算)
+    pub fn add_liquidity_msol(ctx: Context<AddLiquidityMsol>, msol_amount: u64) -> Result<AddLiquidityResult> {
+        check_context(&ctx)?;
+        ctx.accounts.process(msol_amount)
+    }
+
     // 提取质押池代币
-    pub fn remove_liquidity(ctx: Context<RemoveLiquidity>, tokens: u64) -> Result<()> {
+    pub fn remove_liquidity(ctx: Context<RemoveLiquidity>, tokens: u64) -> Result<RemoveLiquidityResult> {
         check_context(&ctx)?;
         ctx.accounts.process(tokens)
     }
 
+    // 提取质押池代币，mSOL 部分在池内按流动性解质押换成 SOL，只支付 SOL
+    pub fn remove_liquidity_sol(
+        ctx: Context<RemoveLiquiditySol>,
+        tokens: u64,
+        min_sol_out: u64
+    ) -> Result<RemoveLiquiditySolResult> {
+        check_context(&ctx)?;
+        ctx.accounts.process(tokens, min_sol_out)
+    }
+
     // 添加验证者
     pub fn add_validator(ctx: Context<AddValidator>, score: u32) -> Result<()> {
         check_context(&ctx)?;
@@ -103,6 +142,204 @@ pub mod easy_stake {
         validator_index: u32
     ) -> Result<()> {
         check_context(&ctx)?;
-        ctx.accounts.process(validator_index)
+        ctx.accounts.process(validator_index, ctx.bumps)
+    }
+
+    // 一次向多个验证者质押，remaining_accounts 为 [validator_vote, stake_account] 对
+    pub fn stake_reserve_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, StakeReserveBatch<'info>>,
+        validator_indices: Vec<u32>
+    ) -> Result<()> {
+        check_program_id(&ctx)?;
+        ctx.accounts.process(validator_indices, ctx.remaining_accounts)
+    }
+
+    // 开启清退模式，禁止存入与新增质押，不可撤销
+    pub fn begin_wind_down(ctx: Context<BeginWindDown>) -> Result<()> {
+        check_context(&ctx)?;
+        ctx.accounts.process()
+    }
+
+    // 清退模式下解除质押（任何人都可调用）
+    pub fn deactivate_stake(ctx: Context<DeactivateStake>, stake_index: u32) -> Result<()> {
+        check_context(&ctx)?;
+        ctx.accounts.process(stake_index)
+    }
+
+    // 按质押账户余额记录质押奖励，每个质押账户每 epoch 一次（任何人都可调用）
+    pub fn update_stake(
+        ctx: Context<UpdateStake>,
+        stake_index: u32,
+        validator_index: u32
+    ) -> Result<()> {
+        check_context(&ctx)?;
+        ctx.accounts.process(stake_index, validator_index)
+    }
+
+    // 清退模式下取回冷却结束的质押到 reserve（任何人都可调用）
+    pub fn withdraw_stake(
+        ctx: Context<WithdrawStake>,
+        stake_index: u32,
+        validator_index: u32
+    ) -> Result<()> {
+        check_context(&ctx)?;
+        ctx.accounts.process(stake_index, validator_index)
+    }
+
+    // 清退模式下按比例赎回 mSOL，不收取手续费
+    pub fn wind_down_redeem(ctx: Context<WindDownRedeem>, msol_amount: u64) -> Result<()> {
+        check_context(&ctx)?;
+        ctx.accounts.process(msol_amount)
+    }
+
+    // 清退完成后关闭质押池并回收租金
+    pub fn close_pool(ctx: Context<ClosePool>) -> Result<()> {
+        check_context(&ctx)?;
+        ctx.accounts.process()
+    }
+
+    // 回收 mSOL leg 中积累的 mSOL，用 reserve 中的 SOL 补充 sol_leg（任何人都可调用）
+    pub fn recycle_msol_leg(ctx: Context<RecycleMsolLeg>) -> Result<()> {
+        check_context(&ctx)?;
+        ctx.accounts.process()
+    }
+
+    // 将 sol_leg 闲置流动性借给 reserve，或在 sol_leg 低于目标时收回（任何人都可调用）
+    pub fn rebalance_sol_leg(ctx: Context<RebalanceSolLeg>) -> Result<()> {
+        check_context(&ctx)?;
+        ctx.accounts.process()
+    }
+
+    // 从 sol_leg 借出闪电贷，同一交易中必须随后调用 flash_repay
+    pub fn flash_borrow(ctx: Context<FlashBorrow>, lamports: u64) -> Result<()> {
+        check_context(&ctx)?;
+        ctx.accounts.process(lamports)
+    }
+
+    // 归还闪电贷并支付手续费
+    pub fn flash_repay(ctx: Context<FlashRepay>) -> Result<()> {
+        check_context(&ctx)?;
+        ctx.accounts.process()
+    }
+
+    // 为合作方创建推荐账户
+    pub fn add_referral(ctx: Context<AddReferral>, fee_share: Fee) -> Result<()> {
+        check_context(&ctx)?;
+        ctx.accounts.process(fee_share, ctx.bumps)
+    }
+
+    // 为用户或程序设置费率覆盖，已存在时更新
+    pub fn set_fee_override(
+        ctx: Context<SetFeeOverride>,
+        key: Pubkey,
+        adjustment: FeeAdjustment
+    ) -> Result<()> {
+        check_context(&ctx)?;
+        ctx.accounts.process(key, adjustment, ctx.bumps)
+    }
+
+    // 删除费率覆盖
+    pub fn remove_fee_override(ctx: Context<RemoveFeeOverride>) -> Result<()> {
+        check_context(&ctx)?;
+        ctx.accounts.process()
+    }
+
+    // 将用户加入白名单（许可模式）
+    pub fn add_to_allowlist(ctx: Context<AddToAllowlist>, user: Pubkey) -> Result<()> {
+        check_context(&ctx)?;
+        ctx.accounts.process(user, ctx.bumps)
+    }
+
+    // 将用户移出白名单
+    pub fn remove_from_allowlist(ctx: Context<RemoveFromAllowlist>) -> Result<()> {
+        check_context(&ctx)?;
+        ctx.accounts.process()
+    }
+
+    // 创建用户存款记录，开启钱包存款限制后存款需要传入
+    pub fn init_user_deposit_record(ctx: Context<InitUserDepositRecord>) -> Result<()> {
+        check_context(&ctx)?;
+        ctx.accounts.process(ctx.bumps)
+    }
+
+    // 修改质押池配置
+    pub fn config_stake_pool(
+        ctx: Context<ConfigStakePool>,
+        params: ConfigStakePoolParams
+    ) -> Result<()> {
+        check_context(&ctx)?;
+        ctx.accounts.process(params)
+    }
+
+    // 修改流动性池配置
+    pub fn config_lp(ctx: Context<ConfigLp>, params: ConfigLpParams) -> Result<()> {
+        check_context(&ctx)?;
+        ctx.accounts.process(params)
+    }
+
+    // 质押报价，不修改任何状态，供 simulateTransaction 使用
+    pub fn quote_deposit(ctx: Context<QuoteDeposit>, lamports: u64) -> Result<DepositResult> {
+        check_context(&ctx)?;
+        ctx.accounts.process(lamports)
+    }
+
+    // 解质押报价，不修改任何状态
+    pub fn quote_unstake(ctx: Context<QuoteUnstake>, msol_amount: u64) -> Result<UnstakeResult> {
+        check_context(&ctx)?;
+        ctx.accounts.process(msol_amount)
+    }
+
+    // 添加流动性(SOL)报价，不修改任何状态
+    pub fn quote_add_liquidity(
+        ctx: Context<QuoteAddLiquidity>,
+        lamports: u64
+    ) -> Result<AddLiquidityResult> {
+        check_context(&ctx)?;
+        ctx.accounts.process(lamports)
+    }
+
+    // 提取流动性报价，不修改任何状态
+    pub fn quote_remove_liquidity(
+        ctx: Context<QuoteRemoveLiquidity>,
+        tokens: u64
+    ) -> Result<RemoveLiquidityResult> {
+        check_context(&ctx)?;
+        ctx.accounts.process(tokens)
+    }
+
+    // 添加流动性(mSOL)报价，不修改任何状态
+    pub fn quote_add_liquidity_msol(
+        ctx: Context<QuoteAddLiquidityMsol>,
+        msol_amount: u64
+    ) -> Result<AddLiquidityResult> {
+        check_context(&ctx)?;
+        ctx.accounts.process(msol_amount)
+    }
+
+    // 只提取 SOL 的提取流动性报价，不修改任何状态
+    pub fn quote_remove_liquidity_sol(
+        ctx: Context<QuoteRemoveLiquiditySol>,
+        tokens: u64
+    ) -> Result<RemoveLiquiditySolResult> {
+        check_context(&ctx)?;
+        ctx.accounts.process(tokens)
+    }
+
+    // 查询当前 mSOL 价格
+    pub fn get_msol_price(ctx: Context<GetMsolPrice>) -> Result<MsolPrice> {
+        check_context(&ctx)?;
+        ctx.accounts.process()
+    }
+
+    // 创建 mSOL 价格预言机账户
+    pub fn init_price_oracle(ctx: Context<InitPriceOracle>) -> Result<()> {
+        check_context(&ctx)?;
+        ctx.accounts.process(ctx.bumps)
+    }
+
+    // 更新 mSOL 价格预言机，任何人都可以调用
+    pub fn update_price_oracle(ctx: Context<UpdatePriceOracle>) -> Result<()> {
+        check_context(&ctx)?;
+        ctx.accounts.process()
     }
 }
@@ -0,0 +1,58 @@
+//! CPI 调用方解码本程序 return data 的辅助函数
+//!
+//! deposit、unstake、流动性相关指令以及对应的报价指令都会把结果写入 return data，
+//! 调用方在 CPI 返回后立即调用对应函数即可拿到结果，无需重新读取账户余额
+
+use anchor_lang::{prelude::*, solana_program::program::get_return_data};
+
+use crate::{
+    error::StakingError,
+    instructions::{
+        AddLiquidityResult,
+        DepositResult,
+        MsolPrice,
+        RemoveLiquidityResult,
+        RemoveLiquiditySolResult,
+        UnstakeResult
+    },
+    ID
+};
+
+
+/// 读取最近一次 CPI 写入的 return data，要求其由本程序写入
+pub fn get_result<T: AnchorDeserialize>() -> Result<T> {
+    let (program_id, data) = get_return_data().ok_or(StakingError::InvalidReturnData)?;
+    require_keys_eq!(program_id, ID, StakingError::InvalidReturnData);
+
+    T::try_from_slice(&data).map_err(|_| error!(StakingError::InvalidReturnData))
+}
+
+/// deposit、deposit_wsol 的结果
+pub fn get_deposit_result() -> Result<DepositResult> {
+    get_result()
+}
+
+/// unstake、unstake_to_wsol 的结果
+pub fn get_unstake_result() -> Result<UnstakeResult> {
+    get_result()
+}
+
+/// add_liquidity、add_liquidity_msol 的结果
+pub fn get_add_liquidity_result() -> Result<AddLiquidityResult> {
+    get_result()
+}
+
+/// remove_liquidity 的结果
+pub fn get_remove_liquidity_result() -> Result<RemoveLiquidityResult> {
+    get_result()
+}
+
+/// remove_liquidity_sol 的结果
+pub fn get_remove_liquidity_sol_result() -> Result<RemoveLiquiditySolResult> {
+    get_result()
+}
+
+/// get_msol_price 的结果
+pub fn get_msol_price_result() -> Result<MsolPrice> {
+    get_result()
+}
@@ -4,17 +4,34 @@ use std::mem::MaybeUninit;
 use anchor_lang::{prelude::*, solana_program::native_token::LAMPORTS_PER_SOL};
 
 pub mod fee;
+pub mod fee_curve;
 pub mod stake_system;
 pub mod validator_system;
 pub mod list;
 pub mod liq_pool;
+pub mod price_oracle;
+pub mod referral;
+pub mod fee_override;
+pub mod allowlist;
+pub mod user_deposit;
 
 pub use fee::Fee;
+pub use fee_curve::{FeeCurve, FeeCurveKind, FeeCurvePoint};
 pub use stake_system::StakeSystem;
 pub use validator_system::ValidatorSystem;
 pub use liq_pool::LiqPool;
+pub use price_oracle::{MsolPriceOracle, PriceRecord};
+pub use referral::ReferralAccount;
+pub use fee_override::{FeeAdjustment, FeeOverride};
+pub use allowlist::AllowlistEntry;
+pub use user_deposit::UserDepositRecord;
 
-use crate::{calc::{shares_from_value, value_from_shares}, error::StakingError, require_lte, ID};
+use crate::{
+    calc::{shares_from_value, value_from_shares, Rounding}, 
+    error::StakingError, 
+    require_lte, 
+    ID
+};
 
 
 #[account]
@@ -28,7 +45,7 @@ pub struct StakePoolConfig {
     /// 管理员地址，拥有修改参数权限
     pub admin_authority: Pubkey,
 
-    /// 用于操作系统 SOL 支付的账户地址
+    /// 用于操作系统 SOL 支付的 PDA 账户地址，接收租金退款并支付 crank 的质押账户租金
     pub operational_sol_account: Pubkey,
 
     /// 接收手续费的 mSOL 国库账户
@@ -61,7 +78,7 @@ pub struct StakePoolConfig {
     /// 当前 mSOL 的总供应量
     pub msol_supply: u64,
 
-    /// 当前 1 mSOL 对应的 SOL 价值（仅用于显示）
+    /// 当前 1 mSOL 对应的 SOL 价值，按 PRICE_DENOMINATOR 放大，由 update_price_oracle 更新
     pub msol_price: u64,
 
     /// 用户最小存入 SOL 限额
@@ -87,6 +104,88 @@ pub struct StakePoolConfig {
 
     /// 每个 epoch 允许移动的最大 stake 数量
     pub max_stake_moved_per_epoch: Fee,
+
+    /// epoch 结束前窗口期内存款额外收取的手续费，SOL 留在 reserve 中归现有 mSOL 持有者，
+    /// 使临近奖励入账时存入、入账后立即解质押的套利无利可图
+    pub epoch_end_deposit_fee: Fee,
+
+    /// epoch 最后多少个 slot 内收取 epoch_end_deposit_fee，0 表示不收取。
+    /// 窗口期持续到新 epoch 中所有质押账户的收益都由 update_stake 入账为止
+    pub epoch_end_window_slots: u64,
+
+    /// 是否开启交易检查：禁止同一交易中同时存入与取出，禁止其他程序通过 CPI 调用存取指令
+    pub transaction_guard_enabled: bool,
+
+    /// 许可模式：开启后存取与流动性操作都要求调用者在白名单中，初始化时设置，之后可由管理员切换
+    pub permissioned: bool,
+
+    /// 管理白名单的权限地址
+    pub allowlist_authority: Pubkey,
+
+    /// 每个 epoch 允许存入的 SOL 总量，u64::MAX 表示不限制
+    pub epoch_deposit_limit: u64,
+
+    /// 每个钱包每个 epoch 允许存入的 SOL，u64::MAX 表示不限制；限制时存款需传入用户存款记录账户
+    pub user_epoch_deposit_limit: u64,
+
+    /// epoch_deposited 所属的 epoch
+    pub deposit_epoch: u64,
+
+    /// deposit_epoch 中已存入的 SOL 总量
+    pub epoch_deposited: u64,
+
+    /// 清退模式：由 begin_wind_down 开启且不可撤销。开启后禁止存入与新增质押，
+    /// 任何人都可以解除并取回全部质押，mSOL 持有者可无手续费按比例赎回 reserve 中的 SOL
+    pub wind_down: bool,
+
+    /// operational_sol_account 的 bump，用于 PDA 派生签名
+    pub operational_sol_bump_seed: u8,
+
+    /// 每个 epoch 允许从 operational_sol_account 支出的 SOL，u64::MAX 表示不限制
+    pub operational_budget_per_epoch: u64,
+
+    /// operational_spent 所属的 epoch
+    pub operational_spent_epoch: u64,
+
+    /// operational_spent_epoch 中已从 operational_sol_account 支出的 SOL
+    pub operational_spent: u64,
+
+    /// 每创建一个质押账户（stake_reserve / stake_reserve_batch）或记录到一次收益（update_stake）
+    /// 支付给调用者的奖励（lamports），0 表示不支付。
+    /// 从 operational_sol_account 支付，与质押账户租金共用 operational_budget_per_epoch
+    pub crank_bounty: u64,
+
+    /// 每个 epoch 支付的调用奖励总额上限，与 operational_budget_per_epoch 同时生效
+    pub crank_bounty_cap_per_epoch: u64,
+
+    /// crank_bounty_paid 所属的 epoch
+    pub crank_bounty_paid_epoch: u64,
+
+    /// crank_bounty_paid_epoch 中已支付的调用奖励
+    pub crank_bounty_paid: u64,
+
+    /// 用户存款由 mSOL leg 兑付时，对兑付部分收取的手续费
+    pub deposit_swap_fee: Fee,
+
+    /// sol_leg 超过目标流动性后额外保留的缓冲（占 lp_liquidity_target 的比例），
+    /// 超出目标 + 缓冲的部分才会借给 reserve 参与质押
+    pub lending_buffer: Fee,
+
+    /// 闪电贷手续费
+    pub flash_loan_fee: Fee,
+
+    /// 当前交易中尚未归还的闪电贷金额，0 表示没有进行中的闪电贷
+    pub flash_loan_amount: u64,
+
+    /// stakes_updated 所属的 epoch
+    pub stakes_updated_epoch: u64,
+
+    /// stakes_updated_epoch 中 last_update_epoch 已更新为该 epoch 的质押账户数量，
+    /// 小于质押列表长度时说明还有质押账户的收益没有入账
+    pub stakes_updated: u32,
+
+    /// 流动性解质押手续费曲线，由管理员配置。定长结构，固定放在账户末尾
+    pub fee_curve: FeeCurve,
 }
 
 
@@ -105,14 +204,22 @@ impl StakePoolConfig {
     pub const STAKE_LIST_SEED: &'static [u8] = b"stake_list";
     /// 验证者列表 PDA 派生种子字符串
     pub const VALIDATOR_LIST_SEED: &'static [u8] = b"validator_list";
+    /// operational_sol_account PDA 派生种子
+    pub const OPERATIONAL_SOL_SEED: &'static [u8] = b"operational_sol";
     /// 用于托管mSOL的Token PDA账户 种子
     pub const TREASURY_MSOL_SEED: &'static [u8] = b"treasury_msol";
+    /// wSOL 存款时临时解包用的 Token PDA 账户 种子
+    pub const WSOL_TEMP_SEED: &'static [u8] = b"wsol_temp";
     /// 最大奖励手续费，单位为基点（1000 = 10%）
     pub const MAX_REWARD_FEE: Fee = Fee::from_basis_points(1_000);
+    /// epoch 结束前窗口期存款手续费上限（100 = 1%）
+    pub const MAX_EPOCH_END_DEPOSIT_FEE: Fee = Fee::from_basis_points(100);
     /// 最大单笔提现金额，单位为 lamports（0.1 SOL）
     pub const MAX_WITHDRAW_ATOM: u64 = LAMPORTS_PER_SOL / 10;
     /// 最小质押下限，单位为 lamports（0.01 SOL）
     pub const MIN_STAKE_LOWER_LIMIT: u64 = LAMPORTS_PER_SOL / 100;
+    /// 初始化时每个 epoch 的调用奖励总额上限，单位为 lamports（0.01 SOL）
+    pub const DEFAULT_CRANK_BOUNTY_CAP_PER_EPOCH: u64 = LAMPORTS_PER_SOL / 100;
 
 
     /// 获取 StakePoolConfig 结构体在链上账户中所需的总存储空间（单位：字节）。
@@ -136,31 +243,105 @@ impl StakePoolConfig {
         self.msol_supply += amount
     }
 
+    pub fn on_msol_burn(&mut self, amount: u64) {
+        self.msol_supply -= amount
+    }
+
     pub fn on_transfer_to_reserve(&mut self, amount: u64) {
         self.available_reserve_balance += amount
     }
 
-    pub fn total_staked_lamports(&self) -> u64 {
-        self.validator_system.total_active_balance 
-            + self.available_reserve_balance
+    pub fn on_transfer_from_reserve(&mut self, amount: u64) {
+        self.available_reserve_balance -= amount
+    }
+
+    /// sol_leg 的 SOL 借给 reserve 后记账
+    pub fn on_lend_from_sol_leg(&mut self, amount: u64) {
+        self.liq_pool.lent_from_sol_leg += amount;
+        self.on_transfer_to_reserve(amount);
+    }
+
+    /// 借出的 SOL 归还 sol_leg 后记账。
+    /// from_reserve 为 false 时 SOL 由新进入 reserve 的存款直接转入 sol_leg，未经过 reserve
+    pub fn on_repay_to_sol_leg(&mut self, amount: u64, from_reserve: bool) {
+        self.liq_pool.lent_from_sol_leg -= amount;
+        if from_reserve {
+            self.on_transfer_from_reserve(amount);
+        }
+    }
+
+    /// mSOL 持有者拥有的 SOL 总量。从 sol_leg 借入 reserve 的 SOL 虽然参与质押，
+    /// 但属于 LP，需要扣除；借出的 SOL 超过质押与 reserve 之和说明记账有误，返回错误
+    pub fn total_staked_lamports(&self) -> Result<u64> {
+        Ok(self.validator_system.total_active_balance
+            .checked_add(self.available_reserve_balance)
+            .and_then(|total| total.checked_sub(self.liq_pool.lent_from_sol_leg))
+            .ok_or(StakingError::CalculationFailure)?)
     }
 
-    pub fn calc_msol_from_lamports(&self, stake_lamports: u64) -> Result<u64> {
+    pub fn calc_msol_from_lamports(&self, stake_lamports: u64, rounding: Rounding) -> Result<u64> {
         shares_from_value(
             stake_lamports, 
-            self.total_staked_lamports(), 
-        self.msol_supply
+            self.total_staked_lamports()?, 
+            self.msol_supply,
+            rounding
         )
     }
 
-    pub fn msol_to_sol(&self, msol_amount: u64) -> Result<u64> {
+    pub fn msol_to_sol(&self, msol_amount: u64, rounding: Rounding) -> Result<u64> {
         value_from_shares(
             msol_amount, 
-            self.total_staked_lamports(), 
-            self.msol_supply
+            self.total_staked_lamports()?, 
+            self.msol_supply,
+            rounding
         )
     }
 
+    /// 当前 1 mSOL 可兑换的 SOL，按 PRICE_DENOMINATOR 放大，向下取整
+    pub fn current_msol_price(&self) -> Result<u64> {
+        self.msol_to_sol(Self::PRICE_DENOMINATOR, Rounding::RoundDown)
+    }
+
+    /// 汇率可能变化的指令在修改状态后调用：同步 msol_price，并在传入价格预言机时写入最新价格，
+    /// 避免预言机只能等待 update_price_oracle 才能反映新的汇率
+    pub fn refresh_price_oracle(
+        &mut self,
+        price_oracle: Option<&mut MsolPriceOracle>
+    ) -> Result<()> {
+        let price = self.current_msol_price()?;
+        self.msol_price = price;
+
+        if let Some(price_oracle) = price_oracle {
+            price_oracle.update(
+                price,
+                self.total_staked_lamports()?,
+                self.msol_supply,
+                &Clock::get()?
+            );
+        }
+
+        Ok(())
+    }
+
+    /// 流动性池总价值：sol_leg 可用余额 + 借给 reserve 的 SOL + mSOL leg 按当前价格折算的价值。
+    /// 用于铸造 LP，mSOL 价值向上取整，使用户获得的 LP 偏少一侧
+    pub fn liq_pool_value(&self, sol_leg_balance: u64, msol_leg_balance: u64) -> Result<u64> {
+        let sol_leg_available_balance = sol_leg_balance.saturating_sub(self.rent_exempt_for_token_acc);
+        let msol_leg_value = self.msol_to_sol(msol_leg_balance, Rounding::RoundUp)?;
+
+        msg!(
+            "liq_pool SOL:{}, lent SOL:{}, liq_pool mSOL value:{}",
+            sol_leg_available_balance,
+            self.liq_pool.lent_from_sol_leg,
+            msol_leg_value
+        );
+
+        sol_leg_available_balance
+            .checked_add(self.liq_pool.lent_from_sol_leg)
+            .and_then(|value| value.checked_add(msol_leg_value))
+            .ok_or(error!(StakingError::MathOverflow))
+    }
+
     pub fn check_staking_cap(&self, transfering_lamports: u64) -> Result<()> {
         let result_amount = self.validator_system.total_active_balance 
             + transfering_lamports;
@@ -174,7 +355,187 @@ impl StakePoolConfig {
         Ok(())
     }
 
+    /// 当前 slot 位于 epoch 结束前窗口期内，或已进入新 epoch 但还有质押账户的收益没有入账时
+    /// 返回 epoch_end_deposit_fee，否则为 0。收益由任何人都可调用的 update_stake 在边界之后入账，
+    /// 边界之后、入账之前存入同样可以分走奖励
+    pub fn current_epoch_end_deposit_fee(&self) -> Result<Fee> {
+        if self.epoch_end_window_slots == 0 {
+            return Ok(Fee::from_basis_points(0));
+        }
+
+        let clock = Clock::get()?;
+        let last_slot = EpochSchedule::get()?.get_last_slot_in_epoch(clock.epoch);
+
+        if last_slot.saturating_sub(clock.slot) < self.epoch_end_window_slots
+            || self.rewards_pending(clock.epoch)
+        {
+            Ok(self.epoch_end_deposit_fee)
+        } else {
+            Ok(Fee::from_basis_points(0))
+        }
+    }
+
+    /// epoch 中 last_update_epoch 已更新为该 epoch 的质押账户数量，进入新 epoch 后计数归零
+    pub fn stakes_updated_at(&self, epoch: u64) -> u32 {
+        if self.stakes_updated_epoch == epoch {
+            self.stakes_updated
+        } else {
+            0
+        }
+    }
+
+    /// 质押账户的 last_update_epoch 更新为 epoch 后记账：update_stake 入账收益或新建质押账户
+    pub fn on_stake_updated(&mut self, epoch: u64) {
+        self.stakes_updated = self.stakes_updated_at(epoch) + 1;
+        self.stakes_updated_epoch = epoch;
+    }
+
+    /// 移除 last_update_epoch 为 record_epoch 的质押记录后记账
+    pub fn on_stake_record_removed(&mut self, record_epoch: u64) {
+        if self.stakes_updated_epoch == record_epoch {
+            self.stakes_updated = self.stakes_updated.saturating_sub(1);
+        }
+    }
+
+    /// 还有质押账户的 last_update_epoch 早于 epoch，即其收益还没有由 update_stake 入账
+    pub fn rewards_pending(&self, epoch: u64) -> bool {
+        self.stakes_updated_at(epoch) < self.stake_system.stake_list.count
+    }
+
+    /// epoch 中已存入的 SOL，进入新 epoch 后计数归零
+    pub fn epoch_deposited_at(&self, epoch: u64) -> u64 {
+        if self.deposit_epoch == epoch {
+            self.epoch_deposited
+        } else {
+            0
+        }
+    }
+
+    pub fn check_epoch_deposit_limit(&self, lamports: u64, epoch: u64) -> Result<()> {
+        require_lte!(
+            self.epoch_deposited_at(epoch).saturating_add(lamports),
+            self.epoch_deposit_limit,
+            StakingError::EpochDepositLimitExceeded
+        );
+
+        Ok(())
+    }
+
+    pub fn on_epoch_deposit(&mut self, lamports: u64, epoch: u64) {
+        self.epoch_deposited = self.epoch_deposited_at(epoch) + lamports;
+        self.deposit_epoch = epoch;
+    }
+
+    /// epoch 中已从 operational_sol_account 支出的 SOL，进入新 epoch 后计数归零
+    pub fn operational_spent_at(&self, epoch: u64) -> u64 {
+        if self.operational_spent_epoch == epoch {
+            self.operational_spent
+        } else {
+            0
+        }
+    }
+
+    /// 本 epoch 还能从 operational_sol_account 支出的 SOL，受预算与账户余额（保留免租金）限制
+    pub fn operational_spendable(&self, operational_sol_balance: u64, epoch: u64) -> Result<u64> {
+        let rent_exempt = Rent::get()?.minimum_balance(0);
+
+        Ok(self.operational_budget_per_epoch
+            .saturating_sub(self.operational_spent_at(epoch))
+            .min(operational_sol_balance.saturating_sub(rent_exempt)))
+    }
+
+    pub fn on_operational_spend(&mut self, lamports: u64, epoch: u64) {
+        self.operational_spent = self.operational_spent_at(epoch) + lamports;
+        self.operational_spent_epoch = epoch;
+    }
+
+    /// epoch 中已支付的调用奖励，进入新 epoch 后计数归零
+    pub fn crank_bounty_paid_at(&self, epoch: u64) -> u64 {
+        if self.crank_bounty_paid_epoch == epoch {
+            self.crank_bounty_paid
+        } else {
+            0
+        }
+    }
+
+    /// 本次可支付的调用奖励，受本 epoch 奖励上限与 operational_sol_account 可支出余额限制
+    pub fn crank_bounty_payable(&self, operational_sol_balance: u64, epoch: u64) -> Result<u64> {
+        Ok(self.crank_bounty
+            .min(self.crank_bounty_cap_per_epoch.saturating_sub(self.crank_bounty_paid_at(epoch)))
+            .min(self.operational_spendable(operational_sol_balance, epoch)?))
+    }
+
+    pub fn on_crank_bounty_paid(&mut self, lamports: u64, epoch: u64) {
+        self.crank_bounty_paid = self.crank_bounty_paid_at(epoch) + lamports;
+        self.crank_bounty_paid_epoch = epoch;
+        self.on_operational_spend(lamports, epoch);
+    }
+
+    pub fn check_not_winding_down(&self) -> Result<()> {
+        require!(!self.wind_down, StakingError::PoolIsWindingDown);
+
+        Ok(())
+    }
+
+    pub fn check_winding_down(&self) -> Result<()> {
+        require!(self.wind_down, StakingError::PoolIsNotWindingDown);
+
+        Ok(())
+    }
+
+    /// 从可用余额为 available_lamports 的 sol_leg 中取走 remove_lamports 时的流动性解质押费率
+    pub fn liquid_unstake_fee(&self, available_lamports: u64, remove_lamports: u64) -> Result<Fee> {
+        self.liq_pool.liquid_unstake_fee(&self.fee_curve, available_lamports, remove_lamports)
+    }
+
+    pub fn validate_fee_curve(&self) -> Result<()> {
+        self.fee_curve
+            .validate(self.liq_pool.lp_min_fee, self.liq_pool.lp_max_fee)
+            .map_err(|e| e.with_source(source!()))
+    }
+
+    /// 流动性池的存款兑付、借出缓冲与闪电贷费率
+    pub fn validate_liq_pool_fees(&self) -> Result<()> {
+        self.deposit_swap_fee
+            .check()
+            .map_err(|e| e.with_source(source!()))?;
+        self.lending_buffer
+            .check()
+            .map_err(|e| e.with_source(source!()))?;
+        self.flash_loan_fee
+            .check()
+            .map_err(|e| e.with_source(source!()))?;
+
+        require_lte!(
+            self.deposit_swap_fee,
+            LiqPool::MAX_FEE,
+            StakingError::DepositSwapFeeIsTooHigh
+        );
+        require_lte!(
+            self.flash_loan_fee,
+            LiqPool::MAX_FEE,
+            StakingError::FlashLoanFeeIsTooHigh
+        );
+        Ok(())
+    }
+
+    /// 闪电贷未归还期间 sol_leg 余额不可信，禁止其他池子操作重入
+    pub fn check_no_flash_loan(&self) -> Result<()> {
+        require_eq!(self.flash_loan_amount, 0, StakingError::FlashLoanActive);
+
+        Ok(())
+    }
+
+    /// sol_leg 超出目标流动性与 lending_buffer 之外、可以借给 reserve 的 SOL
+    pub fn lendable_lamports(&self, sol_leg_available_balance: u64) -> u64 {
+        self.liq_pool.lendable_lamports(self.lending_buffer, sol_leg_available_balance)
+    }
+
+    /// reserve 中可以转出的 SOL。直接转入 reserve 的捐赠不计入 available_reserve_balance，
+    /// 不能被质押或转出，否则 available_reserve_balance 会被扣成负数
     pub fn stake_delta(&self, reserve_balance: u64) -> u64 {
-        reserve_balance.saturating_sub(self.rent_exempt_for_token_acc)
+        reserve_balance
+            .saturating_sub(self.rent_exempt_for_token_acc)
+            .min(self.available_reserve_balance)
     }
 }
@@ -0,0 +1,24 @@
+//! 许可模式下的白名单账户
+
+use anchor_lang::prelude::*;
+
+
+/// 存在即表示 user 已通过审核，可以在许可模式下存取
+#[account]
+pub struct AllowlistEntry {
+    /// 所属质押池配置账户
+    pub state: Pubkey,
+
+    /// 被允许的用户钱包，参与 PDA 派生
+    pub user: Pubkey,
+
+    pub bump: u8,
+}
+
+
+impl AllowlistEntry {
+    /// 白名单 PDA 派生种子
+    pub const SEED: &'static [u8] = b"allowlist";
+    /// 账户空间，包含 8 字节 discriminator
+    pub const LEN: usize = 8 + 32 * 2 + 1;
+}
@@ -2,7 +2,7 @@ use std::fmt::{Display, Formatter};
 
 use anchor_lang::prelude::*;
 
-use crate::{error::StakingError, require_lte};
+use crate::{calc::Rounding, error::StakingError, require_lte};
 
 
 #[derive(
@@ -42,7 +42,13 @@ impl Fee {
         Ok(())
     }
 
-    pub fn apply(&self, lamports: u64) -> u64 {
-        (lamports as u128 * self.basis_points as u128 / Self::MAX_BASIS_POINTS as u128) as u64
+    /// 按费率计算手续费；费率不超过 100%，结果不会超过 lamports
+    pub fn apply(&self, lamports: u64, rounding: Rounding) -> u64 {
+        let product = lamports as u128 * self.basis_points as u128;
+        let denominator = Self::MAX_BASIS_POINTS as u128;
+        (match rounding {
+            Rounding::RoundDown => product / denominator,
+            Rounding::RoundUp => product.div_ceil(denominator),
+        }) as u64
     }
 }
@@ -0,0 +1,402 @@
+//! 流动性解质押手续费曲线
+
+use anchor_lang::prelude::*;
+
+use crate::{calc::{proportional, Rounding}, error::StakingError, require_lte};
+
+use super::Fee;
+
+
+/// 分段线性曲线最多支持的中间断点数量
+pub const MAX_FEE_CURVE_POINTS: usize = 4;
+
+
+#[derive(
+    Clone, Copy, Debug, Default, AnchorSerialize, AnchorDeserialize, PartialEq, Eq
+)]
+pub struct FeeCurvePoint {
+    /// 剩余流动性占 lp_liquidity_target 的比例（基点，10000 = 达到目标）
+    pub liquidity: Fee,
+
+    /// 剩余流动性处于该位置时的费率
+    pub fee: Fee,
+}
+
+
+/// 曲线类型。repr(u8) 保证全零内存对应 Linear，StakePoolConfig::serialized_len 依赖这一点
+#[repr(u8)]
+#[derive(
+    Clone, Copy, Debug, Default, AnchorSerialize, AnchorDeserialize, PartialEq, Eq
+)]
+pub enum FeeCurveKind {
+    /// 在 lp_max_fee 与 lp_min_fee 之间线性插值
+    #[default]
+    Linear,
+
+    /// 经过 points 中前 count 个断点的折线，断点按流动性递增排列
+    PiecewiseLinear,
+
+    /// 距 lp_max_fee 的差额随流动性缺口指数衰减，缺口每增加 half_life（占目标的基点）减半，
+    /// 池子刚开始被抽走时费率就快速上升，而不是等到接近枯竭
+    Exponential,
+}
+
+
+/// 手续费随 sol_leg 剩余流动性变化的曲线。
+/// 所有曲线的两个端点固定：流动性为 0 时为 lp_max_fee，达到 lp_liquidity_target 时为 lp_min_fee。
+/// 所有曲线类型共用同一个定长结构，序列化长度不随类型变化，
+/// 放在 StakePoolConfig 末尾，切换曲线不会移动其他字段的偏移
+#[derive(
+    Clone, Copy, Debug, Default, AnchorSerialize, AnchorDeserialize, PartialEq, Eq
+)]
+pub struct FeeCurve {
+    pub kind: FeeCurveKind,
+
+    /// PiecewiseLinear 的断点，其他类型必须全部为零
+    pub points: [FeeCurvePoint; MAX_FEE_CURVE_POINTS],
+
+    /// PiecewiseLinear 使用的断点数量，其他类型必须为 0
+    pub count: u8,
+
+    /// Exponential 的半衰期（占目标的基点），其他类型必须为 0
+    pub half_life: Fee,
+}
+
+
+impl FeeCurve {
+    /// Q32 定点数中的 1
+    const ONE: u128 = 1 << 32;
+
+    pub const fn linear() -> Self {
+        Self {
+            kind: FeeCurveKind::Linear,
+            points: [FeeCurvePoint {
+                liquidity: Fee::from_basis_points(0),
+                fee: Fee::from_basis_points(0)
+            }; MAX_FEE_CURVE_POINTS],
+            count: 0,
+            half_life: Fee::from_basis_points(0),
+        }
+    }
+
+    pub fn piecewise_linear(points: &[FeeCurvePoint]) -> Self {
+        let mut curve = Self {
+            kind: FeeCurveKind::PiecewiseLinear,
+            count: points.len() as u8,
+            ..Self::linear()
+        };
+        for (slot, point) in curve.points.iter_mut().zip(points) {
+            *slot = *point;
+        }
+
+        curve
+    }
+
+    pub const fn exponential(half_life: Fee) -> Self {
+        Self {
+            kind: FeeCurveKind::Exponential,
+            half_life,
+            ..Self::linear()
+        }
+    }
+
+    pub fn validate(&self, min_fee: Fee, max_fee: Fee) -> Result<()> {
+        let unused_points_from = match self.kind {
+            FeeCurveKind::PiecewiseLinear => self.count as usize,
+            _ => 0,
+        };
+        require_lte!(
+            unused_points_from,
+            MAX_FEE_CURVE_POINTS,
+            StakingError::InvalidFeeCurve
+        );
+        // 未使用的字段必须为零，同一条曲线只有一种表示
+        require!(
+            self.points[unused_points_from..]
+                .iter()
+                .all(|point| *point == FeeCurvePoint::default()),
+            StakingError::InvalidFeeCurve
+        );
+        if self.kind != FeeCurveKind::PiecewiseLinear {
+            require_eq!(self.count, 0, StakingError::InvalidFeeCurve);
+        }
+        if self.kind != FeeCurveKind::Exponential {
+            require_eq!(self.half_life.basis_points, 0, StakingError::InvalidFeeCurve);
+        }
+
+        match self.kind {
+            FeeCurveKind::Linear => {}
+            FeeCurveKind::PiecewiseLinear => {
+                // 从流动性为 0、费率为 lp_max_fee 的端点开始，
+                // 断点流动性必须严格递增、费率不得上升，保证池子越枯竭费率越高
+                let mut prev = FeeCurvePoint { liquidity: Fee::from_basis_points(0), fee: max_fee };
+                for point in &self.points[..self.count as usize] {
+                    require_gt!(point.liquidity, prev.liquidity, StakingError::InvalidFeeCurve);
+                    require_gt!(
+                        Fee::MAX_BASIS_POINTS,
+                        point.liquidity.basis_points,
+                        StakingError::InvalidFeeCurve
+                    );
+                    require_lte!(point.fee, prev.fee, StakingError::InvalidFeeCurve);
+                    prev = *point;
+                }
+                require_gte!(prev.fee, min_fee, StakingError::InvalidFeeCurve);
+            }
+            FeeCurveKind::Exponential => {
+                require_gt!(self.half_life.basis_points, 0, StakingError::InvalidFeeCurve);
+                require_lte!(
+                    self.half_life.basis_points,
+                    Fee::MAX_BASIS_POINTS,
+                    StakingError::InvalidFeeCurve
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 计算剩余流动性为 lamports 时的费率
+    pub fn fee(
+        &self,
+        lamports: u64,
+        liquidity_target: u64,
+        min_fee: Fee,
+        max_fee: Fee
+    ) -> Result<Fee> {
+        if lamports >= liquidity_target {
+            return Ok(min_fee);
+        }
+
+        let delta = max_fee.basis_points.saturating_sub(min_fee.basis_points) as u64;
+
+        // 从最大费率中扣减的部分向下取整，费率偏向池子一侧
+        let fee = match self.kind {
+            FeeCurveKind::Linear => {
+                max_fee.basis_points - proportional(
+                    delta,
+                    lamports,
+                    liquidity_target,
+                    Rounding::RoundDown
+                )? as u32
+            }
+            FeeCurveKind::PiecewiseLinear => {
+                let liquidity = proportional(
+                    lamports,
+                    Fee::MAX_BASIS_POINTS as u64,
+                    liquidity_target,
+                    Rounding::RoundDown
+                )? as u32;
+
+                let mut left = FeeCurvePoint { liquidity: Fee::from_basis_points(0), fee: max_fee };
+                let mut right = FeeCurvePoint {
+                    liquidity: Fee::from_basis_points(Fee::MAX_BASIS_POINTS),
+                    fee: min_fee
+                };
+                for point in &self.points[..(self.count as usize).min(MAX_FEE_CURVE_POINTS)] {
+                    if point.liquidity.basis_points > liquidity {
+                        right = *point;
+                        break;
+                    }
+                    left = *point;
+                }
+
+                left.fee.basis_points - proportional(
+                    left.fee.basis_points.saturating_sub(right.fee.basis_points) as u64,
+                    (liquidity - left.liquidity.basis_points) as u64,
+                    (right.liquidity.basis_points - left.liquidity.basis_points) as u64,
+                    Rounding::RoundDown
+                )? as u32
+            }
+            FeeCurveKind::Exponential => {
+                // discount = delta * (2^(-d/h) - 2^(-1/h)) / (1 - 2^(-1/h))，d 为流动性缺口占目标的比例，
+                // 归一化后在 d = 0（达到目标）时为 delta，d = 1（池子枯竭）时为 0
+                let half_life = self.half_life.basis_points as u128;
+                let shortfall = (liquidity_target - lamports) as u128;
+                let current = Self::pow2_neg(
+                    shortfall * Fee::MAX_BASIS_POINTS as u128,
+                    liquidity_target as u128 * half_life
+                );
+                let at_empty = Self::pow2_neg(Fee::MAX_BASIS_POINTS as u128, half_life);
+
+                let discount = delta as u128 * current.saturating_sub(at_empty)
+                    / (Self::ONE - at_empty);
+
+                max_fee.basis_points - discount as u32
+            }
+        };
+
+        Ok(Fee::from_basis_points(fee))
+    }
+
+    /// 以 Q32 定点数计算 2^(-numerator/denominator)，
+    /// 整数部分精确移位，小数部分在相邻两个整数幂之间线性插值，结果随指数单调递减
+    fn pow2_neg(numerator: u128, denominator: u128) -> u128 {
+        let integer = numerator / denominator;
+        if integer >= 64 {
+            return 0;
+        }
+
+        let base = Self::ONE >> integer;
+        base - base * (numerator % denominator) / (2 * denominator)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARGET: u64 = 50_000_000_000;
+    const MIN_FEE: Fee = Fee::from_basis_points(50);
+    const MAX_FEE: Fee = Fee::from_basis_points(300);
+
+    fn point(liquidity: u32, fee: u32) -> FeeCurvePoint {
+        FeeCurvePoint {
+            liquidity: Fee::from_basis_points(liquidity),
+            fee: Fee::from_basis_points(fee),
+        }
+    }
+
+    fn curves() -> Vec<FeeCurve> {
+        vec![
+            FeeCurve::linear(),
+            FeeCurve::piecewise_linear(&[point(2_000, 250), point(5_000, 100)]),
+            FeeCurve::piecewise_linear(&[
+                point(1_000, 300),
+                point(2_000, 200),
+                point(3_000, 200),
+                point(9_999, 50),
+            ]),
+            FeeCurve::exponential(Fee::from_basis_points(1)),
+            FeeCurve::exponential(Fee::from_basis_points(2_500)),
+            FeeCurve::exponential(Fee::from_basis_points(Fee::MAX_BASIS_POINTS)),
+        ]
+    }
+
+    fn fee(curve: &FeeCurve, lamports: u64) -> Fee {
+        curve.fee(lamports, TARGET, MIN_FEE, MAX_FEE).unwrap()
+    }
+
+    #[test]
+    fn curves_are_valid() {
+        for curve in curves() {
+            curve.validate(MIN_FEE, MAX_FEE).unwrap();
+        }
+    }
+
+    #[test]
+    fn endpoints_are_max_and_min_fee() {
+        for curve in curves() {
+            assert_eq!(fee(&curve, 0), MAX_FEE, "{:?}", curve);
+            assert_eq!(fee(&curve, TARGET), MIN_FEE, "{:?}", curve);
+            assert_eq!(fee(&curve, TARGET * 2), MIN_FEE, "{:?}", curve);
+        }
+    }
+
+    #[test]
+    fn fee_never_falls_as_pool_drains() {
+        for curve in curves() {
+            let mut prev = MIN_FEE;
+            for step in (0..=10_000u64).rev() {
+                let current = fee(&curve, TARGET * step / 10_000);
+                assert!(current >= prev, "{:?} at step {}", curve, step);
+                assert!(current >= MIN_FEE && current <= MAX_FEE, "{:?}", curve);
+                prev = current;
+            }
+        }
+    }
+
+    #[test]
+    fn piecewise_linear_passes_through_breakpoints() {
+        let curve = FeeCurve::piecewise_linear(&[point(2_000, 250), point(5_000, 100)]);
+
+        assert_eq!(fee(&curve, TARGET / 5), Fee::from_basis_points(250));
+        assert_eq!(fee(&curve, TARGET / 2), Fee::from_basis_points(100));
+        // 两个断点正中间
+        assert_eq!(fee(&curve, TARGET * 35 / 100), Fee::from_basis_points(175));
+    }
+
+    #[test]
+    fn pow2_neg_is_exact_at_integer_exponents() {
+        for denominator in [1u128, 3, 2_500, 10_000 * TARGET as u128] {
+            for exponent in 0..64u128 {
+                assert_eq!(
+                    FeeCurve::pow2_neg(exponent * denominator, denominator),
+                    FeeCurve::ONE >> exponent
+                );
+            }
+            assert_eq!(FeeCurve::pow2_neg(64 * denominator, denominator), 0);
+        }
+    }
+
+    #[test]
+    fn pow2_neg_interpolates_and_decreases() {
+        assert_eq!(FeeCurve::pow2_neg(1, 2), FeeCurve::ONE * 3 / 4);
+        assert_eq!(FeeCurve::pow2_neg(3, 2), FeeCurve::ONE * 3 / 8);
+
+        let mut prev = FeeCurve::ONE;
+        for numerator in 1..=20_000u128 {
+            let current = FeeCurve::pow2_neg(numerator, 2_500);
+            assert!(current <= prev, "at {}", numerator);
+            // 线性插值位于真实曲线上方，相对误差在 10% 以内
+            let exact = 2f64.powf(-(numerator as f64) / 2_500.0) * FeeCurve::ONE as f64;
+            assert!(current as f64 >= exact, "at {}", numerator);
+            assert!((current as f64) < exact * 1.1, "at {}", numerator);
+            prev = current;
+        }
+    }
+
+    #[test]
+    fn exponential_fee_matches_closed_form() {
+        // 半衰期为目标的 25%：缺口 25% 时 2^-1，枯竭时 2^-4，
+        // discount = delta * (1/2 - 1/16) / (1 - 1/16) = delta * 7 / 15
+        let curve = FeeCurve::exponential(Fee::from_basis_points(2_500));
+        let delta = (MAX_FEE.basis_points - MIN_FEE.basis_points) as u64;
+
+        assert_eq!(
+            fee(&curve, TARGET * 3 / 4),
+            Fee::from_basis_points(MAX_FEE.basis_points - (delta * 7 / 15) as u32)
+        );
+    }
+
+    #[test]
+    fn validate_rejects_malformed_curves() {
+        let invalid = [
+            // 费率随流动性上升
+            FeeCurve::piecewise_linear(&[point(2_000, 100), point(5_000, 250)]),
+            // 断点流动性未严格递增
+            FeeCurve::piecewise_linear(&[point(5_000, 250), point(5_000, 100)]),
+            // 最后一个断点低于 lp_min_fee
+            FeeCurve::piecewise_linear(&[point(5_000, 10)]),
+            // 起点高于 lp_max_fee
+            FeeCurve::piecewise_linear(&[point(1_000, 400)]),
+            // 断点位于目标之外
+            FeeCurve::piecewise_linear(&[point(Fee::MAX_BASIS_POINTS, 100)]),
+            FeeCurve { count: MAX_FEE_CURVE_POINTS as u8 + 1, ..FeeCurve::piecewise_linear(&[]) },
+            FeeCurve::exponential(Fee::from_basis_points(0)),
+            FeeCurve::exponential(Fee::from_basis_points(Fee::MAX_BASIS_POINTS + 1)),
+            // 未使用的字段必须为零
+            FeeCurve { half_life: Fee::from_basis_points(1), ..FeeCurve::linear() },
+            FeeCurve { count: 1, ..FeeCurve::linear() },
+            FeeCurve { points: [point(1, 1); MAX_FEE_CURVE_POINTS], ..FeeCurve::linear() },
+            FeeCurve {
+                half_life: Fee::from_basis_points(1),
+                ..FeeCurve::piecewise_linear(&[point(5_000, 100)])
+            },
+        ];
+
+        for curve in invalid {
+            assert!(curve.validate(MIN_FEE, MAX_FEE).is_err(), "{:?}", curve);
+        }
+    }
+
+    #[test]
+    fn layout_is_fixed_size() {
+        assert_eq!(FeeCurve::default(), FeeCurve::linear());
+
+        let len = FeeCurve::linear().try_to_vec().unwrap().len();
+        for curve in curves() {
+            assert_eq!(curve.try_to_vec().unwrap().len(), len);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! 集成方费率覆盖账户，由管理员为指定用户或程序设置折扣或固定费率
+
+use anchor_lang::{
+    prelude::*,
+    solana_program::sysvar::instructions::{load_current_index_checked, load_instruction_at_checked}
+};
+
+use crate::{calc::Rounding, error::StakingError};
+
+use super::Fee;
+
+
+#[derive(Clone, Copy, Debug, AnchorSerialize, AnchorDeserialize, PartialEq, Eq)]
+pub enum FeeAdjustment {
+    /// 在原费率基础上按比例折扣，例如 5000 表示费率减半
+    Discount(Fee),
+
+    /// 固定费率，高于原费率时仍按原费率收取
+    Fixed(Fee),
+}
+
+
+#[account]
+pub struct FeeOverride {
+    /// 所属质押池配置账户
+    pub state: Pubkey,
+
+    /// 适用的用户钱包或程序 ID，参与 PDA 派生
+    pub key: Pubkey,
+
+    pub adjustment: FeeAdjustment,
+
+    pub bump: u8,
+}
+
+
+impl FeeOverride {
+    /// 费率覆盖账户 PDA 派生种子
+    pub const SEED: &'static [u8] = b"fee_override";
+    /// 账户空间，包含 8 字节 discriminator
+    pub const LEN: usize = 8 + 32 * 2 + 1 + 4 + 1;
+
+    pub fn validate(adjustment: &FeeAdjustment) -> Result<()> {
+        match adjustment {
+            FeeAdjustment::Discount(fee) | FeeAdjustment::Fixed(fee) => fee.check()
+        }
+    }
+
+    /// 覆盖只适用于 key 本人签名，或者由 key 对应的程序作为交易顶层指令发起 CPI
+    pub fn check_applies(
+        &self,
+        authority: &Pubkey,
+        instructions: Option<&UncheckedAccount>
+    ) -> Result<()> {
+        if self.key == *authority {
+            return Ok(());
+        }
+
+        if let Some(instructions) = instructions {
+            let instructions = instructions.to_account_info();
+            let current_index = load_current_index_checked(&instructions)? as usize;
+            let current = load_instruction_at_checked(current_index, &instructions)?;
+            if current.program_id == self.key {
+                return Ok(());
+            }
+        }
+
+        err!(StakingError::InvalidFeeOverride)
+    }
+
+    /// 计算覆盖后的费率：不会高于原费率，也不会低于 min_fee（原费率本身低于 min_fee 时保持原费率）。
+    /// min_fee 按费用类型传入：流动性解质押为 lp_min_fee，存款手续费为 0
+    pub fn apply(&self, fee: Fee, min_fee: Fee) -> Fee {
+        let adjusted = match self.adjustment {
+            // 折扣部分向下取整，费率偏向池子一侧
+            FeeAdjustment::Discount(discount) => Fee::from_basis_points(
+                fee.basis_points
+                    - discount.apply(fee.basis_points as u64, Rounding::RoundDown) as u32
+            ),
+            FeeAdjustment::Fixed(fixed) => fixed,
+        };
+
+        adjusted.min(fee).max(min_fee.min(fee))
+    }
+
+    /// 未传入覆盖账户时返回原费率
+    pub fn apply_optional(fee_override: Option<&Self>, fee: Fee, min_fee: Fee) -> Fee {
+        fee_override.map_or(fee, |fee_override| fee_override.apply(fee, min_fee))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LP_MIN_FEE: Fee = Fee::from_basis_points(50);
+    const NO_FLOOR: Fee = Fee::from_basis_points(0);
+
+    fn fee_override(adjustment: FeeAdjustment) -> FeeOverride {
+        FeeOverride {
+            state: Pubkey::default(),
+            key: Pubkey::default(),
+            adjustment,
+            bump: 0,
+        }
+    }
+
+    #[test]
+    fn fixed_zero_waives_deposit_fees_but_not_lp_min_fee() {
+        let waiver = fee_override(FeeAdjustment::Fixed(Fee::from_basis_points(0)));
+        let fee = Fee::from_basis_points(100);
+
+        assert_eq!(waiver.apply(fee, NO_FLOOR), Fee::from_basis_points(0));
+        assert_eq!(waiver.apply(fee, LP_MIN_FEE), LP_MIN_FEE);
+    }
+
+    #[test]
+    fn adjusted_fee_never_exceeds_original() {
+        let fixed = fee_override(FeeAdjustment::Fixed(Fee::from_basis_points(200)));
+        assert_eq!(fixed.apply(Fee::from_basis_points(100), LP_MIN_FEE), Fee::from_basis_points(100));
+
+        // 原费率本身低于下限时保持原费率
+        let discount = fee_override(FeeAdjustment::Discount(Fee::from_basis_points(5_000)));
+        assert_eq!(discount.apply(Fee::from_basis_points(30), LP_MIN_FEE), Fee::from_basis_points(30));
+    }
+
+    #[test]
+    fn discount_rounds_in_favour_of_the_pool() {
+        let discount = fee_override(FeeAdjustment::Discount(Fee::from_basis_points(5_000)));
+
+        assert_eq!(discount.apply(Fee::from_basis_points(301), NO_FLOOR), Fee::from_basis_points(151));
+        assert_eq!(discount.apply(Fee::from_basis_points(120), LP_MIN_FEE), Fee::from_basis_points(60));
+        assert_eq!(discount.apply(Fee::from_basis_points(80), LP_MIN_FEE), LP_MIN_FEE);
+    }
+}
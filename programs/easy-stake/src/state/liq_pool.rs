@@ -1,8 +1,13 @@
 use anchor_lang::{prelude::*, solana_program::native_token::LAMPORTS_PER_SOL};
 
-use crate::{calc::proportional, error::StakingError, require_lte, ID};
+use crate::{
+    calc::{proportional, value_from_shares, Rounding}, 
+    error::StakingError, 
+    require_lte, 
+    ID
+};
 
-use super::Fee;
+use super::{Fee, FeeCurve};
 
 
 #[derive(Clone, AnchorSerialize, AnchorDeserialize, Debug)]
@@ -116,7 +121,6 @@ impl LiqPool {
             Self::MAX_TREASURY_CUT,
             StakingError::TreasuryCutIsTooHigh
         );
-
         Ok(())
     }
 
@@ -124,18 +128,85 @@ impl LiqPool {
         self.lp_max_fee.basis_points.saturating_sub(self.lp_min_fee.basis_points)
     }
 
-    pub fn linear_fee(&self, lamports: u64) -> Fee {
-        if lamports >= self.lp_liquidity_target {
-            self.lp_min_fee
-        } else {
-            Fee {
-                basis_points: self.lp_max_fee.basis_points - proportional(
-                    self.delta() as u64, 
-                    lamports, 
-                    self.lp_liquidity_target
-                ).unwrap() as u32
-            }
+    /// 按配置的曲线计算剩余流动性为 lamports 时的费率，曲线存放在 StakePoolConfig::fee_curve
+    pub fn curve_fee(&self, fee_curve: &FeeCurve, lamports: u64) -> Result<Fee> {
+        fee_curve.fee(
+            lamports, 
+            self.lp_liquidity_target, 
+            self.lp_min_fee, 
+            self.lp_max_fee
+        )
+    }
+
+    /// 从可用余额为 available_lamports 的 sol_leg 中取走 remove_lamports 时的费率。
+    /// 链上 unstake 与链下报价共用此函数，保证两者结果一致
+    pub fn liquid_unstake_fee(
+        &self,
+        fee_curve: &FeeCurve,
+        available_lamports: u64,
+        remove_lamports: u64
+    ) -> Result<Fee> {
+        if remove_lamports >= available_lamports {
+            // 兑换的 sol 数量超过池子sol总量，直接收取最大手续费
+            return Ok(self.lp_max_fee);
+        }
+
+        // 用户提取后池子剩余的 SOL，池子越接近枯竭，手续费越高
+        self.curve_fee(fee_curve, available_lamports - remove_lamports)
+    }
+
+    /// 按 LP 份额计算可提取的 SOL 与 mSOL，两条腿均向下取整。
+    /// 与铸造 LP 相同，先按虚拟份额与虚拟价值（calc::value_from_shares）求出份额对应的价值，
+    /// 再按其占池子总价值 liq_pool_value 的比例从两条腿中取出。
+    /// 虚拟份额对应的那部分流动性永久留在池中
+    pub fn liquidity_out(
+        &self,
+        tokens: u64,
+        sol_leg_available_balance: u64,
+        msol_leg_balance: u64,
+        liq_pool_value: u64
+    ) -> Result<(u64, u64)> {
+        if liq_pool_value == 0 {
+            return Ok((0, 0));
         }
+
+        let value_out = value_from_shares(
+            tokens, 
+            liq_pool_value, 
+            self.lp_supply, 
+            Rounding::RoundDown
+        )?.min(liq_pool_value);
+
+        Ok((
+            proportional(
+                sol_leg_available_balance, 
+                value_out, 
+                liq_pool_value, 
+                Rounding::RoundDown
+            )?,
+            proportional(
+                msol_leg_balance, 
+                value_out, 
+                liq_pool_value, 
+                Rounding::RoundDown
+            )?
+        ))
+    }
+
+    /// sol_leg 超出目标流动性与缓冲之外、可以借给 reserve 的 SOL
+    pub fn lendable_lamports(&self, lending_buffer: Fee, sol_leg_available_balance: u64) -> u64 {
+        let keep = self.lp_liquidity_target.saturating_add(
+            lending_buffer.apply(self.lp_liquidity_target, Rounding::RoundUp)
+        );
+
+        sol_leg_available_balance.saturating_sub(keep)
+    }
+
+    /// sol_leg 低于目标流动性时需要收回的借出 SOL
+    pub fn repayable_lamports(&self, sol_leg_available_balance: u64) -> u64 {
+        self.lp_liquidity_target
+            .saturating_sub(sol_leg_available_balance)
+            .min(self.lent_from_sol_leg)
     }
 
     pub fn check_liquidity_cap(
@@ -60,7 +60,7 @@ impl List {
             )
             .map_err(|_| error!(StakingError::CalculationFailure))?
             .checked_div(self.item_size)
-            .unwrap_or(std::u32::MAX)
+            .unwrap_or(u32::MAX)
         )
     }
 
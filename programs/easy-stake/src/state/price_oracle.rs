@@ -0,0 +1,128 @@
+//! mSOL 价格预言机账户，供其他协议低成本读取 mSOL 价格
+
+use anchor_lang::prelude::*;
+
+
+/// 某个 epoch 最后一次记录的价格
+#[derive(
+    Clone, Copy, Debug, Default, AnchorSerialize, AnchorDeserialize, PartialEq, Eq
+)]
+pub struct PriceRecord {
+    pub epoch: u64,
+    pub slot: u64,
+    /// 按 StakePoolConfig::PRICE_DENOMINATOR 放大的价格
+    pub price: u64,
+}
+
+
+/// 账户布局固定，只包含定长字段，其他程序可以按偏移量直接读取（偏移量包含 8 字节 discriminator）：
+///
+/// | 偏移 | 字段                  | 类型                |
+/// |------|-----------------------|---------------------|
+/// | 8    | state                 | Pubkey              |
+/// | 40   | price                 | u64                 |
+/// | 48   | epoch                 | u64                 |
+/// | 56   | slot                  | u64                 |
+/// | 64   | total_staked_lamports | u64                 |
+/// | 72   | msol_supply           | u64                 |
+/// | 80   | history_head          | u8                  |
+/// | 81   | history_len           | u8                  |
+/// | 82   | bump                  | u8                  |
+/// | 83   | history               | [PriceRecord; 32]   |
+///
+/// PriceRecord 依次为 epoch、slot、price 三个 u64，共 24 字节。
+///
+/// 除 update_price_oracle 外，deposit、unstake、flash_repay、recycle_msol_leg、withdraw_stake、
+/// wind_down_redeem 传入本账户时也会在汇率变化后同步刷新。读取方应检查 slot（见 is_stale），
+/// 拒绝使用过期的价格
+#[account]
+pub struct MsolPriceOracle {
+    /// 所属质押池配置账户
+    pub state: Pubkey,
+
+    /// 1 mSOL 可兑换的 SOL，按 StakePoolConfig::PRICE_DENOMINATOR 放大
+    pub price: u64,
+
+    /// 最近一次更新时的 epoch
+    pub epoch: u64,
+
+    /// 最近一次更新时的 slot
+    pub slot: u64,
+
+    /// 最近一次更新时 mSOL 持有者拥有的 SOL 总量
+    pub total_staked_lamports: u64,
+
+    /// 最近一次更新时的 mSOL 供应量
+    pub msol_supply: u64,
+
+    /// history 中下一条记录的写入位置
+    pub history_head: u8,
+
+    /// history 中有效记录的数量
+    pub history_len: u8,
+
+    pub bump: u8,
+
+    /// 最近 HISTORY_LEN 个 epoch 的价格环形缓冲区，每个 epoch 只保留最后一次更新，用于计算 TWAP 与 APY
+    pub history: [PriceRecord; MsolPriceOracle::HISTORY_LEN],
+}
+
+
+impl MsolPriceOracle {
+    /// 价格预言机 PDA 派生种子
+    pub const SEED: &'static [u8] = b"msol_price";
+    /// 环形缓冲区保留的 epoch 数量
+    pub const HISTORY_LEN: usize = 32;
+    /// 账户空间，包含 8 字节 discriminator
+    pub const LEN: usize = 8 + 32 + 8 * 5 + 3 + 24 * Self::HISTORY_LEN;
+
+    /// 写入最新价格。同一 epoch 内覆盖该 epoch 的历史记录，进入新 epoch 时追加一条
+    pub fn update(
+        &mut self,
+        price: u64,
+        total_staked_lamports: u64,
+        msol_supply: u64,
+        clock: &Clock
+    ) {
+        self.price = price;
+        self.epoch = clock.epoch;
+        self.slot = clock.slot;
+        self.total_staked_lamports = total_staked_lamports;
+        self.msol_supply = msol_supply;
+
+        let record = PriceRecord {
+            epoch: clock.epoch,
+            slot: clock.slot,
+            price
+        };
+
+        match self.last_record() {
+            Some(last) if last.epoch == clock.epoch => {
+                let index = self.last_index();
+                self.history[index] = record;
+            }
+            _ => {
+                self.history[self.history_head as usize] = record;
+                self.history_head = ((self.history_head as usize + 1) % Self::HISTORY_LEN) as u8;
+                self.history_len = (self.history_len as usize + 1).min(Self::HISTORY_LEN) as u8;
+            }
+        }
+    }
+
+    /// 读取方使用：最近一次更新距今超过 max_age_slots 时价格视为过期，不应直接用于估值
+    pub fn is_stale(&self, clock: &Clock, max_age_slots: u64) -> bool {
+        clock.slot.saturating_sub(self.slot) > max_age_slots
+    }
+
+    pub fn last_record(&self) -> Option<PriceRecord> {
+        if self.history_len == 0 {
+            None
+        } else {
+            Some(self.history[self.last_index()])
+        }
+    }
+
+    fn last_index(&self) -> usize {
+        (self.history_head as usize + Self::HISTORY_LEN - 1) % Self::HISTORY_LEN
+    }
+}
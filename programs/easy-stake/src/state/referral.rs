@@ -0,0 +1,98 @@
+//! 合作方推荐账户，记录推荐量并分享国库手续费
+
+use anchor_lang::prelude::*;
+use anchor_spl::token::TokenAccount;
+
+use crate::{calc::Rounding, error::StakingError};
+
+use super::Fee;
+
+
+/// 推荐账户分成后发出的事件，包含该合作方的累计数据
+#[event]
+pub struct ReferralEvent {
+    pub state: Pubkey,
+    pub referral: Pubkey,
+    pub partner: Pubkey,
+    pub fee_share: Fee,
+    pub msol_cut: u64,
+    // 累计数据
+    pub deposited_lamports: u64,
+    pub unstaked_msol: u64,
+    pub msol_earned: u64,
+}
+
+
+#[account]
+pub struct ReferralAccount {
+    /// 所属质押池配置账户
+    pub state: Pubkey,
+
+    /// 合作方钱包地址，参与 PDA 派生
+    pub partner: Pubkey,
+
+    /// 接收分成的合作方 mSOL 代币账户
+    pub partner_msol_account: Pubkey,
+
+    /// 合作方从国库手续费中分得的比例
+    pub fee_share: Fee,
+
+    /// 通过该推荐存入的 SOL 累计
+    pub deposited_lamports: u64,
+
+    /// 通过该推荐流动性解质押的 mSOL 累计
+    pub unstaked_msol: u64,
+
+    /// 合作方累计获得的 mSOL 分成
+    pub msol_earned: u64,
+
+    pub bump: u8,
+}
+
+
+impl ReferralAccount {
+    /// 推荐账户 PDA 派生种子
+    pub const SEED: &'static [u8] = b"referral";
+    /// 账户空间，包含 8 字节 discriminator
+    pub const LEN: usize = 8 + 32 * 3 + 4 + 8 * 3 + 1;
+
+    /// 国库分成中归合作方的部分，向下取整，余数留给国库
+    pub fn partner_cut(&self, treasury_msol_cut: u64) -> u64 {
+        self.fee_share.apply(treasury_msol_cut, Rounding::RoundDown)
+    }
+
+    /// 分成必须转入创建推荐账户时登记的 mSOL 账户
+    pub fn check_partner_msol_account(&self, account: Option<&Account<TokenAccount>>) -> Result<()> {
+        let account = account.ok_or(StakingError::InvalidReferralAccount)?;
+        require_keys_eq!(
+            account.key(),
+            self.partner_msol_account,
+            StakingError::InvalidReferralAccount
+        );
+
+        Ok(())
+    }
+
+    pub fn on_deposit(&mut self, lamports: u64, msol_cut: u64) {
+        self.deposited_lamports += lamports;
+        self.msol_earned += msol_cut;
+    }
+
+    pub fn on_unstake(&mut self, msol_amount: u64, msol_cut: u64) {
+        self.unstaked_msol += msol_amount;
+        self.msol_earned += msol_cut;
+    }
+
+    pub fn emit_event(&self, referral: Pubkey, msol_cut: u64) {
+        emit!(ReferralEvent {
+            state: self.state,
+            referral,
+            partner: self.partner,
+            fee_share: self.fee_share,
+            msol_cut,
+            deposited_lamports: self.deposited_lamports,
+            unstaked_msol: self.unstaked_msol,
+            msol_earned: self.msol_earned,
+        });
+    }
+}
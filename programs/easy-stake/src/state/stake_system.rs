@@ -17,7 +17,7 @@ pub struct StakeRecord {
     pub last_update_epoch: u64,
 
     /// 是否是紧急解押状态（1 表示冷却中，0 表示正常）
-    /// 1 表示紧急解押后处于冷却中，0 表示正常状态
+    /// 1 表示紧急解押或清退解除质押后处于冷却中，0 表示正常状态
     pub is_emergency_unstaking: u8,
 }
 
@@ -94,6 +94,9 @@ pub struct StakeSystem {
 
     /// 本 epoch 内额外进行的 stake delta 调整次数（限频使用）
     pub extra_stake_delta_runs: u32,
+
+    /// 下一个质押账户派生地址使用的序号，每创建一个质押账户加一
+    pub stake_account_counter: u64,
 }
 
 impl StakeSystem {
@@ -101,10 +104,14 @@ impl StakeSystem {
     pub const STAKE_WITHDRAW_SEED: &'static [u8] = b"withdraw";
     /// 用于派生存入 stake 账户地址的种子
     pub const STAKE_DEPOSIT_SEED: &'static [u8] = b"deposit";
+    /// 质押账户 PDA 派生种子，与验证者 vote 地址和序号一起派生
+    pub const STAKE_ACCOUNT_SEED: &'static [u8] = b"stake_account";
     /// stake delta 重新计算前必须经过的最小 slot 间隔
     pub const MIN_UPDATE_WINDOW: u64 = 3_000;
     /// StakeRecord 长度
     pub const STAKE_RECORD_LEN: usize = 49;
+    /// stake_reserve_batch 单次最多处理的验证者数量，每个验证者需创建并委托一个质押账户，受计算单元限制
+    pub const MAX_STAKE_BATCH_SIZE: usize = 5;
 
     pub fn new(
         stake_pool: &Pubkey,
@@ -130,7 +137,8 @@ impl StakeSystem {
             slots_for_stake_delta, 
             last_stake_delta_epoch: Epoch::MAX, 
             min_stake, 
-            extra_stake_delta_runs
+            extra_stake_delta_runs,
+            stake_account_counter: 0
         })
     }
 
@@ -148,6 +156,22 @@ impl StakeSystem {
         )
     }
 
+    pub fn find_stake_account(
+        stake_pool: &Pubkey,
+        validator_vote: &Pubkey,
+        counter: u64
+    ) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[
+                &stake_pool.to_bytes()[..32],
+                Self::STAKE_ACCOUNT_SEED,
+                &validator_vote.to_bytes()[..32],
+                &counter.to_le_bytes()
+            ],
+            &ID,
+        )
+    }
+
     pub fn add(
         &mut self,
         stake_list_data: &mut [u8],
@@ -197,4 +221,26 @@ impl StakeSystem {
         Ok(stake_record)
     }
 
+    pub fn set(
+        &self,
+        stake_list_data: &mut [u8],
+        index: u32,
+        stake_record: StakeRecord
+    ) -> Result<()> {
+        self.stake_list.set(
+            stake_list_data,
+            index,
+            stake_record
+        ).map_err(|e| e.with_account_name("stake_list"))
+    }
+
+    /// 删除记录，最后一项会移动到 index 位置
+    pub fn remove(
+        &mut self,
+        stake_list_data: &mut [u8],
+        index: u32
+    ) -> Result<()> {
+        self.stake_list.remove(stake_list_data, index)
+            .map_err(|e| e.with_account_name("stake_list"))
+    }
 }
@@ -0,0 +1,55 @@
+//! 单个钱包的存款记录，用于每个 epoch 的钱包存款限制
+
+use anchor_lang::prelude::*;
+
+use crate::{error::StakingError, require_lte};
+
+
+#[account]
+pub struct UserDepositRecord {
+    /// 所属质押池配置账户
+    pub state: Pubkey,
+
+    /// 钱包地址，参与 PDA 派生
+    pub user: Pubkey,
+
+    /// deposited 所属的 epoch
+    pub epoch: u64,
+
+    /// epoch 中该钱包已存入的 SOL
+    pub deposited: u64,
+
+    pub bump: u8,
+}
+
+
+impl UserDepositRecord {
+    /// 用户存款记录 PDA 派生种子
+    pub const SEED: &'static [u8] = b"user_deposit";
+    /// 账户空间，包含 8 字节 discriminator
+    pub const LEN: usize = 8 + 32 * 2 + 8 * 2 + 1;
+
+    /// epoch 中已存入的 SOL，进入新 epoch 后计数归零
+    pub fn deposited_at(&self, epoch: u64) -> u64 {
+        if self.epoch == epoch {
+            self.deposited
+        } else {
+            0
+        }
+    }
+
+    pub fn check_limit(&self, lamports: u64, epoch: u64, limit: u64) -> Result<()> {
+        require_lte!(
+            self.deposited_at(epoch).saturating_add(lamports),
+            limit,
+            StakingError::UserDepositLimitExceeded
+        );
+
+        Ok(())
+    }
+
+    pub fn on_deposit(&mut self, lamports: u64, epoch: u64) {
+        self.deposited = self.deposited_at(epoch) + lamports;
+        self.epoch = epoch;
+    }
+}
@@ -2,7 +2,7 @@
 
 use anchor_lang::prelude::*;
 
-use crate::{calc::proportional, error::StakingError, ID};
+use crate::{calc::{proportional, Rounding}, error::StakingError, require_lt, ID};
 
 use super::list::List;
 
@@ -81,7 +81,7 @@ impl ValidatorRecord {
             validator_account,
             active_balance: 0,
             score,
-            last_stake_delta_epoch: std::u64::MAX,
+            last_stake_delta_epoch: u64::MAX,
             duplication_flag_bump_seed
         })
     }
@@ -89,6 +89,17 @@ impl ValidatorRecord {
 
 
 
+/// 验证者实际质押与目标的偏差，用于事件上报
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, AnchorSerialize, AnchorDeserialize)]
+pub struct ValidatorStakeDeviation {
+    pub validator_index: u32,
+    pub active_balance: u64,
+    pub stake_target: u64,
+    /// active_balance - stake_target，负数表示质押不足
+    pub deviation: i64,
+}
+
+
 #[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize)]
 pub struct ValidatorList {}
 
@@ -146,6 +157,10 @@ pub struct ValidatorSystem {
 impl ValidatorSystem {
     /// ValidatorRecord 大小
     pub const VALIDATOR_RECORD_LEN: usize = 53;
+    /// 验证者数量上限。每次质押都要遍历整个列表寻找缺口最大的验证者，列表长度受计算单元限制
+    pub const MAX_VALIDATORS: u32 = 100;
+    /// StakeDistributionEvent 最多上报的验证者数量，按偏差绝对值从大到小选取
+    pub const MAX_REPORTED_DEVIATIONS: usize = 10;
 
     pub fn new(
         validator_list_account: Pubkey,
@@ -174,6 +189,12 @@ impl ValidatorSystem {
         stake_config_key: &Pubkey,
         duplication_flag_address: &Pubkey
     ) -> Result<()> {
+        require_lt!(
+            self.validator_list.count,
+            Self::MAX_VALIDATORS,
+            StakingError::TooManyValidators
+        );
+
         self.validator_list.push(
             validator_list_data, 
             ValidatorRecord::new(
@@ -246,6 +267,92 @@ impl ValidatorSystem {
             .map_err(|e| e.with_account_name("validator_list"))
     }
 
+    /// 找出质押缺口（目标 - active_balance）最大的验证者，缺口相同时取序号较小者。
+    /// allow_repeat 为 false 时跳过本 epoch 已经质押过的验证者；没有缺口时返回 None
+    pub fn most_under_allocated(
+        &self,
+        validator_list_data: &[u8],
+        total_stake_target: u64,
+        epoch: u64,
+        allow_repeat: bool
+    ) -> Result<Option<(u32, u64)>> {
+        Ok(self
+            .most_under_allocated_n(validator_list_data, total_stake_target, epoch, allow_repeat, 1)?
+            .first()
+            .copied())
+    }
+
+    /// 按质押缺口从大到小取前 n 个有缺口的验证者 (序号, 缺口)，排序规则与 most_under_allocated 相同。
+    /// 只遍历一次列表，stake_reserve_batch 用它校验各对的顺序
+    pub fn most_under_allocated_n(
+        &self,
+        validator_list_data: &[u8],
+        total_stake_target: u64,
+        epoch: u64,
+        allow_repeat: bool,
+        n: usize
+    ) -> Result<Vec<(u32, u64)>> {
+        let mut result: Vec<(u32, u64)> = Vec::with_capacity(n + 1);
+        for index in 0..self.validator_list.count {
+            let validator = self.get(validator_list_data, index)?;
+            if !allow_repeat && validator.last_stake_delta_epoch == epoch {
+                continue;
+            }
+
+            let shortfall = self.validator_stake_target(&validator, total_stake_target)?
+                .saturating_sub(validator.active_balance);
+            if shortfall == 0 {
+                continue;
+            }
+
+            // 缺口相同时先遍历到的序号较小者在前
+            let position = result
+                .iter()
+                .position(|&(_, other)| shortfall > other)
+                .unwrap_or(result.len());
+            if position < n {
+                result.insert(position, (index, shortfall));
+                result.truncate(n);
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// 偏差绝对值最大的 MAX_REPORTED_DEVIATIONS 个验证者，按偏差绝对值从大到小排列
+    pub fn stake_deviations(
+        &self,
+        validator_list_data: &[u8],
+        total_stake_target: u64
+    ) -> Result<Vec<ValidatorStakeDeviation>> {
+        let mut result: Vec<ValidatorStakeDeviation> =
+            Vec::with_capacity(Self::MAX_REPORTED_DEVIATIONS + 1);
+        for index in 0..self.validator_list.count {
+            let validator = self.get(validator_list_data, index)?;
+            let stake_target = self.validator_stake_target(&validator, total_stake_target)?;
+            let deviation = ValidatorStakeDeviation {
+                validator_index: index,
+                active_balance: validator.active_balance,
+                stake_target,
+                // 在 i128 中相减后截断到 i64 范围，异常数据不会使偏差回绕
+                deviation: (validator.active_balance as i128 - stake_target as i128)
+                    .clamp(i64::MIN as i128, i64::MAX as i128) as i64,
+            };
+
+            let position = result
+                .iter()
+                .position(|other| deviation.deviation.unsigned_abs() > other.deviation.unsigned_abs())
+                .unwrap_or(result.len());
+            if position < Self::MAX_REPORTED_DEVIATIONS {
+                result.insert(position, deviation);
+                result.truncate(Self::MAX_REPORTED_DEVIATIONS);
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// 按评分分配 total_stake_target（池子总质押目标）中该验证者的份额
     pub fn validator_stake_target(
         &self,
         validator: &ValidatorRecord,
@@ -258,7 +365,127 @@ impl ValidatorSystem {
         proportional(
             total_stake_target, 
             validator.score as u64, 
-            self.total_validator_score as u64
+            self.total_validator_score as u64,
+            Rounding::RoundDown
         )
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPOCH: u64 = 10;
+
+    /// 按 (score, active_balance, last_stake_delta_epoch) 依次构造验证者列表
+    fn validator_system(validators: &[(u32, u64, u64)]) -> (ValidatorSystem, Vec<u8>) {
+        let mut data = vec![0; 8 + ValidatorSystem::VALIDATOR_RECORD_LEN * validators.len()];
+        let mut system = ValidatorSystem::new(
+            Pubkey::default(),
+            &mut data,
+            Pubkey::default(),
+            0
+        ).unwrap();
+        for &(score, active_balance, last_stake_delta_epoch) in validators {
+            system.validator_list.push(
+                &mut data,
+                ValidatorRecord {
+                    validator_account: Pubkey::new_unique(),
+                    active_balance,
+                    score,
+                    last_stake_delta_epoch,
+                    duplication_flag_bump_seed: 0,
+                }
+            ).unwrap();
+            system.total_validator_score += score;
+            system.total_active_balance += active_balance;
+        }
+
+        (system, data)
+    }
+
+    #[test]
+    fn stake_target_is_the_score_share_of_the_total_rounded_down() {
+        let (system, data) = validator_system(&[(1, 0, 0), (2, 0, 0)]);
+        let first = system.get(&data, 0).unwrap();
+        let second = system.get(&data, 1).unwrap();
+
+        assert_eq!(system.validator_stake_target(&first, 100).unwrap(), 33);
+        assert_eq!(system.validator_stake_target(&second, 100).unwrap(), 66);
+    }
+
+    #[test]
+    fn stake_target_is_zero_without_scores() {
+        let (system, data) = validator_system(&[(0, 0, 0)]);
+        let validator = system.get(&data, 0).unwrap();
+
+        assert_eq!(system.validator_stake_target(&validator, 100).unwrap(), 0);
+        assert_eq!(system.most_under_allocated(&data, 100, EPOCH, false).unwrap(), None);
+    }
+
+    #[test]
+    fn most_under_allocated_ranks_by_shortfall_then_index() {
+        // 总目标 400，每个验证者的目标为 100
+        let (system, data) = validator_system(&[(1, 50, 0), (1, 0, 0), (1, 100, 0), (1, 0, 0)]);
+
+        assert_eq!(
+            system.most_under_allocated_n(&data, 400, EPOCH, false, 4).unwrap(),
+            vec![(1, 100), (3, 100), (0, 50)]
+        );
+        assert_eq!(
+            system.most_under_allocated_n(&data, 400, EPOCH, false, 2).unwrap(),
+            vec![(1, 100), (3, 100)]
+        );
+        assert_eq!(
+            system.most_under_allocated(&data, 400, EPOCH, false).unwrap(),
+            Some((1, 100))
+        );
+    }
+
+    #[test]
+    fn most_under_allocated_skips_validators_staked_this_epoch_unless_repeat_is_allowed() {
+        let (system, data) = validator_system(&[(1, 0, EPOCH), (1, 50, 0)]);
+
+        assert_eq!(system.most_under_allocated(&data, 200, EPOCH, false).unwrap(), Some((1, 50)));
+        assert_eq!(system.most_under_allocated(&data, 200, EPOCH, true).unwrap(), Some((0, 100)));
+    }
+
+    #[test]
+    fn most_under_allocated_is_none_when_every_validator_reached_its_target() {
+        let (system, data) = validator_system(&[(1, 100, 0), (1, 150, 0)]);
+
+        assert_eq!(system.most_under_allocated(&data, 200, EPOCH, false).unwrap(), None);
+    }
+
+    #[test]
+    fn stake_deviations_report_only_the_largest_deviations() {
+        // 总目标 1200，每个验证者的目标为 100，偏差依次为 -100, -80, ..., 120
+        let validators: Vec<_> = (0..12).map(|i| (1, i * 20, 0)).collect();
+        let (system, data) = validator_system(&validators);
+
+        let deviations = system.stake_deviations(&data, 1_200).unwrap();
+        assert_eq!(
+            deviations.iter().map(|d| d.validator_index).collect::<Vec<_>>(),
+            vec![11, 0, 10, 1, 9, 2, 8, 3, 7, 4]
+        );
+        assert_eq!(
+            deviations[0],
+            ValidatorStakeDeviation {
+                validator_index: 11,
+                active_balance: 220,
+                stake_target: 100,
+                deviation: 120,
+            }
+        );
+    }
+
+    #[test]
+    fn stake_deviation_saturates_instead_of_wrapping() {
+        let (system, data) = validator_system(&[(1, u64::MAX, 0)]);
+        assert_eq!(system.stake_deviations(&data, 0).unwrap()[0].deviation, i64::MAX);
+
+        let (system, data) = validator_system(&[(1, 0, 0)]);
+        assert_eq!(system.stake_deviations(&data, u64::MAX).unwrap()[0].deviation, i64::MIN);
+    }
+}
@@ -1,8 +1,31 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { EasyStake } from "../target/types/easy_stake";
-import { PublicKey } from "@solana/web3.js";
-import { AccountLayout, MintLayout } from "@solana/spl-token";
+import {
+  ComputeBudgetProgram,
+  Keypair,
+  LAMPORTS_PER_SOL,
+  ParsedAccountData,
+  PublicKey,
+  SYSVAR_INSTRUCTIONS_PUBKEY,
+  SYSVAR_STAKE_HISTORY_PUBKEY,
+  StakeProgram,
+  SystemProgram,
+  Transaction,
+  TransactionInstruction,
+  VoteInit,
+  VoteProgram,
+} from "@solana/web3.js";
+import {
+  AccountLayout,
+  MintLayout,
+  NATIVE_MINT,
+  TOKEN_PROGRAM_ID,
+  createAssociatedTokenAccountIdempotent,
+  createWrappedNativeAccount,
+  getAssociatedTokenAddressSync,
+} from "@solana/spl-token";
+import { assert } from "chai";
 
 describe("easy-stake", () => {
   const provider = anchor.AnchorProvider.env();
@@ -25,8 +48,411 @@ describe("easy-stake", () => {
   let msolLegtPda: PublicKey;
   let msolLegBump: number;
 
+  let reservePda: PublicKey;
+  let treasuryMsolPda: PublicKey;
+
   // additional PDAs
   let operationalSolAccount: PublicKey;
+  let priceOraclePda: PublicKey;
+
+  // 与 calc::VIRTUAL_SHARES / calc::VIRTUAL_VALUE 保持一致
+  const VIRTUAL_SHARES = new anchor.BN(1_000_000);
+  const VIRTUAL_VALUE = new anchor.BN(1_000_000);
+
+  // 测试中生成的用户，清退时需要用它们签名取回剩余的 LP 与 mSOL
+  const users = new Map<string, Keypair>();
+  const newUser = () => {
+    const user = Keypair.generate();
+    users.set(user.publicKey.toBase58(), user);
+    return user;
+  };
+
+  const airdrop = async (to: PublicKey, lamports: number) => {
+    const sig = await provider.connection.requestAirdrop(to, lamports);
+    const latest = await provider.connection.getLatestBlockhash();
+    await provider.connection.confirmTransaction({ signature: sig, ...latest });
+  };
+
+  const donate = async (from: Keypair, to: PublicKey, lamports: number) => {
+    await provider.sendAndConfirm(
+      new Transaction().add(
+        SystemProgram.transfer({ fromPubkey: from.publicKey, toPubkey: to, lamports })
+      ),
+      [from]
+    );
+  };
+
+  const tokenBalance = async (address: PublicKey) =>
+    new anchor.BN(
+      (await provider.connection.getTokenAccountBalance(address)).value.amount
+    );
+
+  // 断言操作失败且错误码（或程序日志）中包含 code
+  const expectError = async (action: () => Promise<unknown>, code: string) => {
+    try {
+      await action();
+    } catch (err) {
+      const logs: string[] = err.logs ?? err.transactionLogs ?? [];
+      const message = [err.error?.errorCode?.code, err.message, ...logs].join("\n");
+      assert.include(message, code);
+      return;
+    }
+    assert.fail(`expected ${code}`);
+  };
+
+  const sendInstructions = async (
+    instructions: TransactionInstruction[],
+    signers: Keypair[]
+  ) => provider.sendAndConfirm(new Transaction().add(...instructions), signers);
+
+  const configLp = async (params: object) => {
+    await program.methods
+      .configLp(params as any)
+      .accountsPartial({
+        adminAuthority: payer,
+        stakePoolConfig: stakePoolConfigPda,
+      })
+      .rpc();
+  };
+
+  const configStakePool = async (params: object) => {
+    await program.methods
+      .configStakePool(params as any)
+      .accountsPartial({
+        adminAuthority: payer,
+        stakePoolConfig: stakePoolConfigPda,
+      })
+      .rpc();
+  };
+
+  const depositIx = (user: Keypair, lamports: anchor.BN, extra: object = {}) =>
+    program.methods
+      .deposit(lamports)
+      .accountsPartial({
+        user: user.publicKey,
+        stakePoolConfig: stakePoolConfigPda,
+        msolMint: msolPda,
+        liqPoolSolLegPda: solLegtPda,
+        liqPoolMsolLeg: msolLegtPda,
+        treasuryMsolAccount: treasuryMsolPda,
+        reservePda,
+        mintTo: getAssociatedTokenAddressSync(msolPda, user.publicKey),
+        ...extra,
+      })
+      .instruction();
+
+  const unstakeIx = (user: Keypair, msolAmount: anchor.BN, extra: object = {}) =>
+    program.methods
+      .unstake(msolAmount)
+      .accountsPartial({
+        getMsolFromAuthority: user.publicKey,
+        stakePoolConfig: stakePoolConfigPda,
+        msolMint: msolPda,
+        treasuryMsolAccount: treasuryMsolPda,
+        liqPoolSolLegPda: solLegtPda,
+        liqPoolMsolLeg: msolLegtPda,
+        getMsolFrom: getAssociatedTokenAddressSync(msolPda, user.publicKey),
+        transferSolTo: user.publicKey,
+        ...extra,
+      })
+      .instruction();
+
+  const unstake = async (user: Keypair, msolAmount: anchor.BN, extra: object = {}) =>
+    sendInstructions([await unstakeIx(user, msolAmount, extra)], [user]);
+
+  const addLiquidityMsol = async (user: Keypair, msolAmount: anchor.BN) =>
+    program.methods
+      .addLiquidityMsol(msolAmount)
+      .accountsPartial({
+        transferFromAuthority: user.publicKey,
+        transferFrom: getAssociatedTokenAddressSync(msolPda, user.publicKey),
+        stakePoolConfig: stakePoolConfigPda,
+        lpMint: lpMintPda,
+        liqPoolMsolLeg: msolLegtPda,
+        liqPoolSolLegPda: solLegtPda,
+        mintTo: getAssociatedTokenAddressSync(lpMintPda, user.publicKey),
+      })
+      .signers([user])
+      .rpc();
+
+  const removeLiquiditySol = async (user: Keypair, tokens: anchor.BN, minSolOut: anchor.BN) =>
+    program.methods
+      .removeLiquiditySol(tokens, minSolOut)
+      .accountsPartial({
+        burnFromAuthority: user.publicKey,
+        burnFrom: getAssociatedTokenAddressSync(lpMintPda, user.publicKey),
+        stakePoolConfig: stakePoolConfigPda,
+        lpMint: lpMintPda,
+        transferSolTo: user.publicKey,
+        liqPoolSolLegPda: solLegtPda,
+        liqPoolMsolLeg: msolLegtPda,
+        treasuryMsolAccount: treasuryMsolPda,
+      })
+      .signers([user])
+      .rpc();
+
+  const U64_MAX = new anchor.BN("18446744073709551615");
+  const OPERATIONAL_BUDGET = new anchor.BN(LAMPORTS_PER_SOL);
+
+  // 与 StakePoolConfig::total_staked_lamports 一致
+  const totalStakedLamports = (state: {
+    validatorSystem: { totalActiveBalance: anchor.BN };
+    availableReserveBalance: anchor.BN;
+    liqPool: { lentFromSolLeg: anchor.BN };
+  }) =>
+    state.validatorSystem.totalActiveBalance
+      .add(state.availableReserveBalance)
+      .sub(state.liqPool.lentFromSolLeg);
+
+  const quoteAccounts = () => ({
+    stakePoolConfig: stakePoolConfigPda,
+    lpMint: lpMintPda,
+    liqPoolSolLegPda: solLegtPda,
+    liqPoolMsolLeg: msolLegtPda,
+  });
+
+  const createAta = async (owner: Keypair, mint: PublicKey) =>
+    createAssociatedTokenAccountIdempotent(
+      provider.connection,
+      owner,
+      mint,
+      owner.publicKey
+    );
+
+  // 某个 mint 下余额不为零的全部 token 账户
+  const tokenAccounts = async (mint: PublicKey) =>
+    (
+      await provider.connection.getParsedProgramAccounts(TOKEN_PROGRAM_ID, {
+        filters: [{ dataSize: AccountLayout.span }, { memcmp: { offset: 0, bytes: mint.toBase58() } }],
+      })
+    )
+      .map(({ pubkey, account }) => {
+        const info = (account.data as ParsedAccountData).parsed.info;
+        return {
+          address: pubkey,
+          owner: new PublicKey(info.owner),
+          amount: new anchor.BN(info.tokenAmount.amount),
+        };
+      })
+      .filter(({ amount }) => !amount.isZero());
+
+  const STAKE_CONFIG_ID = new PublicKey("StakeConfig11111111111111111111111111111111");
+
+  // 按 List 布局读取记录：8 字节 discriminator 之后按 item_size 依次排列
+  const listRecords = async (list: { account: PublicKey; itemSize: number; count: number }) => {
+    const info = await provider.connection.getAccountInfo(list.account);
+    return Array.from({ length: list.count }, (_, i) =>
+      info.data.subarray(8 + i * list.itemSize, 8 + (i + 1) * list.itemSize)
+    );
+  };
+
+  const validatorRecords = async () => {
+    const state = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    return (await listRecords(state.validatorSystem.validatorList)).map((data) => ({
+      validatorAccount: new PublicKey(data.subarray(0, 32)),
+      activeBalance: new anchor.BN(data.subarray(32, 40), "le"),
+      score: data.readUInt32LE(40),
+      lastStakeDeltaEpoch: new anchor.BN(data.subarray(44, 52), "le"),
+    }));
+  };
+
+  const stakeRecords = async () => {
+    const state = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    return (await listRecords(state.stakeSystem.stakeList)).map((data) => ({
+      stakeAccount: new PublicKey(data.subarray(0, 32)),
+      lastUpdateDelegatedLamports: new anchor.BN(data.subarray(32, 40), "le"),
+      lastUpdateEpoch: new anchor.BN(data.subarray(40, 48), "le"),
+      isEmergencyUnstaking: data[48],
+    }));
+  };
+
+  const validatorIndex = async (vote: PublicKey) =>
+    (await validatorRecords()).findIndex((v) => v.validatorAccount.equals(vote));
+
+  const stakeIndex = async (stakeAccount: PublicKey) =>
+    (await stakeRecords()).findIndex((s) => s.stakeAccount.equals(stakeAccount));
+
+  // 与 StakeSystem::find_stake_account 一致
+  const stakeAccountPda = (vote: PublicKey, counter: anchor.BN) =>
+    PublicKey.findProgramAddressSync(
+      [
+        stakePoolConfigPda.toBuffer(),
+        Buffer.from("stake_account"),
+        vote.toBuffer(),
+        counter.toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    )[0];
+
+  // 创建一个不投票的 vote 账户作为测试验证者，质押可以正常委托给它
+  const createVoteAccount = async () => {
+    const node = Keypair.generate();
+    const vote = Keypair.generate();
+    await provider.sendAndConfirm(
+      VoteProgram.createAccount({
+        fromPubkey: payer,
+        votePubkey: vote.publicKey,
+        voteInit: new VoteInit(node.publicKey, payer, payer, 0),
+        lamports: await provider.connection.getMinimumBalanceForRentExemption(VoteProgram.space),
+      }),
+      [vote, node]
+    );
+    return vote.publicKey;
+  };
+
+  const addValidator = async (vote: PublicKey, score: number) => {
+    const state = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    await program.methods
+      .addValidator(score)
+      .accountsPartial({
+        rentPayer: payer,
+        managerAuthority: payer,
+        stakePoolConfig: stakePoolConfigPda,
+        validatorList: state.validatorSystem.validatorList.account,
+        validatorVote: vote,
+      })
+      .rpc();
+  };
+
+  // 返回本次使用的质押账户地址，未满足质押条件时指令不创建该账户
+  const stakeReserve = async (vote: PublicKey) => {
+    const state = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    const stakeAccount = stakeAccountPda(vote, state.stakeSystem.stakeAccountCounter);
+    await program.methods
+      .stakeReserve(await validatorIndex(vote))
+      .accountsPartial({
+        rentPayer: payer,
+        stakePoolConfig: stakePoolConfigPda,
+        validatorList: state.validatorSystem.validatorList.account,
+        stakeList: state.stakeSystem.stakeList.account,
+        validatorVote: vote,
+        reservePda,
+        operationalSolAccount,
+        stakeAccount,
+        stakeHistory: SYSVAR_STAKE_HISTORY_PUBKEY,
+        stakeConfig: STAKE_CONFIG_ID,
+        stakeProgram: StakeProgram.programId,
+      })
+      .rpc();
+    return stakeAccount;
+  };
+
+  // 第 i 对的质押账户由当前计数器 + i 派生，返回交易签名与这些地址
+  const stakeReserveBatch = async (
+    votes: PublicKey[],
+    preInstructions: TransactionInstruction[] = []
+  ) => {
+    const state = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    const stakeAccounts = votes.map((vote, i) =>
+      stakeAccountPda(vote, state.stakeSystem.stakeAccountCounter.addn(i))
+    );
+    const indices: number[] = [];
+    for (const vote of votes) indices.push(await validatorIndex(vote));
+    const signature = await program.methods
+      .stakeReserveBatch(indices)
+      .accountsPartial({
+        rentPayer: payer,
+        stakePoolConfig: stakePoolConfigPda,
+        validatorList: state.validatorSystem.validatorList.account,
+        stakeList: state.stakeSystem.stakeList.account,
+        reservePda,
+        operationalSolAccount,
+        stakeHistory: SYSVAR_STAKE_HISTORY_PUBKEY,
+        stakeConfig: STAKE_CONFIG_ID,
+        stakeProgram: StakeProgram.programId,
+      })
+      .remainingAccounts(
+        votes.flatMap((vote, i) => [
+          { pubkey: vote, isSigner: false, isWritable: false },
+          { pubkey: stakeAccounts[i], isSigner: false, isWritable: true },
+        ])
+      )
+      .preInstructions(preInstructions)
+      .rpc();
+    return { signature, stakeAccounts };
+  };
+
+  const setValidatorScore = async (vote: PublicKey, score: number) => {
+    const state = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    await program.methods
+      .setValidatorScore(await validatorIndex(vote), vote, score)
+      .accountsPartial({
+        stakePoolConfig: stakePoolConfigPda,
+        managerAuthority: payer,
+        validatorList: state.validatorSystem.validatorList.account,
+      })
+      .rpc();
+  };
+
+  const updateStake = async (stakeAccount: PublicKey, vote: PublicKey, extra: object = {}) => {
+    const state = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    await program.methods
+      .updateStake(await stakeIndex(stakeAccount), await validatorIndex(vote))
+      .accountsPartial({
+        crank: payer,
+        stakePoolConfig: stakePoolConfigPda,
+        validatorList: state.validatorSystem.validatorList.account,
+        stakeList: state.stakeSystem.stakeList.account,
+        stakeAccount,
+        msolMint: msolPda,
+        treasuryMsolAccount: treasuryMsolPda,
+        operationalSolAccount,
+        ...extra,
+      })
+      .rpc();
+  };
+
+  // 测试验证器的 epoch 只有 64 个 slot（见 Anchor.toml），可以直接等待进入下一个 epoch
+  const waitForEpoch = async (epoch: number) => {
+    while ((await provider.connection.getEpochInfo()).epoch < epoch) {
+      await new Promise((resolve) => setTimeout(resolve, 400));
+    }
+  };
+
+  // 等到下一个 epoch 刚开始，使随后的若干笔交易落在同一个 epoch 中
+  const waitForFreshEpoch = async () =>
+    waitForEpoch((await provider.connection.getEpochInfo()).epoch + 1);
+
+  const addLiquidity = async (user: Keypair, lamports: anchor.BN) => {
+    await program.methods
+      .addLiquidity(lamports)
+      .accountsPartial({
+        transferFrom: user.publicKey,
+        stakePoolConfig: stakePoolConfigPda,
+        lpMint: lpMintPda,
+        liqPoolMsolLeg: msolLegtPda,
+        liqPoolSolLegPda: solLegtPda,
+        mintTo: getAssociatedTokenAddressSync(lpMintPda, user.publicKey),
+      })
+      .signers([user])
+      .rpc();
+  };
+
+  const deposit = async (user: Keypair, lamports: anchor.BN) => {
+    await program.methods
+      .deposit(lamports)
+      .accountsPartial({
+        user: user.publicKey,
+        stakePoolConfig: stakePoolConfigPda,
+        msolMint: msolPda,
+        liqPoolSolLegPda: solLegtPda,
+        liqPoolMsolLeg: msolLegtPda,
+        treasuryMsolAccount: treasuryMsolPda,
+        reservePda,
+        mintTo: getAssociatedTokenAddressSync(msolPda, user.publicKey),
+      })
+      .signers([user])
+      .rpc();
+  };
+
+  // 使至少 lamports 进入 reserve：存款会先买走 mSOL leg 中的 mSOL，并先归还 sol_leg 的借款
+  const depositToReserve = async (user: Keypair, lamports: number) => {
+    const state = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    const msolLegValue = (await tokenBalance(msolLegtPda))
+      .mul(totalStakedLamports(state).add(VIRTUAL_VALUE))
+      .div(state.msolSupply.add(VIRTUAL_SHARES));
+    await deposit(user, msolLegValue.add(state.liqPool.lentFromSolLeg).addn(lamports));
+  };
 
   before(async () => {
     [stakePoolConfigPda, stakePoolConfigBump] = PublicKey.findProgramAddressSync(
@@ -55,8 +481,25 @@ describe("easy-stake", () => {
     );
 
 
-    // operational_sol_account — 这里直接用 payer
-    operationalSolAccount = payer;
+    [reservePda] = PublicKey.findProgramAddressSync(
+      [stakePoolConfigPda.toBuffer(), Buffer.from("reserve")],
+      program.programId
+    );
+
+    [treasuryMsolPda] = PublicKey.findProgramAddressSync(
+      [stakePoolConfigPda.toBuffer(), Buffer.from("treasury_msol")],
+      program.programId
+    );
+
+    [operationalSolAccount] = PublicKey.findProgramAddressSync(
+      [stakePoolConfigPda.toBuffer(), Buffer.from("operational_sol")],
+      program.programId
+    );
+
+    [priceOraclePda] = PublicKey.findProgramAddressSync(
+      [stakePoolConfigPda.toBuffer(), Buffer.from("msol_price")],
+      program.programId
+    );
   })
 
   it("Is initialized!", async () => {
@@ -76,7 +519,10 @@ describe("easy-stake", () => {
       additionalStakeRecordSpace: 0,       // 额外 stake_list 空间（字节）
       additionalValidatorRecordSpace: 0,    // 额外 validator_list 空间（字节）
       slotsForStakeDelta: new anchor.BN(3000),            // 每 3000 slots 允许一次 stake delta
-      pauseAuthority: payer                       // 紧急暂停地址
+      pauseAuthority: payer,                      // 紧急暂停地址
+      permissioned: false,                        // 非许可模式，任何人都可以存取
+      allowlistAuthority: payer,                  // 白名单管理员
+      operationalBudgetPerEpoch: OPERATIONAL_BUDGET // 每个 epoch 运营账户最多支出 1 SOL
     };
 
     // Add your test here.
@@ -85,7 +531,6 @@ describe("easy-stake", () => {
       .accounts({
         // ---- core ----
         payer,
-        operationalSolAccount
       })
       .rpc();
 
@@ -146,9 +591,1656 @@ describe("easy-stake", () => {
     if (!new PublicKey(msolLegData.owner).equals(msolLegAuth))
       throw new Error("msol_leg owner 错误");
 
+    // ---------- 7. 校验 operational_sol PDA ----------
+    const operationalInfo = await provider.connection.getAccountInfo(operationalSolAccount);
+    if (!operationalInfo || !operationalInfo.owner.equals(SystemProgram.programId))
+      throw new Error("operational_sol_account 未创建");
+
     console.log("全部校验通过 ✅");
 
   });
 
+  it("First LP depositor cannot inflate the LP share price", async () => {
+    const attacker = newUser();
+    const victim = newUser();
+    await airdrop(attacker.publicKey, 20 * LAMPORTS_PER_SOL);
+    await airdrop(victim.publicKey, 10 * LAMPORTS_PER_SOL);
+
+    // 攻击者先以极少的 SOL 拿到首批 LP，再直接向 sol_leg 捐赠大量 SOL
+    await addLiquidity(attacker, new anchor.BN(1_000));
+    await donate(attacker, solLegtPda, 10 * LAMPORTS_PER_SOL);
+
+    const victimDeposit = new anchor.BN(5 * LAMPORTS_PER_SOL);
+    await addLiquidity(victim, victimDeposit);
+
+    const state = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    const rent = state.rentExemptForTokenAcc;
+    const solLegValue = new anchor.BN(
+      await provider.connection.getBalance(solLegtPda)
+    ).sub(rent);
+    const lpWithVirtual = state.liqPool.lpSupply.add(VIRTUAL_SHARES);
+
+    const victimLp = await tokenBalance(
+      getAssociatedTokenAddressSync(lpMintPda, victim.publicKey)
+    );
+    const attackerLp = await tokenBalance(
+      getAssociatedTokenAddressSync(lpMintPda, attacker.publicKey)
+    );
+    const victimValue = victimLp.mul(solLegValue).div(lpWithVirtual);
+    const attackerValue = attackerLp.mul(solLegValue).div(lpWithVirtual);
+
+    // 没有虚拟份额时受害者只能拿到 499 份 LP，损失约三分之一
+    assert.isTrue(
+      victimValue.gte(victimDeposit.muln(99).divn(100)),
+      `victim LP is worth ${victimValue} lamports`
+    );
+    // 捐赠的价值几乎全部归属虚拟份额，攻击者无法收回
+    assert.isTrue(
+      attackerValue.lt(new anchor.BN(LAMPORTS_PER_SOL)),
+      `attacker LP is worth ${attackerValue} lamports`
+    );
+  });
+
+  it("Burning LP uses the same virtual offsets as minting", async () => {
+    const user = newUser();
+    await airdrop(user.publicKey, 10 * LAMPORTS_PER_SOL);
+
+    const provided = new anchor.BN(2 * LAMPORTS_PER_SOL);
+    await addLiquidity(user, provided);
+    const userLp = getAssociatedTokenAddressSync(lpMintPda, user.publicKey);
+    const lp = await tokenBalance(userLp);
+    const msolAta = await createAta(user, msolPda);
+
+    const state = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    // 此前还没有任何 mSOL 进入流动性池，池子价值只有 SOL 一条腿
+    assert.isTrue((await tokenBalance(msolLegtPda)).isZero());
+    const solLegValue = new anchor.BN(await provider.connection.getBalance(solLegtPda))
+      .sub(state.rentExemptForTokenAcc)
+      .add(state.liqPool.lentFromSolLeg);
+    const valueOut = lp
+      .mul(solLegValue.add(VIRTUAL_VALUE))
+      .div(state.liqPool.lpSupply.add(VIRTUAL_SHARES));
+    const expectedSol = solLegValue.mul(valueOut).div(solLegValue);
+
+    const balanceBefore = await provider.connection.getBalance(user.publicKey);
+    await program.methods
+      .removeLiquidity(lp)
+      .accountsPartial({
+        burnFromAuthority: user.publicKey,
+        burnFrom: userLp,
+        stakePoolConfig: stakePoolConfigPda,
+        lpMint: lpMintPda,
+        transferSolTo: user.publicKey,
+        transferMsolTo: msolAta,
+        liqPoolSolLegPda: solLegtPda,
+        liqPoolMsolLeg: msolLegtPda,
+      })
+      .signers([user])
+      .rpc();
+    const received = new anchor.BN(
+      (await provider.connection.getBalance(user.publicKey)) - balanceBefore
+    );
+
+    assert.isTrue(received.eq(expectedSol), `user received ${received} lamports`);
+    // 铸造与销毁使用同一组虚拟偏移，一进一出不会多拿，也只损失舍入误差
+    assert.isTrue(received.lte(provided), `user received ${received} lamports`);
+    assert.isTrue(
+      received.gte(provided.muln(99).divn(100)),
+      `user received ${received} lamports`
+    );
+  });
+
+  it("Donating to the reserve does not change the mSOL price", async () => {
+    const attacker = newUser();
+    const victim = newUser();
+    await airdrop(attacker.publicKey, 20 * LAMPORTS_PER_SOL);
+    await airdrop(victim.publicKey, 10 * LAMPORTS_PER_SOL);
+
+    await deposit(attacker, new anchor.BN(1_000));
+    await donate(attacker, reservePda, 10 * LAMPORTS_PER_SOL);
+
+    const before = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    const victimDeposit = new anchor.BN(5 * LAMPORTS_PER_SOL);
+    await deposit(victim, victimDeposit);
+
+    const victimMsol = await tokenBalance(
+      getAssociatedTokenAddressSync(msolPda, victim.publicKey)
+    );
+    const totalStaked = before.validatorSystem.totalActiveBalance.add(
+      before.availableReserveBalance
+    );
+    const expected = victimDeposit
+      .mul(before.msolSupply.add(VIRTUAL_SHARES))
+      .div(totalStaked.add(VIRTUAL_VALUE));
+
+    assert.isTrue(victimMsol.eq(expected), `victim got ${victimMsol} mSOL`);
+    assert.isTrue(
+      victimMsol.gte(victimDeposit.muln(99).divn(100)),
+      `victim got ${victimMsol} mSOL`
+    );
+  });
+
+  it("Deposits pay the epoch-end fee inside the window and until the new epoch's rewards are recorded", async () => {
+    const vote = await createVoteAccount();
+    await addValidator(vote, 100);
+    const stakeAccount = await stakeReserve(vote);
+    const [stake] = await stakeRecords();
+    assert.isTrue(stake.stakeAccount.equals(stakeAccount));
+
+    // 手续费取 MAX_EPOCH_END_DEPOSIT_FEE，窗口为 epoch 的最后 16 个 slot
+    const FEE_BPS = 100;
+    const WINDOW_SLOTS = 16;
+    await configStakePool({
+      epochEndDepositFee: { basisPoints: FEE_BPS },
+      epochEndWindowSlots: new anchor.BN(WINDOW_SLOTS),
+    });
+
+    const user = newUser();
+    await airdrop(user.publicKey, 20 * LAMPORTS_PER_SOL);
+    const amount = new anchor.BN(LAMPORTS_PER_SOL);
+    const fullFee = amount.muln(FEE_BPS).divn(10_000);
+    const epochSchedule = await provider.connection.getEpochSchedule();
+    const waitForSlot = async (slot: number) => {
+      while ((await provider.connection.getSlot()) < slot) {
+        await new Promise((resolve) => setTimeout(resolve, 200));
+      }
+    };
+
+    // 存款并返回交易所在的 epoch、距 epoch 结束的 slot 数与收取的窗口期手续费
+    const depositAt = async () => {
+      const signature = await sendInstructions([await depositIx(user, amount)], [user]);
+      const latest = await provider.connection.getLatestBlockhash();
+      await provider.connection.confirmTransaction({ signature, ...latest }, "confirmed");
+      const tx = await provider.connection.getTransaction(signature, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+      const result = program.coder.types.decode(
+        "DepositResult",
+        Buffer.from(tx.meta.returnData.data[0], "base64")
+      );
+      const epoch = epochSchedule.getEpoch(tx.slot);
+      return {
+        epoch,
+        slotsLeft: epochSchedule.getLastSlotInEpoch(epoch) - tx.slot,
+        fee: result.epochEndFee as anchor.BN,
+      };
+    };
+
+    // 新 epoch 开始后先把唯一的质押账户收益入账，之后只有窗口期内收费
+    await waitForFreshEpoch();
+    await updateStake(stakeAccount, vote);
+    const epoch = (await stakeRecords())[0].lastUpdateEpoch.toNumber();
+    const lastSlot = epochSchedule.getLastSlotInEpoch(epoch);
+
+    // 窗口开始前几个 slot 存入，不收费
+    await waitForSlot(lastSlot - WINDOW_SLOTS - 6);
+    const outside = await depositAt();
+    assert.equal(outside.epoch, epoch);
+    assert.isAtLeast(outside.slotsLeft, WINDOW_SLOTS, "deposit landed inside the window");
+    assert.isTrue(outside.fee.isZero());
+
+    // 窗口期内存入，收取全额手续费
+    await waitForSlot(lastSlot - WINDOW_SLOTS + 4);
+    const inside = await depositAt();
+    assert.equal(inside.epoch, epoch, "epoch ended during the test");
+    assert.isBelow(inside.slotsLeft, WINDOW_SLOTS);
+    assert.isTrue(inside.fee.eq(fullFee));
+
+    // 进入新 epoch 后窗口已过，但收益还没有由 update_stake 入账，存款仍然收费
+    await waitForEpoch(epoch + 1);
+    const pending = await depositAt();
+    assert.equal(pending.epoch, epoch + 1);
+    assert.isAtLeast(pending.slotsLeft, WINDOW_SLOTS);
+    assert.isTrue(pending.fee.eq(fullFee));
+
+    // 所有质押账户的收益入账后不再收费
+    await updateStake(stakeAccount, vote);
+    const recorded = await depositAt();
+    assert.equal(recorded.epoch, epoch + 1, "epoch ended during the test");
+    assert.isAtLeast(recorded.slotsLeft, WINDOW_SLOTS);
+    assert.isTrue(recorded.fee.isZero());
+
+    await configStakePool({
+      epochEndDepositFee: { basisPoints: 0 },
+      epochEndWindowSlots: new anchor.BN(0),
+    });
+  });
+
+  it("Flash loans are repaid in the same transaction and lock the pool while open", async () => {
+    const borrower = newUser();
+    await airdrop(borrower.publicKey, 10 * LAMPORTS_PER_SOL);
+    await deposit(borrower, new anchor.BN(LAMPORTS_PER_SOL));
+    const borrowerMsol = await tokenBalance(
+      getAssociatedTokenAddressSync(msolPda, borrower.publicKey)
+    );
+
+    const FLASH_FEE_BPS = 30;
+    await configLp({ flashLoanFee: { basisPoints: FLASH_FEE_BPS } });
+
+    const borrowIx = (lamports: number) =>
+      program.methods
+        .flashBorrow(new anchor.BN(lamports))
+        .accountsPartial({
+          stakePoolConfig: stakePoolConfigPda,
+          liqPoolSolLegPda: solLegtPda,
+          transferSolTo: borrower.publicKey,
+          instructions: SYSVAR_INSTRUCTIONS_PUBKEY,
+        })
+        .instruction();
+    const repayIx = () =>
+      program.methods
+        .flashRepay()
+        .accountsPartial({
+          repayer: borrower.publicKey,
+          stakePoolConfig: stakePoolConfigPda,
+          liqPoolSolLegPda: solLegtPda,
+          reservePda,
+          msolMint: msolPda,
+          treasuryMsolAccount: treasuryMsolPda,
+        })
+        .instruction();
+
+    // 借款之后没有还款指令
+    await expectError(
+      async () => sendInstructions([await borrowIx(LAMPORTS_PER_SOL)], [borrower]),
+      "FlashRepayMissing"
+    );
+    // 还款出现在借款之前，不算与之配对
+    await expectError(
+      async () => sendInstructions([await repayIx(), await borrowIx(LAMPORTS_PER_SOL)], [borrower]),
+      "FlashLoanNotActive"
+    );
+    // 两笔借款只配一次还款，第二笔借款时上一笔还未归还
+    await expectError(
+      async () =>
+        sendInstructions(
+          [await borrowIx(LAMPORTS_PER_SOL), await borrowIx(LAMPORTS_PER_SOL), await repayIx()],
+          [borrower]
+        ),
+      "FlashLoanActive"
+    );
+    // 借款未归还期间禁止存入与解质押
+    await expectError(
+      async () =>
+        sendInstructions(
+          [
+            await borrowIx(LAMPORTS_PER_SOL),
+            await depositIx(borrower, new anchor.BN(LAMPORTS_PER_SOL)),
+            await repayIx(),
+          ],
+          [borrower]
+        ),
+      "FlashLoanActive"
+    );
+    await expectError(
+      async () =>
+        sendInstructions(
+          [
+            await borrowIx(LAMPORTS_PER_SOL),
+            await unstakeIx(borrower, borrowerMsol),
+            await repayIx(),
+          ],
+          [borrower]
+        ),
+      "FlashLoanActive"
+    );
+
+    // 正常借还：手续费扣除国库分成后留在 sol_leg，国库分成为国库铸造 mSOL
+    const state = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    const amount = 2 * LAMPORTS_PER_SOL;
+    const fee = Math.ceil((amount * FLASH_FEE_BPS) / 10_000);
+    const treasuryFee = Math.floor((fee * state.liqPool.treasuryCut.basisPoints) / 10_000);
+    const solLegBefore = await provider.connection.getBalance(solLegtPda);
+    const treasuryBefore = await tokenBalance(treasuryMsolPda);
+    await sendInstructions([await borrowIx(amount), await repayIx()], [borrower]);
+    assert.equal(
+      (await provider.connection.getBalance(solLegtPda)) - solLegBefore,
+      fee - treasuryFee
+    );
+    assert.isTrue((await tokenBalance(treasuryMsolPda)).gt(treasuryBefore));
+
+    // 国库分成只有 1 lamport，按当前价格（> 1）铸造不出 mSOL，整笔手续费都归 sol_leg
+    const priced = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    const totalStaked = priced.validatorSystem.totalActiveBalance
+      .add(priced.availableReserveBalance)
+      .sub(priced.liqPool.lentFromSolLeg);
+    assert.isTrue(
+      priced.msolSupply.add(VIRTUAL_SHARES).lt(totalStaked.add(VIRTUAL_VALUE)),
+      "mSOL price should be above 1 SOL"
+    );
+    const smallAmount = 20_000;
+    const smallFee = Math.ceil((smallAmount * FLASH_FEE_BPS) / 10_000);
+    assert.equal(
+      Math.floor((smallFee * priced.liqPool.treasuryCut.basisPoints) / 10_000),
+      1
+    );
+    const smallBefore = await provider.connection.getBalance(solLegtPda);
+    const smallTreasuryBefore = await tokenBalance(treasuryMsolPda);
+    await sendInstructions([await borrowIx(smallAmount), await repayIx()], [borrower]);
+    assert.equal((await provider.connection.getBalance(solLegtPda)) - smallBefore, smallFee);
+    assert.isTrue((await tokenBalance(treasuryMsolPda)).eq(smallTreasuryBefore));
+
+    await configLp({ flashLoanFee: { basisPoints: 0 } });
+  });
+
+  it("mSOL liquidity quotes match execution", async () => {
+    const user = newUser();
+    await airdrop(user.publicKey, 10 * LAMPORTS_PER_SOL);
+    await deposit(user, new anchor.BN(3 * LAMPORTS_PER_SOL));
+    const msolAmount = await tokenBalance(
+      getAssociatedTokenAddressSync(msolPda, user.publicKey)
+    );
+
+    const addQuote = await program.methods
+      .quoteAddLiquidityMsol(msolAmount)
+      .accountsPartial(quoteAccounts())
+      .view();
+    await addLiquidityMsol(user, msolAmount);
+    const lp = await tokenBalance(getAssociatedTokenAddressSync(lpMintPda, user.publicKey));
+    assert.isTrue(lp.eq(addQuote.lpMinted), `quoted ${addQuote.lpMinted}, minted ${lp}`);
+
+    const removeQuote = await program.methods
+      .quoteRemoveLiquiditySol(lp)
+      .accountsPartial(quoteAccounts())
+      .view();
+    const balanceBefore = await provider.connection.getBalance(user.publicKey);
+    const treasuryBefore = await tokenBalance(treasuryMsolPda);
+    await removeLiquiditySol(user, lp, removeQuote.solOut);
+    const received = (await provider.connection.getBalance(user.publicKey)) - balanceBefore;
+
+    assert.equal(received, removeQuote.solOut.toNumber());
+    assert.isTrue(
+      (await tokenBalance(treasuryMsolPda)).sub(treasuryBefore).eq(removeQuote.treasuryMsolCut)
+    );
+  });
+
+  it("Rate-changing instructions refresh the price oracle", async () => {
+    await program.methods
+      .initPriceOracle()
+      .accountsPartial({
+        rentPayer: payer,
+        adminAuthority: payer,
+        stakePoolConfig: stakePoolConfigPda,
+        priceOracle: priceOraclePda,
+      })
+      .rpc();
+    const initial = await program.account.msolPriceOracle.fetch(priceOraclePda);
+
+    // 窗口期存款手续费留在 reserve 中且不铸造 mSOL，存款后汇率上升
+    await configStakePool({
+      epochEndDepositFee: { basisPoints: 50 },
+      epochEndWindowSlots: U64_MAX,
+    });
+    const user = newUser();
+    await airdrop(user.publicKey, 5 * LAMPORTS_PER_SOL);
+    await sendInstructions(
+      [await depositIx(user, new anchor.BN(2 * LAMPORTS_PER_SOL), { priceOracle: priceOraclePda })],
+      [user]
+    );
+    await configStakePool({
+      epochEndDepositFee: { basisPoints: 0 },
+      epochEndWindowSlots: new anchor.BN(0),
+    });
+
+    const oracle = await program.account.msolPriceOracle.fetch(priceOraclePda);
+    const state = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    assert.isTrue(oracle.price.gt(initial.price), `oracle price ${oracle.price}`);
+    assert.isTrue(oracle.price.eq(state.msolPrice));
+    assert.isTrue(oracle.slot.gt(initial.slot));
+    assert.isTrue(oracle.totalStakedLamports.eq(totalStakedLamports(state)));
+    assert.isTrue(oracle.msolSupply.eq(state.msolSupply));
+  });
+
+  it("Transaction guard rejects transactions mixing pool directions", async () => {
+    const user = newUser();
+    await airdrop(user.publicKey, 10 * LAMPORTS_PER_SOL);
+    await deposit(user, new anchor.BN(2 * LAMPORTS_PER_SOL));
+    const msol = await tokenBalance(getAssociatedTokenAddressSync(msolPda, user.publicKey));
+
+    await configStakePool({ transactionGuardEnabled: true });
+    const guarded = { instructions: SYSVAR_INSTRUCTIONS_PUBKEY };
+
+    // 同一交易中先存入再解质押
+    await expectError(
+      async () =>
+        sendInstructions(
+          [
+            await depositIx(user, new anchor.BN(LAMPORTS_PER_SOL), guarded),
+            await unstakeIx(user, msol, guarded),
+          ],
+          [user]
+        ),
+      "ConflictingPoolInstructions"
+    );
+
+    // 闪电贷已在存款前归还，不触发借款锁定，但仍与存款冲突
+    const flashIxs = [
+      await program.methods
+        .flashBorrow(new anchor.BN(LAMPORTS_PER_SOL))
+        .accountsPartial({
+          stakePoolConfig: stakePoolConfigPda,
+          liqPoolSolLegPda: solLegtPda,
+          transferSolTo: user.publicKey,
+          instructions: SYSVAR_INSTRUCTIONS_PUBKEY,
+        })
+        .instruction(),
+      await program.methods
+        .flashRepay()
+        .accountsPartial({
+          repayer: user.publicKey,
+          stakePoolConfig: stakePoolConfigPda,
+          liqPoolSolLegPda: solLegtPda,
+          reservePda,
+          msolMint: msolPda,
+          treasuryMsolAccount: treasuryMsolPda,
+        })
+        .instruction(),
+    ];
+    await expectError(
+      async () =>
+        sendInstructions(
+          [...flashIxs, await depositIx(user, new anchor.BN(LAMPORTS_PER_SOL), guarded)],
+          [user]
+        ),
+      "ConflictingPoolInstructions"
+    );
+
+    // 开启检查后必须传入指令 sysvar；单一方向的交易不受影响
+    await expectError(
+      async () =>
+        sendInstructions(
+          [await depositIx(user, new anchor.BN(LAMPORTS_PER_SOL), { instructions: null })],
+          [user]
+        ),
+      "ConflictingPoolInstructions"
+    );
+    await sendInstructions(
+      [
+        await depositIx(user, new anchor.BN(LAMPORTS_PER_SOL), guarded),
+        await depositIx(user, new anchor.BN(LAMPORTS_PER_SOL), guarded),
+      ],
+      [user]
+    );
+
+    await configStakePool({ transactionGuardEnabled: false });
+  });
+
+  it("Fee overrides can waive deposit fees but not go below lp_min_fee on unstake", async () => {
+    const user = newUser();
+    const other = newUser();
+    await airdrop(user.publicKey, 10 * LAMPORTS_PER_SOL);
+    await airdrop(other.publicKey, 10 * LAMPORTS_PER_SOL);
+
+    const [feeOverride] = PublicKey.findProgramAddressSync(
+      [stakePoolConfigPda.toBuffer(), Buffer.from("fee_override"), user.publicKey.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .setFeeOverride(user.publicKey, { fixed: { 0: { basisPoints: 0 } } } as any)
+      .accountsPartial({
+        rentPayer: payer,
+        adminAuthority: payer,
+        stakePoolConfig: stakePoolConfigPda,
+        feeOverride,
+      })
+      .rpc();
+
+    await configStakePool({
+      epochEndDepositFee: { basisPoints: 100 },
+      epochEndWindowSlots: U64_MAX,
+    });
+    const amount = new anchor.BN(2 * LAMPORTS_PER_SOL);
+    const quoteDeposit = (override: PublicKey | null) =>
+      program.methods
+        .quoteDeposit(amount)
+        .accountsPartial({
+          stakePoolConfig: stakePoolConfigPda,
+          liqPoolSolLegPda: solLegtPda,
+          liqPoolMsolLeg: msolLegtPda,
+          feeOverride: override,
+        })
+        .view();
+
+    // 存款手续费没有下限，Fixed(0) 可以免除窗口期手续费
+    const full = await quoteDeposit(null);
+    const waived = await quoteDeposit(feeOverride);
+    assert.isTrue(full.epochEndFee.eq(amount.muln(100).divn(10_000)));
+    assert.isTrue(waived.epochEndFee.isZero());
+    assert.isTrue(waived.msolOut.gt(full.msolOut));
+
+    await sendInstructions([await depositIx(user, amount, { feeOverride })], [user]);
+    const msol = await tokenBalance(getAssociatedTokenAddressSync(msolPda, user.publicKey));
+    assert.isTrue(msol.eq(waived.msolOut), `quoted ${waived.msolOut}, got ${msol}`);
+
+    // 覆盖只适用于 key 本人
+    await expectError(
+      async () => sendInstructions([await depositIx(other, amount, { feeOverride })], [other]),
+      "InvalidFeeOverride"
+    );
+
+    await configStakePool({
+      epochEndDepositFee: { basisPoints: 0 },
+      epochEndWindowSlots: new anchor.BN(0),
+    });
+
+    // 流动性解质押手续费不低于 lp_min_fee
+    const state = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    const unstakeQuote = await program.methods
+      .quoteUnstake(msol)
+      .accountsPartial({
+        stakePoolConfig: stakePoolConfigPda,
+        liqPoolSolLegPda: solLegtPda,
+        feeOverride,
+      })
+      .view();
+    assert.equal(
+      unstakeQuote.liquidUnstakeFee.basisPoints,
+      state.liqPool.lpMinFee.basisPoints
+    );
+    assert.isTrue(unstakeQuote.fee.gtn(0));
+
+    const balanceBefore = await provider.connection.getBalance(user.publicKey);
+    await unstake(user, msol, { feeOverride });
+    assert.equal(
+      (await provider.connection.getBalance(user.publicKey)) - balanceBefore,
+      unstakeQuote.solOut.toNumber()
+    );
+  });
+
+  it("Adding liquidity with mSOL mints LP by the mSOL value", async () => {
+    const user = newUser();
+    await airdrop(user.publicKey, 10 * LAMPORTS_PER_SOL);
+    await deposit(user, new anchor.BN(4 * LAMPORTS_PER_SOL));
+    const userMsol = getAssociatedTokenAddressSync(msolPda, user.publicKey);
+    const msolAmount = (await tokenBalance(userMsol)).divn(2);
+
+    const state = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    const totalStaked = totalStakedLamports(state).add(VIRTUAL_VALUE);
+    const msolSupply = state.msolSupply.add(VIRTUAL_SHARES);
+    const msolLegBefore = await tokenBalance(msolLegtPda);
+    // 存入的 mSOL 价值向下取整，池子中 mSOL 价值向上取整
+    const addedValue = msolAmount.mul(totalStaked).div(msolSupply);
+    const msolLegValue = msolLegBefore.mul(totalStaked).add(msolSupply.subn(1)).div(msolSupply);
+    const poolValue = new anchor.BN(await provider.connection.getBalance(solLegtPda))
+      .sub(state.rentExemptForTokenAcc)
+      .add(state.liqPool.lentFromSolLeg)
+      .add(msolLegValue);
+    const expectedLp = addedValue
+      .mul(state.liqPool.lpSupply.add(VIRTUAL_SHARES))
+      .div(poolValue.add(VIRTUAL_VALUE));
+
+    await addLiquidityMsol(user, msolAmount);
+    const userLp = getAssociatedTokenAddressSync(lpMintPda, user.publicKey);
+    const lp = await tokenBalance(userLp);
+    assert.isTrue(lp.eq(expectedLp), `expected ${expectedLp} LP, got ${lp}`);
+    assert.isTrue((await tokenBalance(msolLegtPda)).sub(msolLegBefore).eq(msolAmount));
+
+    // 同一交易中分别用 SOL 和剩余的 mSOL 添加流动性
+    const solAmount = new anchor.BN(LAMPORTS_PER_SOL);
+    const rest = await tokenBalance(userMsol);
+    await sendInstructions(
+      [
+        await program.methods
+          .addLiquidity(solAmount)
+          .accountsPartial({
+            transferFrom: user.publicKey,
+            stakePoolConfig: stakePoolConfigPda,
+            lpMint: lpMintPda,
+            liqPoolMsolLeg: msolLegtPda,
+            liqPoolSolLegPda: solLegtPda,
+            mintTo: userLp,
+          })
+          .instruction(),
+        await program.methods
+          .addLiquidityMsol(rest)
+          .accountsPartial({
+            transferFromAuthority: user.publicKey,
+            transferFrom: userMsol,
+            stakePoolConfig: stakePoolConfigPda,
+            lpMint: lpMintPda,
+            liqPoolMsolLeg: msolLegtPda,
+            liqPoolSolLegPda: solLegtPda,
+            mintTo: userLp,
+          })
+          .instruction(),
+      ],
+      [user]
+    );
+    assert.isTrue((await tokenBalance(userMsol)).isZero());
+    assert.isTrue((await tokenBalance(userLp)).gt(lp));
+
+    await expectError(() => addLiquidityMsol(user, new anchor.BN(LAMPORTS_PER_SOL)), "NotEnoughUserFunds");
+  });
+
+  it("Single-sided LP removal pays only SOL and enforces the minimum out", async () => {
+    const user = newUser();
+    await airdrop(user.publicKey, 10 * LAMPORTS_PER_SOL);
+    const provided = new anchor.BN(3 * LAMPORTS_PER_SOL);
+    await addLiquidity(user, provided);
+    const userLp = getAssociatedTokenAddressSync(lpMintPda, user.publicKey);
+    const lp = await tokenBalance(userLp);
+    assert.isTrue((await tokenBalance(msolLegtPda)).gtn(0), "mSOL leg should hold mSOL");
+
+    const quote = await program.methods
+      .quoteRemoveLiquiditySol(lp)
+      .accountsPartial(quoteAccounts())
+      .view();
+    // 报价之外的滑点保护：实际 SOL 低于 min_sol_out 时整笔交易失败
+    await expectError(
+      () => removeLiquiditySol(user, lp, quote.solOut.addn(1)),
+      "SolOutBelowMinimum"
+    );
+
+    const msolLegBefore = await tokenBalance(msolLegtPda);
+    const treasuryBefore = await tokenBalance(treasuryMsolPda);
+    const balanceBefore = await provider.connection.getBalance(user.publicKey);
+    await removeLiquiditySol(user, lp, quote.solOut);
+    const received = (await provider.connection.getBalance(user.publicKey)) - balanceBefore;
+
+    assert.equal(received, quote.solOut.toNumber());
+    assert.isTrue((await tokenBalance(userLp)).isZero());
+    // mSOL 份额按流动性解质押换成 SOL，只有国库分成离开 mSOL leg
+    const treasuryCut = (await tokenBalance(treasuryMsolPda)).sub(treasuryBefore);
+    assert.isTrue(treasuryCut.eq(quote.treasuryMsolCut));
+    assert.isTrue(msolLegBefore.sub(await tokenBalance(msolLegtPda)).eq(treasuryCut));
+    // 换成 SOL 需要支付流动性解质押手续费，不会多于提供的流动性
+    assert.isTrue(new anchor.BN(received).lte(provided), `user received ${received} lamports`);
+  });
+
+  it("Recycling the mSOL leg refills the SOL leg from the reserve without moving prices", async () => {
+    // 大额解质押把 sol_leg 压到目标流动性之下，mSOL 进入 mSOL leg
+    const user = newUser();
+    await airdrop(user.publicKey, 100 * LAMPORTS_PER_SOL);
+    await deposit(user, new anchor.BN(90 * LAMPORTS_PER_SOL));
+    const userMsol = getAssociatedTokenAddressSync(msolPda, user.publicKey);
+    const config = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    const target = config.liqPool.lpLiquidityTarget;
+    const solLegAvailable = async () =>
+      new anchor.BN(await provider.connection.getBalance(solLegtPda)).sub(
+        config.rentExemptForTokenAcc
+      );
+    while ((await solLegAvailable()).gt(target.subn(2 * LAMPORTS_PER_SOL))) {
+      const msol = await tokenBalance(userMsol);
+      assert.isTrue(msol.gtn(0), "not enough mSOL to drain the SOL leg");
+      await unstake(user, anchor.BN.min(msol, new anchor.BN(5 * LAMPORTS_PER_SOL)));
+    }
+
+    const before = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    const solLegBefore = await solLegAvailable();
+    const msolLegBefore = await tokenBalance(msolLegtPda);
+    const reserveBefore = await provider.connection.getBalance(reservePda);
+    const poolValue = (state: typeof before, solLeg: anchor.BN, msolLeg: anchor.BN) =>
+      solLeg
+        .add(state.liqPool.lentFromSolLeg)
+        .add(
+          msolLeg
+            .mul(totalStakedLamports(state).add(VIRTUAL_VALUE))
+            .div(state.msolSupply.add(VIRTUAL_SHARES))
+        );
+
+    await program.methods
+      .recycleMsolLeg()
+      .accountsPartial({
+        stakePoolConfig: stakePoolConfigPda,
+        msolMint: msolPda,
+        liqPoolMsolLeg: msolLegtPda,
+        liqPoolSolLegPda: solLegtPda,
+        reservePda,
+      })
+      .rpc();
+
+    const after = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    const solLegAfter = await solLegAvailable();
+    const msolLegAfter = await tokenBalance(msolLegtPda);
+    const recycled = solLegAfter.sub(solLegBefore);
+    const burned = msolLegBefore.sub(msolLegAfter);
+    assert.isTrue(recycled.gtn(0) && burned.gtn(0), "nothing was recycled");
+    assert.isTrue(solLegAfter.lte(target), "SOL leg refilled beyond the target");
+    assert.equal(reserveBefore - (await provider.connection.getBalance(reservePda)), recycled.toNumber());
+    assert.isTrue(before.availableReserveBalance.sub(after.availableReserveBalance).eq(recycled));
+    assert.isTrue(before.msolSupply.sub(after.msolSupply).eq(burned));
+
+    // 销毁的 mSOL 价值向下取整后不低于转出的 SOL：mSOL 价格不降，LP 价值只差舍入
+    assert.isTrue(after.msolPrice.gte(before.msolPrice));
+    assert.isTrue(
+      poolValue(after, solLegAfter, msolLegAfter).gte(
+        poolValue(before, solLegBefore, msolLegBefore).subn(1)
+      )
+    );
+  });
+
+  it("Idle SOL leg liquidity is lent to the reserve and pulled back below the target", async () => {
+    const rebalance = () =>
+      program.methods
+        .rebalanceSolLeg()
+        .accountsPartial({
+          stakePoolConfig: stakePoolConfigPda,
+          liqPoolSolLegPda: solLegtPda,
+          reservePda,
+        })
+        .rpc();
+    const config = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    const target = config.liqPool.lpLiquidityTarget;
+    const keep = target.add(
+      target.muln(config.lendingBuffer.basisPoints).addn(9_999).divn(10_000)
+    );
+    const solLegAvailable = async () =>
+      new anchor.BN(await provider.connection.getBalance(solLegtPda)).sub(
+        config.rentExemptForTokenAcc
+      );
+
+    const holder = newUser();
+    await airdrop(holder.publicKey, 40 * LAMPORTS_PER_SOL);
+    await deposit(holder, new anchor.BN(30 * LAMPORTS_PER_SOL));
+    const lp = newUser();
+    await airdrop(lp.publicKey, 50 * LAMPORTS_PER_SOL);
+    await addLiquidity(lp, new anchor.BN(40 * LAMPORTS_PER_SOL));
+
+    // 超出目标与缓冲的部分借给 reserve；借出的 SOL 仍计入 LP 价值，不计入 mSOL 持有者
+    const before = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    const lendable = (await solLegAvailable()).sub(keep);
+    assert.isTrue(lendable.gtn(0), "SOL leg should be above the lending threshold");
+    await rebalance();
+    const lent = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    assert.isTrue((await solLegAvailable()).eq(keep));
+    assert.isTrue(lent.liqPool.lentFromSolLeg.sub(before.liqPool.lentFromSolLeg).eq(lendable));
+    assert.isTrue(lent.availableReserveBalance.sub(before.availableReserveBalance).eq(lendable));
+    assert.isTrue(totalStakedLamports(lent).eq(totalStakedLamports(before)));
+
+    // 平衡状态下再次调用不做任何事
+    await rebalance();
+    const idle = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    assert.isTrue(idle.liqPool.lentFromSolLeg.eq(lent.liqPool.lentFromSolLeg));
+
+    // 解质押把 sol_leg 压到目标之下后，从 reserve 收回借出的 SOL
+    const holderMsol = getAssociatedTokenAddressSync(msolPda, holder.publicKey);
+    while ((await solLegAvailable()).gte(target)) {
+      const msol = await tokenBalance(holderMsol);
+      assert.isTrue(msol.gtn(0), "not enough mSOL to drain the SOL leg");
+      await unstake(holder, anchor.BN.min(msol, new anchor.BN(5 * LAMPORTS_PER_SOL)));
+    }
+    const drained = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    const solLegBefore = await solLegAvailable();
+    const expectedRepay = anchor.BN.min(target.sub(solLegBefore), drained.liqPool.lentFromSolLeg);
+    await rebalance();
+    const repaid = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    assert.isTrue((await solLegAvailable()).sub(solLegBefore).eq(expectedRepay));
+    assert.isTrue(
+      drained.liqPool.lentFromSolLeg.sub(repaid.liqPool.lentFromSolLeg).eq(expectedRepay)
+    );
+    assert.isTrue(
+      drained.availableReserveBalance.sub(repaid.availableReserveBalance).eq(expectedRepay)
+    );
+    assert.isTrue(totalStakedLamports(repaid).eq(totalStakedLamports(drained)));
+  });
+
+  it("Deposits from and unstakes into wrapped SOL token accounts", async () => {
+    const user = newUser();
+    const other = newUser();
+    await airdrop(user.publicKey, 10 * LAMPORTS_PER_SOL);
+    const wsol = await createWrappedNativeAccount(
+      provider.connection,
+      user,
+      user.publicKey,
+      5 * LAMPORTS_PER_SOL
+    );
+    const userMsol = getAssociatedTokenAddressSync(msolPda, user.publicKey);
+    const [wsolTemp] = PublicKey.findProgramAddressSync(
+      [stakePoolConfigPda.toBuffer(), Buffer.from("wsol_temp"), user.publicKey.toBuffer()],
+      program.programId
+    );
+
+    const amount = new anchor.BN(2 * LAMPORTS_PER_SOL);
+    const depositQuote = await program.methods
+      .quoteDeposit(amount)
+      .accountsPartial({
+        stakePoolConfig: stakePoolConfigPda,
+        liqPoolSolLegPda: solLegtPda,
+        liqPoolMsolLeg: msolLegtPda,
+      })
+      .view();
+    await program.methods
+      .depositWsol(amount)
+      .accountsPartial({
+        deposit: {
+          user: user.publicKey,
+          stakePoolConfig: stakePoolConfigPda,
+          msolMint: msolPda,
+          liqPoolSolLegPda: solLegtPda,
+          liqPoolMsolLeg: msolLegtPda,
+          treasuryMsolAccount: treasuryMsolPda,
+          reservePda,
+          mintTo: userMsol,
+        },
+        wsolFrom: wsol,
+        wsolTemp,
+        wsolMint: NATIVE_MINT,
+      } as any)
+      .signers([user])
+      .rpc();
+
+    assert.isTrue((await tokenBalance(wsol)).eq(new anchor.BN(3 * LAMPORTS_PER_SOL)));
+    const msol = await tokenBalance(userMsol);
+    assert.isTrue(msol.eq(depositQuote.msolOut), `quoted ${depositQuote.msolOut}, got ${msol}`);
+    // 临时账户在指令内关闭，不留下租金
+    assert.isNull(await provider.connection.getAccountInfo(wsolTemp));
+
+    const unstakeToWsol = (transferSolTo: PublicKey) =>
+      program.methods
+        .unstakeToWsol(msol)
+        .accountsPartial({
+          unstake: {
+            getMsolFromAuthority: user.publicKey,
+            stakePoolConfig: stakePoolConfigPda,
+            msolMint: msolPda,
+            treasuryMsolAccount: treasuryMsolPda,
+            liqPoolSolLegPda: solLegtPda,
+            liqPoolMsolLeg: msolLegtPda,
+            getMsolFrom: userMsol,
+            transferSolTo,
+          },
+          wsolTo: wsol,
+        } as any)
+        .signers([user])
+        .rpc();
+
+    // SOL 必须先转给 mSOL 持有者本人再包装
+    await expectError(() => unstakeToWsol(other.publicKey), "InvalidWsolUnstakeRecipient");
+
+    const unstakeQuote = await program.methods
+      .quoteUnstake(msol)
+      .accountsPartial({ stakePoolConfig: stakePoolConfigPda, liqPoolSolLegPda: solLegtPda })
+      .view();
+    const lamportsBefore = await provider.connection.getBalance(user.publicKey);
+    await unstakeToWsol(user.publicKey);
+
+    assert.isTrue((await tokenBalance(userMsol)).isZero());
+    assert.isTrue(
+      (await tokenBalance(wsol)).eq(unstakeQuote.solOut.addn(3 * LAMPORTS_PER_SOL))
+    );
+    assert.equal(await provider.connection.getBalance(user.publicKey), lamportsBefore);
+  });
+
+  it("Deposit, unstake and liquidity instructions return their results", async () => {
+    // 按 IDL 类型解码交易写入的 return data
+    const returnData = async (signature: string, typeName: string) => {
+      const latest = await provider.connection.getLatestBlockhash();
+      await provider.connection.confirmTransaction({ signature, ...latest }, "confirmed");
+      const tx = await provider.connection.getTransaction(signature, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+      const { programId, data } = tx.meta.returnData;
+      assert.isTrue(new PublicKey(programId).equals(program.programId));
+      return program.coder.types.decode(typeName, Buffer.from(data[0], "base64"));
+    };
+
+    const user = newUser();
+    await airdrop(user.publicKey, 10 * LAMPORTS_PER_SOL);
+    const userMsol = getAssociatedTokenAddressSync(msolPda, user.publicKey);
+    const userLp = getAssociatedTokenAddressSync(lpMintPda, user.publicKey);
+
+    const depositResult = await returnData(
+      await sendInstructions([await depositIx(user, new anchor.BN(3 * LAMPORTS_PER_SOL))], [user]),
+      "DepositResult"
+    );
+    const msol = await tokenBalance(userMsol);
+    assert.isTrue(depositResult.msolOut.eq(msol));
+    assert.isTrue(depositResult.msolSwapped.add(depositResult.msolMinted).gte(msol));
+
+    const half = msol.divn(2);
+    const balanceBefore = await provider.connection.getBalance(user.publicKey);
+    const unstakeResult = await returnData(
+      await sendInstructions([await unstakeIx(user, half)], [user]),
+      "UnstakeResult"
+    );
+    assert.equal(
+      (await provider.connection.getBalance(user.publicKey)) - balanceBefore,
+      unstakeResult.solOut.toNumber()
+    );
+
+    const addResult = await returnData(
+      await sendInstructions(
+        [
+          await program.methods
+            .addLiquidity(new anchor.BN(LAMPORTS_PER_SOL))
+            .accountsPartial({
+              transferFrom: user.publicKey,
+              stakePoolConfig: stakePoolConfigPda,
+              lpMint: lpMintPda,
+              liqPoolMsolLeg: msolLegtPda,
+              liqPoolSolLegPda: solLegtPda,
+              mintTo: userLp,
+            })
+            .instruction(),
+        ],
+        [user]
+      ),
+      "AddLiquidityResult"
+    );
+    const lp = await tokenBalance(userLp);
+    assert.isTrue(addResult.lpMinted.eq(lp));
+
+    const removeBefore = await provider.connection.getBalance(user.publicKey);
+    const removeResult = await returnData(
+      await sendInstructions(
+        [
+          await program.methods
+            .removeLiquiditySol(lp, new anchor.BN(0))
+            .accountsPartial({
+              burnFromAuthority: user.publicKey,
+              burnFrom: userLp,
+              stakePoolConfig: stakePoolConfigPda,
+              lpMint: lpMintPda,
+              transferSolTo: user.publicKey,
+              liqPoolSolLegPda: solLegtPda,
+              liqPoolMsolLeg: msolLegtPda,
+              treasuryMsolAccount: treasuryMsolPda,
+            })
+            .instruction(),
+        ],
+        [user]
+      ),
+      "RemoveLiquiditySolResult"
+    );
+    assert.equal(
+      (await provider.connection.getBalance(user.publicKey)) - removeBefore,
+      removeResult.solOut.toNumber()
+    );
+  });
+
+  it("Referrals take their share of the treasury fee on unstake", async () => {
+    const partner = newUser();
+    const user = newUser();
+    await airdrop(partner.publicKey, LAMPORTS_PER_SOL);
+    await airdrop(user.publicKey, 10 * LAMPORTS_PER_SOL);
+    const partnerMsol = await createAta(partner, msolPda);
+    const [referral] = PublicKey.findProgramAddressSync(
+      [stakePoolConfigPda.toBuffer(), Buffer.from("referral"), partner.publicKey.toBuffer()],
+      program.programId
+    );
+    const FEE_SHARE_BPS = 5_000;
+    await program.methods
+      .addReferral({ basisPoints: FEE_SHARE_BPS })
+      .accountsPartial({
+        rentPayer: payer,
+        adminAuthority: payer,
+        stakePoolConfig: stakePoolConfigPda,
+        partner: partner.publicKey,
+        partnerMsolAccount: partnerMsol,
+        referral,
+      })
+      .rpc();
+
+    const amount = new anchor.BN(4 * LAMPORTS_PER_SOL);
+    await sendInstructions(
+      [await depositIx(user, amount, { referral, referralMsolAccount: partnerMsol })],
+      [user]
+    );
+    const msol = await tokenBalance(getAssociatedTokenAddressSync(msolPda, user.publicKey));
+
+    // 分成只能转入登记的合作方 mSOL 账户
+    await expectError(
+      async () =>
+        unstake(user, msol, {
+          referral,
+          referralMsolAccount: getAssociatedTokenAddressSync(msolPda, user.publicKey),
+        }),
+      "InvalidReferralAccount"
+    );
+
+    const quote = await program.methods
+      .quoteUnstake(msol)
+      .accountsPartial({ stakePoolConfig: stakePoolConfigPda, liqPoolSolLegPda: solLegtPda })
+      .view();
+    assert.isTrue(quote.treasuryMsolCut.gtn(1), "unstake should pay a treasury cut");
+    const partnerCut = quote.treasuryMsolCut.muln(FEE_SHARE_BPS).divn(10_000);
+    const treasuryBefore = await tokenBalance(treasuryMsolPda);
+    await unstake(user, msol, { referral, referralMsolAccount: partnerMsol });
+
+    // 合作方分得国库分成的 FEE_SHARE_BPS（向下取整），余数留给国库
+    assert.isTrue((await tokenBalance(partnerMsol)).eq(partnerCut));
+    assert.isTrue(
+      (await tokenBalance(treasuryMsolPda))
+        .sub(treasuryBefore)
+        .eq(quote.treasuryMsolCut.sub(partnerCut))
+    );
+
+    const stats = await program.account.referralAccount.fetch(referral);
+    assert.isTrue(stats.depositedLamports.eq(amount));
+    assert.isTrue(stats.unstakedMsol.eq(msol));
+    assert.isTrue(stats.msolEarned.eq(partnerCut));
+  });
+
+  it("Permissioned mode rejects callers without their own allowlist entry", async () => {
+    const allowed = newUser();
+    const stranger = newUser();
+    await airdrop(allowed.publicKey, 10 * LAMPORTS_PER_SOL);
+    await airdrop(stranger.publicKey, 10 * LAMPORTS_PER_SOL);
+    const allowlistPda = (user: PublicKey) =>
+      PublicKey.findProgramAddressSync(
+        [stakePoolConfigPda.toBuffer(), Buffer.from("allowlist"), user.toBuffer()],
+        program.programId
+      )[0];
+    const allowedEntry = allowlistPda(allowed.publicKey);
+    await program.methods
+      .addToAllowlist(allowed.publicKey)
+      .accountsPartial({
+        rentPayer: payer,
+        allowlistAuthority: payer,
+        stakePoolConfig: stakePoolConfigPda,
+        allowlistEntry: allowedEntry,
+      })
+      .rpc();
+    await configStakePool({ permissioned: true });
+
+    const amount = new anchor.BN(LAMPORTS_PER_SOL);
+    await expectError(
+      async () => sendInstructions([await depositIx(stranger, amount)], [stranger]),
+      "NotAllowlisted"
+    );
+    // 白名单账户只对本人有效
+    await expectError(
+      async () =>
+        sendInstructions([await depositIx(stranger, amount, { allowlistEntry: allowedEntry })], [stranger]),
+      "NotAllowlisted"
+    );
+    await expectError(
+      async () =>
+        program.methods
+          .addLiquidity(amount)
+          .accountsPartial({
+            transferFrom: stranger.publicKey,
+            stakePoolConfig: stakePoolConfigPda,
+            lpMint: lpMintPda,
+            liqPoolMsolLeg: msolLegtPda,
+            liqPoolSolLegPda: solLegtPda,
+            mintTo: getAssociatedTokenAddressSync(lpMintPda, stranger.publicKey),
+          })
+          .signers([stranger])
+          .rpc(),
+      "NotAllowlisted"
+    );
+
+    await sendInstructions(
+      [await depositIx(allowed, amount.muln(2), { allowlistEntry: allowedEntry })],
+      [allowed]
+    );
+    const msol = await tokenBalance(getAssociatedTokenAddressSync(msolPda, allowed.publicKey));
+    await expectError(() => unstake(allowed, msol.divn(2)), "NotAllowlisted");
+    await unstake(allowed, msol.divn(2), { allowlistEntry: allowedEntry });
+
+    // 移出白名单后账户被关闭，不能再用于存取
+    await program.methods
+      .removeFromAllowlist()
+      .accountsPartial({
+        allowlistAuthority: payer,
+        stakePoolConfig: stakePoolConfigPda,
+        allowlistEntry: allowedEntry,
+        rentCollector: payer,
+      })
+      .rpc();
+    assert.isNull(await provider.connection.getAccountInfo(allowedEntry));
+    await expectError(
+      async () => unstake(allowed, msol.divn(4), { allowlistEntry: allowedEntry }),
+      "AccountNotInitialized"
+    );
+
+    // 关闭许可模式后恢复开放
+    await configStakePool({ permissioned: false });
+    await unstake(allowed, msol.divn(4));
+  });
+
+  it("Per-epoch and per-wallet deposit limits reset with the epoch", async () => {
+    const first = newUser();
+    const second = newUser();
+    await airdrop(first.publicKey, 10 * LAMPORTS_PER_SOL);
+    await airdrop(second.publicKey, 10 * LAMPORTS_PER_SOL);
+    const recordPda = (user: PublicKey) =>
+      PublicKey.findProgramAddressSync(
+        [stakePoolConfigPda.toBuffer(), Buffer.from("user_deposit"), user.toBuffer()],
+        program.programId
+      )[0];
+    for (const user of [first, second]) {
+      await program.methods
+        .initUserDepositRecord()
+        .accountsPartial({
+          rentPayer: payer,
+          user: user.publicKey,
+          stakePoolConfig: stakePoolConfigPda,
+          userDepositRecord: recordPda(user.publicKey),
+        })
+        .rpc();
+    }
+    const depositWithRecord = async (user: Keypair, lamports: number) =>
+      sendInstructions(
+        [
+          await depositIx(user, new anchor.BN(lamports), {
+            userDepositRecord: recordPda(user.publicKey),
+          }),
+        ],
+        [user]
+      );
+
+    await configStakePool({
+      epochDepositLimit: new anchor.BN(3 * LAMPORTS_PER_SOL),
+      userEpochDepositLimit: new anchor.BN(2 * LAMPORTS_PER_SOL),
+    });
+    // 以下存款都在同一个 epoch 内完成
+    await waitForFreshEpoch();
+
+    // 开启钱包限制后必须传入本人的存款记录
+    await expectError(
+      async () => sendInstructions([await depositIx(first, new anchor.BN(LAMPORTS_PER_SOL))], [first]),
+      "InvalidUserDepositRecord"
+    );
+    await expectError(
+      async () =>
+        sendInstructions(
+          [
+            await depositIx(first, new anchor.BN(LAMPORTS_PER_SOL), {
+              userDepositRecord: recordPda(second.publicKey),
+            }),
+          ],
+          [first]
+        ),
+      "InvalidUserDepositRecord"
+    );
+
+    await depositWithRecord(first, 2 * LAMPORTS_PER_SOL);
+    await expectError(() => depositWithRecord(first, LAMPORTS_PER_SOL), "UserDepositLimitExceeded");
+    await depositWithRecord(second, LAMPORTS_PER_SOL);
+    await expectError(() => depositWithRecord(second, LAMPORTS_PER_SOL), "EpochDepositLimitExceeded");
+
+    const record = await program.account.userDepositRecord.fetch(recordPda(first.publicKey));
+    const state = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    assert.isTrue(record.deposited.eqn(2 * LAMPORTS_PER_SOL));
+    assert.isTrue(record.epoch.eq(state.depositEpoch));
+    assert.isTrue(state.epochDeposited.eqn(3 * LAMPORTS_PER_SOL));
+
+    // 进入新 epoch 后两项计数都归零
+    await waitForEpoch(record.epoch.toNumber() + 1);
+    await depositWithRecord(first, 2 * LAMPORTS_PER_SOL);
+    const next = await program.account.userDepositRecord.fetch(recordPda(first.publicKey));
+    assert.isTrue(next.epoch.gt(record.epoch));
+    assert.isTrue(next.deposited.eqn(2 * LAMPORTS_PER_SOL));
+
+    await configStakePool({ epochDepositLimit: U64_MAX, userEpochDepositLimit: U64_MAX });
+  });
+
+  it("Delegating reserve SOL leaves total_staked_lamports unchanged", async () => {
+    const user = newUser();
+    await airdrop(user.publicKey, 20 * LAMPORTS_PER_SOL);
+    await deposit(user, new anchor.BN(10 * LAMPORTS_PER_SOL));
+    const validator = await createVoteAccount();
+    await addValidator(validator, 100);
+
+    // 委托的 SOL 从 reserve 转入 active_balance，池子总价值与 mSOL 价格不变
+    const before = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    const stakeAccount = await stakeReserve(validator);
+    const after = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    const amount = (await stakeRecords())[await stakeIndex(stakeAccount)].lastUpdateDelegatedLamports;
+    assert.isTrue(amount.gtn(0));
+    assert.isTrue(
+      after.validatorSystem.totalActiveBalance.eq(before.validatorSystem.totalActiveBalance.add(amount))
+    );
+    assert.isTrue(after.availableReserveBalance.eq(before.availableReserveBalance.sub(amount)));
+    assert.isTrue(totalStakedLamports(after).eq(totalStakedLamports(before)));
+    assert.isTrue(after.msolPrice.eq(before.msolPrice));
+  });
+
+  it("Stake accounts derive from the validator vote and the counter", async () => {
+    const user = newUser();
+    await airdrop(user.publicKey, 20 * LAMPORTS_PER_SOL);
+    await deposit(user, new anchor.BN(10 * LAMPORTS_PER_SOL));
+    const first = await createVoteAccount();
+    const second = await createVoteAccount();
+    await addValidator(first, 100);
+    await addValidator(second, 100);
+
+    const fetchState = () => program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    const delegated = async (stakeAccount: PublicKey) =>
+      (await stakeRecords())[await stakeIndex(stakeAccount)].lastUpdateDelegatedLamports;
+
+    // 地址由 validator_vote 与当前计数器推导，创建后计数器加一
+    let before = await fetchState();
+    const firstStake = await stakeReserve(first);
+    const after = await fetchState();
+    assert.isTrue(firstStake.equals(stakeAccountPda(first, before.stakeSystem.stakeAccountCounter)));
+    assert.isTrue(after.stakeSystem.stakeAccountCounter.eq(before.stakeSystem.stakeAccountCounter.addn(1)));
+    const firstAmount = await delegated(firstStake);
+    assert.isTrue(firstAmount.gtn(0));
+
+    // 下一个地址已被他人预先转入 lamports：只补足租金差额，质押照常创建
+    before = after;
+    const prefunded = stakeAccountPda(second, before.stakeSystem.stakeAccountCounter);
+    const rentExempt = await provider.connection.getMinimumBalanceForRentExemption(StakeProgram.space);
+    await donate(user, prefunded, Math.floor(rentExempt / 2));
+    assert.isTrue((await stakeReserve(second)).equals(prefunded));
+    const secondAmount = await delegated(prefunded);
+    assert.equal(
+      await provider.connection.getBalance(prefunded),
+      rentExempt + secondAmount.toNumber()
+    );
+  });
+
+  it("Stake account rent comes from the operational account within its epoch budget", async () => {
+    const user = newUser();
+    await airdrop(user.publicKey, 300 * LAMPORTS_PER_SOL);
+    await donate(user, operationalSolAccount, LAMPORTS_PER_SOL);
+    const rentExempt = await provider.connection.getMinimumBalanceForRentExemption(StakeProgram.space);
+    await configStakePool({ operationalBudgetPerEpoch: new anchor.BN(rentExempt) });
+
+    const votes = [await createVoteAccount(), await createVoteAccount(), await createVoteAccount()];
+    for (const vote of votes) await addValidator(vote, 100);
+    const balance = (address: PublicKey) => provider.connection.getBalance(address);
+    // 质押并返回质押记录所在的 epoch，避免与测试中读取的 epoch 产生竞争
+    const stakeEpoch = async (vote: PublicKey) => {
+      await depositToReserve(user, 5 * LAMPORTS_PER_SOL);
+      const stakeAccount = await stakeReserve(vote);
+      const record = (await stakeRecords())[await stakeIndex(stakeAccount)];
+      assert.isDefined(record, "stake account was not created");
+      return record.lastUpdateEpoch;
+    };
+
+    // 预算内由运营账户支付租金
+    await waitForFreshEpoch();
+    let operationalBefore = await balance(operationalSolAccount);
+    const epoch = await stakeEpoch(votes[0]);
+    assert.equal(await balance(operationalSolAccount), operationalBefore - rentExempt);
+    let state = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    assert.isTrue(state.operationalSpent.eqn(rentExempt));
+    assert.isTrue(state.operationalSpentEpoch.eq(epoch));
+
+    // 同一 epoch 内预算已用完，改由 rent_payer 支付
+    operationalBefore = await balance(operationalSolAccount);
+    const payerBefore = await balance(payer);
+    assert.isTrue((await stakeEpoch(votes[1])).eq(epoch), "epoch ended during the test");
+    assert.equal(await balance(operationalSolAccount), operationalBefore);
+    assert.isAtLeast(payerBefore - (await balance(payer)), rentExempt);
+
+    // 进入新 epoch 后支出计数归零，运营账户重新支付
+    await waitForFreshEpoch();
+    operationalBefore = await balance(operationalSolAccount);
+    const nextEpoch = await stakeEpoch(votes[2]);
+    assert.equal(await balance(operationalSolAccount), operationalBefore - rentExempt);
+    state = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    assert.isTrue(state.operationalSpent.eqn(rentExempt));
+    assert.isTrue(state.operationalSpentEpoch.eq(nextEpoch));
+
+    await configStakePool({ operationalBudgetPerEpoch: OPERATIONAL_BUDGET });
+  });
+
+  it("Crank bounties are paid per stake up to the epoch cap and never for donated lamports", async () => {
+    const user = newUser();
+    await airdrop(user.publicKey, 300 * LAMPORTS_PER_SOL);
+    await donate(user, operationalSolAccount, LAMPORTS_PER_SOL);
+    const BOUNTY = 1_000_000;
+    const CAP = 1_500_000;
+    await configStakePool({
+      crankBounty: new anchor.BN(BOUNTY),
+      crankBountyCapPerEpoch: new anchor.BN(CAP),
+    });
+    const rentExempt = await provider.connection.getMinimumBalanceForRentExemption(StakeProgram.space);
+    const balance = (address: PublicKey) => provider.connection.getBalance(address);
+    const fetchState = () => program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+
+    const votes = [await createVoteAccount(), await createVoteAccount()];
+    for (const vote of votes) await addValidator(vote, 100);
+
+    // 每创建一个质押账户支付一次奖励，本 epoch 的总额不超过上限
+    await waitForFreshEpoch();
+    const stakes: PublicKey[] = [];
+    const expectedBounties = [BOUNTY, CAP - BOUNTY];
+    for (const [i, vote] of votes.entries()) {
+      await depositToReserve(user, 5 * LAMPORTS_PER_SOL);
+      const operationalBefore = await balance(operationalSolAccount);
+      stakes.push(await stakeReserve(vote));
+      assert.equal(
+        operationalBefore - (await balance(operationalSolAccount)),
+        rentExempt + expectedBounties[i]
+      );
+    }
+    const records = await stakeRecords();
+    const epoch = records[await stakeIndex(stakes[0])].lastUpdateEpoch;
+    assert.isTrue(records[await stakeIndex(stakes[1])].lastUpdateEpoch.eq(epoch), "epoch ended during the test");
+    const state = await fetchState();
+    assert.isTrue(state.crankBountyPaid.eqn(CAP));
+    assert.isTrue(state.crankBountyPaidEpoch.eq(epoch));
+
+    // 直接转入质押账户的 lamports 不增加委托数量：update_stake 不把它作为收益入账，
+    // 不铸造手续费、不改变 mSOL 价格，也不支付调用奖励
+    await donate(user, stakes[0], LAMPORTS_PER_SOL / 10);
+    await waitForFreshEpoch();
+    const treasuryBefore = await tokenBalance(treasuryMsolPda);
+    for (const [i, stake] of stakes.entries()) {
+      const operationalBefore = await balance(operationalSolAccount);
+      await updateStake(stake, votes[i]);
+      assert.equal(await balance(operationalSolAccount), operationalBefore);
+    }
+    const after = await fetchState();
+    assert.isTrue(after.validatorSystem.totalActiveBalance.eq(state.validatorSystem.totalActiveBalance));
+    assert.isTrue(totalStakedLamports(after).eq(totalStakedLamports(state)));
+    assert.isTrue((await tokenBalance(treasuryMsolPda)).eq(treasuryBefore));
+    assert.isTrue(after.crankBountyPaidEpoch.eq(epoch));
+    const updated = await stakeRecords();
+    for (const stake of stakes) {
+      const index = await stakeIndex(stake);
+      assert.isTrue(updated[index].lastUpdateEpoch.gt(epoch));
+      assert.isTrue(updated[index].lastUpdateDelegatedLamports.eq(records[index].lastUpdateDelegatedLamports));
+    }
+
+    await configStakePool({ crankBounty: new anchor.BN(0) });
+  });
+
+  it("A full stake_reserve_batch stakes every pair and only counts created stake accounts", async () => {
+    const user = newUser();
+    const fetchState = () => program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    const votes: PublicKey[] = [];
+    for (let i = 0; i < 5; i++) {
+      votes.push(await createVoteAccount());
+      await addValidator(votes[i], 100);
+    }
+
+    // 新验证者的缺口相同且最大，按序号依次质押；保证前 4 个补足目标后仍剩 min_stake 以上给第 5 个
+    let state = await fetchState();
+    const share = (100 * (votes.length - 1)) / state.validatorSystem.totalValidatorScore;
+    const minStake = state.stakeSystem.minStake.toNumber();
+    const needed = Math.ceil(
+      (share * state.validatorSystem.totalActiveBalance.toNumber() + 2 * minStake) / (1 - share)
+    );
+    const stakeDelta = Math.min(
+      (await provider.connection.getBalance(reservePda)) - state.rentExemptForTokenAcc.toNumber(),
+      state.availableReserveBalance.toNumber()
+    );
+    await airdrop(user.publicKey, Math.max(needed - stakeDelta, 0) + 300 * LAMPORTS_PER_SOL);
+    if (needed > stakeDelta) await depositToReserve(user, needed - stakeDelta);
+
+    const counter = state.stakeSystem.stakeAccountCounter;
+    const { signature, stakeAccounts } = await stakeReserveBatch(votes, [
+      ComputeBudgetProgram.setComputeUnitLimit({ units: 1_400_000 }),
+    ]);
+    state = await fetchState();
+    assert.isTrue(state.stakeSystem.stakeAccountCounter.eq(counter.addn(votes.length)));
+    const records = await stakeRecords();
+    for (const stakeAccount of stakeAccounts) {
+      const index = await stakeIndex(stakeAccount);
+      assert.isAtLeast(index, 0);
+      assert.isTrue(records[index].lastUpdateDelegatedLamports.gtn(0));
+    }
+    const tx = await provider.connection.getTransaction(signature, {
+      commitment: "confirmed",
+      maxSupportedTransactionVersion: 0,
+    });
+    console.log(`stake_reserve_batch with ${votes.length} pairs used ${tx.meta.computeUnitsConsumed} CU`);
+    assert.isBelow(tx.meta.computeUnitsConsumed, 1_400_000);
+
+    // 第一个验证者用完 stake_delta 后停止，后面的对不创建质押账户也不占用计数器
+    const greedy = await createVoteAccount();
+    const starved = await createVoteAccount();
+    await addValidator(greedy, 10_000);
+    await addValidator(starved, 100);
+    await depositToReserve(user, 5 * LAMPORTS_PER_SOL);
+    const before = (await fetchState()).stakeSystem.stakeAccountCounter;
+    const batch = await stakeReserveBatch([greedy, starved]);
+    assert.isAtLeast(await stakeIndex(batch.stakeAccounts[0]), 0);
+    assert.isNull(await provider.connection.getAccountInfo(batch.stakeAccounts[1]));
+    assert.isTrue((await fetchState()).stakeSystem.stakeAccountCounter.eq(before.addn(1)));
+
+    // 不影响后续测试的分配
+    await setValidatorScore(greedy, 0);
+    await setValidatorScore(starved, 0);
+  });
+
+  it("Stake goes to the largest shortfall against the score share of the whole pool", async () => {
+    const user = newUser();
+    await airdrop(user.publicKey, 300 * LAMPORTS_PER_SOL);
+    const first = await createVoteAccount();
+    const second = await createVoteAccount();
+    await addValidator(first, 100);
+    await addValidator(second, 100);
+    await depositToReserve(user, 5 * LAMPORTS_PER_SOL);
+
+    // 缺口相同时序号较小者优先，单独质押或批量质押其他验证者都会被拒绝
+    await expectError(() => stakeReserve(second), "ValidatorNotMostUnderAllocated");
+    await expectError(() => stakeReserveBatch([second, first]), "ValidatorNotMostUnderAllocated");
+
+    // 目标 = (total_active_balance + stake_delta) * score / total_validator_score
+    const state = await program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    const stakeDelta = anchor.BN.min(
+      new anchor.BN(await provider.connection.getBalance(reservePda)).sub(state.rentExemptForTokenAcc),
+      state.availableReserveBalance
+    );
+    const totalStakeTarget = state.validatorSystem.totalActiveBalance.add(stakeDelta);
+    const totalScore = state.validatorSystem.totalValidatorScore;
+    const target = totalStakeTarget.muln(100).divn(totalScore);
+    let amount = anchor.BN.min(target, stakeDelta);
+    if (stakeDelta.sub(amount).lt(state.stakeSystem.minStake)) amount = stakeDelta;
+
+    const stakeAccount = await stakeReserve(first);
+    const [{ signature }] = await provider.connection.getSignaturesForAddress(stakeAccount, {}, "confirmed");
+    const tx = await provider.connection.getTransaction(signature, {
+      commitment: "confirmed",
+      maxSupportedTransactionVersion: 0,
+    });
+    const events = [
+      ...new anchor.EventParser(program.programId, program.coder).parseLogs(tx.meta.logMessages),
+    ];
+    const event = (name: string) => events.find((e) => e.name.toLowerCase() === name.toLowerCase()).data;
+
+    const staked = event("stakeReserveEvent");
+    assert.isTrue(staked.validatorStakeTarget.eq(target));
+    assert.isTrue(staked.amount.eq(amount));
+    assert.isTrue((await stakeRecords())[await stakeIndex(stakeAccount)].lastUpdateDelegatedLamports.eq(amount));
+
+    // 只上报偏差最大的若干验证者，每一项都按同一目标计算
+    const distribution = event("stakeDistributionEvent");
+    assert.isTrue(distribution.totalStakeTarget.eq(totalStakeTarget));
+    assert.isAtMost(distribution.deviations.length, 10);
+    const validators = await validatorRecords();
+    let previous = Infinity;
+    for (const { validatorIndex: index, activeBalance, stakeTarget, deviation } of distribution.deviations) {
+      const validator = validators[index];
+      assert.isTrue(stakeTarget.eq(totalStakeTarget.muln(validator.score).divn(totalScore)));
+      assert.isTrue(activeBalance.eq(validator.activeBalance));
+      assert.isTrue(deviation.eq(activeBalance.sub(stakeTarget)));
+      assert.isAtMost(deviation.abs().toNumber(), previous);
+      previous = deviation.abs().toNumber();
+    }
+
+    // 未质押的验证者不再参与后续测试的排序
+    await setValidatorScore(second, 0);
+  });
+
+  // 必须是最后一个测试：关闭后质押池不再可用
+  it("Winding down returns all SOL to holders and closes the pool", async () => {
+    users.set(payer.toBase58(), (provider.wallet as anchor.Wallet).payer);
+    const holder = (owner: PublicKey) => {
+      const user = users.get(owner.toBase58());
+      assert.isDefined(user, `unknown holder ${owner.toBase58()}`);
+      return user;
+    };
+    const fetchState = () => program.account.stakePoolConfig.fetch(stakePoolConfigPda);
+    const closePool = async (rentCollector: PublicKey, treasuryDestination: PublicKey) => {
+      const state = await fetchState();
+      return program.methods
+        .closePool()
+        .accountsPartial({
+          adminAuthority: payer,
+          rentCollector,
+          treasuryDestination,
+          stakePoolConfig: stakePoolConfigPda,
+          msolMint: msolPda,
+          stakeList: state.stakeSystem.stakeList.account,
+          validatorList: state.validatorSystem.validatorList.account,
+          reservePda,
+          liqPoolSolLegPda: solLegtPda,
+          liqPoolMsolLeg: msolLegtPda,
+          treasuryMsolAccount: treasuryMsolPda,
+          operationalSolAccount,
+        })
+        .rpc();
+    };
+
+    await program.methods
+      .beginWindDown()
+      .accountsPartial({ adminAuthority: payer, stakePoolConfig: stakePoolConfigPda })
+      .rpc();
+    const latecomer = newUser();
+    await airdrop(latecomer.publicKey, 2 * LAMPORTS_PER_SOL);
+    await expectError(() => deposit(latecomer, new anchor.BN(LAMPORTS_PER_SOL)), "PoolIsWindingDown");
+    await expectError(() => closePool(payer, payer), "PoolNotEmpty");
+
+    // 1. 解除全部质押，冷却完成后取回 reserve
+    for (const { stakeAccount } of await stakeRecords()) {
+      const state = await fetchState();
+      await program.methods
+        .deactivateStake(await stakeIndex(stakeAccount))
+        .accountsPartial({
+          stakePoolConfig: stakePoolConfigPda,
+          stakeList: state.stakeSystem.stakeList.account,
+          stakeAccount,
+          stakeProgram: StakeProgram.programId,
+        })
+        .rpc();
+    }
+    const withdrawStake = async (stakeAccount: PublicKey) => {
+      const state = await fetchState();
+      const info = await provider.connection.getParsedAccountInfo(stakeAccount);
+      const vote = new PublicKey(
+        (info.value.data as ParsedAccountData).parsed.info.stake.delegation.voter
+      );
+      await program.methods
+        .withdrawStake(await stakeIndex(stakeAccount), await validatorIndex(vote))
+        .accountsPartial({
+          stakePoolConfig: stakePoolConfigPda,
+          validatorList: state.validatorSystem.validatorList.account,
+          stakeList: state.stakeSystem.stakeList.account,
+          stakeAccount,
+          reservePda,
+          stakeHistory: SYSVAR_STAKE_HISTORY_PUBKEY,
+          priceOracle: null,
+          stakeProgram: StakeProgram.programId,
+        })
+        .rpc();
+    };
+    let cooling = (await stakeRecords()).map(({ stakeAccount }) => stakeAccount);
+    for (let epochs = 0; cooling.length > 0; epochs++) {
+      assert.isBelow(epochs, 20, "stake cooldown did not finish");
+      await waitForFreshEpoch();
+      const pending: PublicKey[] = [];
+      for (const stakeAccount of cooling) {
+        await withdrawStake(stakeAccount).catch(() => pending.push(stakeAccount));
+      }
+      cooling = pending;
+    }
+    let state = await fetchState();
+    assert.equal(state.stakeSystem.stakeList.count, 0);
+    assert.isTrue(state.validatorSystem.totalActiveBalance.isZero());
+
+    // 2. reserve 归还 sol_leg 的借款，LP 全部退出
+    await program.methods
+      .rebalanceSolLeg()
+      .accountsPartial({
+        stakePoolConfig: stakePoolConfigPda,
+        liqPoolSolLegPda: solLegtPda,
+        reservePda,
+      })
+      .rpc();
+    assert.isTrue((await fetchState()).liqPool.lentFromSolLeg.isZero());
+
+    for (const { address, owner, amount } of await tokenAccounts(lpMintPda)) {
+      const user = holder(owner);
+      const msolAta = await createAta(user, msolPda);
+      await program.methods
+        .removeLiquidity(amount)
+        .accountsPartial({
+          burnFromAuthority: owner,
+          burnFrom: address,
+          stakePoolConfig: stakePoolConfigPda,
+          lpMint: lpMintPda,
+          transferSolTo: owner,
+          transferMsolTo: msolAta,
+          liqPoolSolLegPda: solLegtPda,
+          liqPoolMsolLeg: msolLegtPda,
+          allowlistEntry: null,
+          instructions: null,
+        })
+        .signers([user])
+        .rpc();
+    }
+    assert.isTrue((await fetchState()).liqPool.lpSupply.isZero());
+
+    // 3. 所有用户按当前价格赎回 mSOL，国库与 mSOL leg 的余额留到关闭时处理
+    for (const { address, owner, amount } of await tokenAccounts(msolPda)) {
+      if (address.equals(treasuryMsolPda) || address.equals(msolLegtPda)) continue;
+      const user = holder(owner);
+      const before = await provider.connection.getBalance(owner);
+      await program.methods
+        .windDownRedeem(amount)
+        .accountsPartial({
+          burnMsolAuthority: owner,
+          stakePoolConfig: stakePoolConfigPda,
+          msolMint: msolPda,
+          burnMsolFrom: address,
+          transferSolTo: owner,
+          reservePda,
+          priceOracle: null,
+        })
+        .signers([user])
+        .rpc();
+      assert.isAbove(await provider.connection.getBalance(owner), before);
+    }
+
+    // 4. 移除全部验证者
+    for (const { validatorAccount } of await validatorRecords()) {
+      state = await fetchState();
+      await program.methods
+        .removeValidator(await validatorIndex(validatorAccount), validatorAccount)
+        .accountsPartial({
+          stakePoolConfig: stakePoolConfigPda,
+          operationalSolAccount,
+          managerAuthority: payer,
+          validatorList: state.validatorSystem.validatorList.account,
+        })
+        .rpc();
+    }
+
+    // 5. 关闭：rent_collector 只收回租金，国库份额、剩余 SOL 与运营账户余额转给 treasury_destination
+    state = await fetchState();
+    const treasuryValue = (await tokenBalance(treasuryMsolPda))
+      .mul(totalStakedLamports(state).add(VIRTUAL_VALUE))
+      .div(state.msolSupply.add(VIRTUAL_SHARES));
+    const rentCollector = newUser().publicKey;
+    const treasuryDestination = newUser().publicKey;
+    const balance = (address: PublicKey) => provider.connection.getBalance(address);
+    const closedAccounts = [
+      stakePoolConfigPda,
+      state.stakeSystem.stakeList.account,
+      state.validatorSystem.validatorList.account,
+      msolLegtPda,
+      treasuryMsolPda,
+    ];
+    let closedRent = 0;
+    for (const address of closedAccounts) closedRent += await balance(address);
+    const pinnedRent = 2 * state.rentExemptForTokenAcc.toNumber();
+    const swept = (await balance(reservePda)) + (await balance(solLegtPda)) - pinnedRent;
+    const operational = await balance(operationalSolAccount);
+    assert.isAbove(operational, 0);
+
+    // 池子自己的 PDA 在关闭后无法签名，不能作为接收方
+    await expectError(() => closePool(rentCollector, operationalSolAccount), "RequireKeysNeqViolated");
+
+    await closePool(rentCollector, treasuryDestination);
+
+    assert.equal(await balance(rentCollector), closedRent + pinnedRent);
+    assert.equal(await balance(treasuryDestination), swept + operational);
+    assert.isAtLeast(swept, treasuryValue.toNumber());
+    assert.equal(await balance(reservePda), 0);
+    assert.equal(await balance(solLegtPda), 0);
+    assert.equal(await balance(operationalSolAccount), 0);
+    assert.isNull(await provider.connection.getAccountInfo(stakePoolConfigPda));
+  });
 
 });